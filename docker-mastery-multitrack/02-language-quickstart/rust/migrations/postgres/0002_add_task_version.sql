@@ -0,0 +1,2 @@
+-- Write counter behind the task ETag; every update increments it
+ALTER TABLE tasks ADD COLUMN IF NOT EXISTS version BIGINT NOT NULL DEFAULT 1;
@@ -0,0 +1,3 @@
+-- When a task was moved to the trash; NULL for live tasks
+ALTER TABLE tasks ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMPTZ;
+CREATE INDEX IF NOT EXISTS idx_tasks_deleted_at ON tasks (deleted_at);
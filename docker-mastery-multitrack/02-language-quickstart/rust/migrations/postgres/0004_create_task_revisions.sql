@@ -0,0 +1,12 @@
+-- Immutable revision history of every task write (see src/history.rs). There
+-- is no foreign key to tasks: the history outlives a purged task.
+CREATE TABLE IF NOT EXISTS task_revisions (
+    task_id UUID NOT NULL,
+    revision BIGINT NOT NULL,
+    action VARCHAR(16) NOT NULL,
+    actor VARCHAR(255) NOT NULL,
+    changed_at TIMESTAMPTZ NOT NULL,
+    -- The task as the revision left it, serialized like the API returns it
+    task JSONB NOT NULL,
+    PRIMARY KEY (task_id, revision)
+);
@@ -0,0 +1,3 @@
+-- Optional due date; tasks past it and not completed are overdue
+ALTER TABLE tasks ADD COLUMN IF NOT EXISTS due_at TIMESTAMPTZ;
+CREATE INDEX IF NOT EXISTS idx_tasks_due_at ON tasks (due_at);
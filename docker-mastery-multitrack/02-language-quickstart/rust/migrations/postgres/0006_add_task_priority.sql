@@ -0,0 +1,4 @@
+-- Priority as its rank: 0 low, 1 normal, 2 high, 3 urgent
+ALTER TABLE tasks ADD COLUMN IF NOT EXISTS priority SMALLINT NOT NULL DEFAULT 1
+    CHECK (priority BETWEEN 0 AND 3);
+CREATE INDEX IF NOT EXISTS idx_tasks_priority ON tasks (priority);
@@ -0,0 +1,9 @@
+-- Tags of each task (see src/tags.rs); a tag exists while a task has it.
+-- There is no foreign key to tasks: a purge reads a task's tags as it deletes
+-- it and clears them afterwards.
+CREATE TABLE IF NOT EXISTS task_tags (
+    task_id UUID NOT NULL,
+    tag VARCHAR(50) NOT NULL,
+    PRIMARY KEY (task_id, tag)
+);
+CREATE INDEX IF NOT EXISTS idx_task_tags_tag ON task_tags (tag);
@@ -0,0 +1,3 @@
+-- Purging a task now drops its history with it (see src/history.rs). Drop
+-- what earlier purges left behind.
+DELETE FROM task_revisions WHERE task_id NOT IN (SELECT id FROM tasks);
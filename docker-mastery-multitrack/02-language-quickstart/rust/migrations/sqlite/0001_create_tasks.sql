@@ -0,0 +1,18 @@
+-- SQLite counterpart of migrations/postgres/0001_create_tasks.sql
+-- Timestamps are stored as RFC 3339 text so they sort chronologically.
+CREATE TABLE IF NOT EXISTS tasks (
+    id TEXT PRIMARY KEY NOT NULL,
+    title VARCHAR(255) NOT NULL,
+    description TEXT NOT NULL DEFAULT '',
+    completed BOOLEAN NOT NULL DEFAULT FALSE,
+    created_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')),
+    updated_at TEXT NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now'))
+);
+
+-- Keep updated_at current for writes that do not set it explicitly
+CREATE TRIGGER IF NOT EXISTS update_tasks_updated_at AFTER UPDATE
+ON tasks FOR EACH ROW WHEN NEW.updated_at = OLD.updated_at
+BEGIN
+    UPDATE tasks SET updated_at = strftime('%Y-%m-%dT%H:%M:%f+00:00', 'now')
+    WHERE id = NEW.id;
+END;
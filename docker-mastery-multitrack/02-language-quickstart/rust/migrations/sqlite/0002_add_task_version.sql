@@ -0,0 +1,2 @@
+-- SQLite counterpart of migrations/postgres/0002_add_task_version.sql
+ALTER TABLE tasks ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
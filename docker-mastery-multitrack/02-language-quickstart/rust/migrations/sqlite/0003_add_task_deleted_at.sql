@@ -0,0 +1,3 @@
+-- SQLite counterpart of migrations/postgres/0003_add_task_deleted_at.sql
+ALTER TABLE tasks ADD COLUMN deleted_at TEXT;
+CREATE INDEX IF NOT EXISTS idx_tasks_deleted_at ON tasks (deleted_at);
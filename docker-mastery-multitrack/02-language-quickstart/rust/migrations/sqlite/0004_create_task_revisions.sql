@@ -0,0 +1,10 @@
+-- SQLite counterpart of migrations/postgres/0004_create_task_revisions.sql
+CREATE TABLE IF NOT EXISTS task_revisions (
+    task_id TEXT NOT NULL,
+    revision INTEGER NOT NULL,
+    action VARCHAR(16) NOT NULL,
+    actor VARCHAR(255) NOT NULL,
+    changed_at TEXT NOT NULL,
+    task TEXT NOT NULL,
+    PRIMARY KEY (task_id, revision)
+);
@@ -0,0 +1,3 @@
+-- SQLite counterpart of migrations/postgres/0005_add_task_due_at.sql
+ALTER TABLE tasks ADD COLUMN due_at TEXT;
+CREATE INDEX IF NOT EXISTS idx_tasks_due_at ON tasks (due_at);
@@ -0,0 +1,4 @@
+-- SQLite counterpart of migrations/postgres/0006_add_task_priority.sql
+ALTER TABLE tasks ADD COLUMN priority INTEGER NOT NULL DEFAULT 1
+    CHECK (priority BETWEEN 0 AND 3);
+CREATE INDEX IF NOT EXISTS idx_tasks_priority ON tasks (priority);
@@ -0,0 +1,7 @@
+-- SQLite counterpart of migrations/postgres/0007_create_task_tags.sql
+CREATE TABLE IF NOT EXISTS task_tags (
+    task_id TEXT NOT NULL,
+    tag VARCHAR(50) NOT NULL,
+    PRIMARY KEY (task_id, tag)
+);
+CREATE INDEX IF NOT EXISTS idx_task_tags_tag ON task_tags (tag);
@@ -0,0 +1,2 @@
+-- SQLite counterpart of migrations/postgres/0008_drop_purged_task_history.sql
+DELETE FROM task_revisions WHERE task_id NOT IN (SELECT id FROM tasks);
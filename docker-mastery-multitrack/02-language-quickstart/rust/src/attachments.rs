@@ -0,0 +1,561 @@
+/*!
+ * File attachments on tasks
+ *
+ * Attachments live in the uploads directory (UPLOAD_DIR, by default `uploads`
+ * under the working directory, i.e. the `/app/uploads` volume), one
+ * directory per task. Each file is stored under its attachment id next to a
+ * JSON file with its metadata, so the client's file name never becomes a
+ * path. Uploads are limited in size (MAX_UPLOAD_BYTES) and to the media types
+ * in UPLOAD_ALLOWED_TYPES. The endpoints are off unless ENABLE_FILE_UPLOAD=true.
+ */
+
+use actix_files::NamedFile;
+use actix_multipart::{Field, Multipart};
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::{http::StatusCode, mime, HttpResponse, ResponseError};
+use chrono::{DateTime, Utc};
+use futures_util::TryStreamExt;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::models::timestamp;
+
+const DEFAULT_MAX_UPLOAD_BYTES: u64 = 10 * 1024 * 1024;
+const DEFAULT_ALLOWED_TYPES: &str = "image/png,image/jpeg,image/gif,image/webp,application/pdf,\
+    text/plain,text/csv,text/markdown,application/json,application/zip";
+// Files accepted in one upload request
+const MAX_FILES_PER_UPLOAD: usize = 20;
+const MAX_FILENAME_LENGTH: usize = 255;
+
+// Upload settings read from the environment
+#[derive(Debug, Clone)]
+pub struct UploadConfig {
+    pub enabled: bool,
+    pub dir: PathBuf,
+    pub max_bytes: u64,
+    // Media types like `image/png`, or `image/*` for a whole family
+    pub allowed_types: Vec<String>,
+}
+
+impl UploadConfig {
+    pub fn from_env() -> Self {
+        let allowed_types = std::env::var("UPLOAD_ALLOWED_TYPES")
+            .ok()
+            .filter(|types| !types.trim().is_empty())
+            .unwrap_or_else(|| DEFAULT_ALLOWED_TYPES.to_string());
+
+        UploadConfig {
+            enabled: std::env::var("ENABLE_FILE_UPLOAD")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(false),
+            dir: std::env::var("UPLOAD_DIR")
+                .ok()
+                .filter(|dir| !dir.is_empty())
+                .map_or_else(|| PathBuf::from("uploads"), PathBuf::from),
+            max_bytes: std::env::var("MAX_UPLOAD_BYTES")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(DEFAULT_MAX_UPLOAD_BYTES),
+            allowed_types: allowed_types
+                .split(',')
+                .map(|media_type| media_type.trim().to_ascii_lowercase())
+                .filter(|media_type| !media_type.is_empty())
+                .collect(),
+        }
+    }
+
+    fn allows(&self, media_type: &str) -> bool {
+        self.allowed_types.iter().any(|allowed| match allowed.strip_suffix("/*") {
+            Some(family) => media_type.split('/').next() == Some(family),
+            None => allowed == media_type,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum AttachmentError {
+    Disabled,
+    NotFound(String),
+    TooLarge(u64),
+    UnsupportedType(String),
+    // The request had no file parts, too many, or was not valid multipart
+    BadUpload(String),
+    Io(io::Error),
+}
+
+impl fmt::Display for AttachmentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AttachmentError::Disabled => {
+                write!(f, "file upload is disabled (set ENABLE_FILE_UPLOAD=true)")
+            }
+            AttachmentError::NotFound(id) => write!(f, "Attachment with id {} not found", id),
+            AttachmentError::TooLarge(max) => {
+                write!(f, "attachments must be at most {} bytes", max)
+            }
+            AttachmentError::UnsupportedType(media_type) => {
+                write!(f, "attachments of type {} are not allowed", media_type)
+            }
+            AttachmentError::BadUpload(msg) => write!(f, "invalid upload: {}", msg),
+            AttachmentError::Io(err) => write!(f, "attachment storage failed: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for AttachmentError {}
+
+impl From<io::Error> for AttachmentError {
+    fn from(err: io::Error) -> Self {
+        AttachmentError::Io(err)
+    }
+}
+
+impl ResponseError for AttachmentError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AttachmentError::Disabled | AttachmentError::NotFound(_) => StatusCode::NOT_FOUND,
+            AttachmentError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            AttachmentError::UnsupportedType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            AttachmentError::BadUpload(_) => StatusCode::BAD_REQUEST,
+            AttachmentError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(serde_json::json!({
+            "error": self.to_string()
+        }))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Attachment {
+    pub id: String,
+    pub task_id: String,
+    pub filename: String,
+    pub content_type: String,
+    pub size: u64,
+    pub sha256: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Attachment {
+    // Content-Disposition offering the file under its original name
+    pub fn content_disposition(&self) -> ContentDisposition {
+        ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename(self.filename.clone())],
+        }
+    }
+}
+
+pub struct AttachmentStore {
+    config: UploadConfig,
+}
+
+impl AttachmentStore {
+    pub fn new(config: UploadConfig) -> Self {
+        AttachmentStore { config }
+    }
+
+    pub fn from_env() -> Self {
+        Self::new(UploadConfig::from_env())
+    }
+
+    pub fn check_enabled(&self) -> Result<(), AttachmentError> {
+        if self.config.enabled {
+            Ok(())
+        } else {
+            Err(AttachmentError::Disabled)
+        }
+    }
+
+    // Task and attachment ids are UUIDs; anything else never names a file
+    fn task_dir(&self, task_id: &str) -> Option<PathBuf> {
+        let task_id = Uuid::parse_str(task_id).ok()?;
+        Some(self.config.dir.join(task_id.to_string()))
+    }
+
+    fn paths(&self, task_id: &str, attachment_id: &str) -> Option<(PathBuf, PathBuf)> {
+        let attachment_id = Uuid::parse_str(attachment_id).ok()?;
+        let dir = self.task_dir(task_id)?;
+        Some((
+            dir.join(attachment_id.to_string()),
+            dir.join(format!("{}.json", attachment_id)),
+        ))
+    }
+
+    pub async fn list(&self, task_id: &str) -> Result<Vec<Attachment>, AttachmentError> {
+        let Some(dir) = self.task_dir(task_id) else {
+            return Ok(Vec::new());
+        };
+        let mut entries = match tokio::fs::read_dir(&dir).await {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut attachments = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            if entry.path().extension().is_some_and(|extension| extension == "json") {
+                attachments.push(read_metadata(&entry.path()).await?);
+            }
+        }
+        attachments.sort_by(|a, b| (a.created_at, &a.id).cmp(&(b.created_at, &b.id)));
+        Ok(attachments)
+    }
+
+    // An attachment's content, ready to be served with its media type and
+    // original file name
+    pub async fn open(&self, task_id: &str, attachment_id: &str) -> Result<NamedFile, AttachmentError> {
+        let not_found = || AttachmentError::NotFound(attachment_id.to_string());
+        let (file, metadata) = self.paths(task_id, attachment_id).ok_or_else(not_found)?;
+
+        let opened = match read_metadata(&metadata).await {
+            Ok(attachment) => NamedFile::open_async(&file).await.map(|file| (attachment, file)),
+            Err(AttachmentError::Io(err)) => Err(err),
+            Err(err) => return Err(err),
+        };
+        let (attachment, file) = match opened {
+            Ok(opened) => opened,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Err(not_found()),
+            Err(err) => return Err(err.into()),
+        };
+
+        let content_type = attachment
+            .content_type
+            .parse()
+            .unwrap_or(mime::APPLICATION_OCTET_STREAM);
+        Ok(file
+            .set_content_type(content_type)
+            .set_content_disposition(attachment.content_disposition()))
+    }
+
+    pub async fn delete(&self, task_id: &str, attachment_id: &str) -> Result<(), AttachmentError> {
+        let (file, metadata) = self
+            .paths(task_id, attachment_id)
+            .ok_or_else(|| AttachmentError::NotFound(attachment_id.to_string()))?;
+        // Metadata first, so the attachment disappears from listings at once
+        match tokio::fs::remove_file(&metadata).await {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(AttachmentError::NotFound(attachment_id.to_string()))
+            }
+            Err(err) => return Err(err.into()),
+        }
+        ignore_missing(tokio::fs::remove_file(&file).await)?;
+        Ok(())
+    }
+
+    // Remove every attachment of a task that no longer exists
+    pub async fn remove_all(&self, task_id: &str) -> Result<(), AttachmentError> {
+        match self.task_dir(task_id) {
+            Some(dir) => Ok(ignore_missing(tokio::fs::remove_dir_all(&dir).await)?),
+            None => Ok(()),
+        }
+    }
+
+    // Store every file part of a multipart upload. Either all files are
+    // stored or, if one is rejected, none.
+    pub async fn save_all(
+        &self,
+        task_id: &str,
+        mut payload: Multipart,
+    ) -> Result<Vec<Attachment>, AttachmentError> {
+        let dir = self
+            .task_dir(task_id)
+            .ok_or_else(|| AttachmentError::BadUpload("invalid task id".to_string()))?;
+        tokio::fs::create_dir_all(&dir).await?;
+
+        let mut saved = Vec::new();
+        let result = async {
+            while let Some(field) = payload.try_next().await.map_err(bad_upload)? {
+                // Plain form fields carry no file
+                if field.content_disposition().and_then(|cd| cd.get_filename()).is_none() {
+                    continue;
+                }
+                if saved.len() == MAX_FILES_PER_UPLOAD {
+                    return Err(AttachmentError::BadUpload(format!(
+                        "at most {} files can be uploaded at once",
+                        MAX_FILES_PER_UPLOAD
+                    )));
+                }
+                saved.push(self.save(task_id, &dir, field).await?);
+            }
+            if saved.is_empty() {
+                return Err(AttachmentError::BadUpload("no file parts in the request".to_string()));
+            }
+            Ok(())
+        }
+        .await;
+
+        if let Err(err) = result {
+            for attachment in &saved {
+                let _ = self.delete(task_id, &attachment.id).await;
+            }
+            return Err(err);
+        }
+        Ok(saved)
+    }
+
+    async fn save(
+        &self,
+        task_id: &str,
+        dir: &Path,
+        mut field: Field,
+    ) -> Result<Attachment, AttachmentError> {
+        let filename = field
+            .content_disposition()
+            .and_then(|cd| cd.get_filename())
+            .map(sanitize_filename)
+            .unwrap_or_default();
+        let content_type = media_type(field.content_type(), &filename);
+        if !self.config.allows(&content_type) {
+            return Err(AttachmentError::UnsupportedType(content_type));
+        }
+
+        let id = Uuid::new_v4().to_string();
+        let tmp_path = dir.join(format!(".{}.tmp", id));
+        let written = async {
+            let mut out = tokio::fs::File::create(&tmp_path).await?;
+            let mut hasher = Sha256::new();
+            let mut size = 0;
+            while let Some(chunk) = field.try_next().await.map_err(bad_upload)? {
+                size += chunk.len() as u64;
+                if size > self.config.max_bytes {
+                    return Err(AttachmentError::TooLarge(self.config.max_bytes));
+                }
+                hasher.update(&chunk);
+                out.write_all(&chunk).await?;
+            }
+            out.sync_all().await?;
+            Ok((size, hasher.finalize()))
+        }
+        .await;
+
+        let (size, digest) = match written {
+            Ok(written) => written,
+            Err(err) => {
+                let _ = tokio::fs::remove_file(&tmp_path).await;
+                return Err(err);
+            }
+        };
+        tokio::fs::rename(&tmp_path, dir.join(&id)).await?;
+
+        let attachment = Attachment {
+            id,
+            task_id: task_id.to_string(),
+            filename,
+            content_type,
+            size,
+            sha256: digest.iter().map(|byte| format!("{:02x}", byte)).collect(),
+            created_at: timestamp(),
+        };
+        write_metadata(dir, &attachment).await?;
+        Ok(attachment)
+    }
+}
+
+fn bad_upload(err: actix_multipart::MultipartError) -> AttachmentError {
+    AttachmentError::BadUpload(err.to_string())
+}
+
+fn ignore_missing(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+// The declared media type, or a guess from the file name when the client
+// sent none or a generic one
+fn media_type(declared: Option<&mime::Mime>, filename: &str) -> String {
+    let declared = declared.map(|mime| mime.essence_str().to_ascii_lowercase());
+    match declared.as_deref() {
+        None | Some("application/octet-stream") => mime_guess::from_path(filename)
+            .first_raw()
+            .unwrap_or("application/octet-stream")
+            .to_string(),
+        Some(media_type) => media_type.to_string(),
+    }
+}
+
+// Just the last path component, without control characters
+fn sanitize_filename(name: &str) -> String {
+    let name = name.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name: String = name
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_FILENAME_LENGTH)
+        .collect();
+    match name.trim() {
+        "" | "." | ".." => "attachment".to_string(),
+        name => name.to_string(),
+    }
+}
+
+async fn read_metadata(path: &Path) -> Result<Attachment, AttachmentError> {
+    let json = tokio::fs::read(path).await?;
+    serde_json::from_slice(&json)
+        .map_err(|err| AttachmentError::Io(io::Error::new(io::ErrorKind::InvalidData, err)))
+}
+
+// Written last and renamed into place, so listings only see complete uploads
+async fn write_metadata(dir: &Path, attachment: &Attachment) -> Result<(), AttachmentError> {
+    let tmp_path = dir.join(format!(".{}.json.tmp", attachment.id));
+    let json = serde_json::to_vec(attachment).expect("attachments serialize to JSON");
+    tokio::fs::write(&tmp_path, json).await?;
+    tokio::fs::rename(&tmp_path, dir.join(format!("{}.json", attachment.id))).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
+    use actix_web::web::Bytes;
+
+    const BOUNDARY: &str = "task-api-test-boundary";
+
+    fn store(max_bytes: u64) -> AttachmentStore {
+        AttachmentStore::new(UploadConfig {
+            enabled: true,
+            dir: std::env::temp_dir().join(format!("task-api-uploads-{}", Uuid::new_v4())),
+            max_bytes,
+            allowed_types: vec!["image/*".to_string(), "text/plain".to_string()],
+        })
+    }
+
+    // A multipart/form-data body with one part per (file name, media type,
+    // content); parts without a file name are plain form fields
+    fn upload(parts: &[(Option<&str>, Option<&str>, &[u8])]) -> Multipart {
+        let mut body = Vec::new();
+        for (filename, media_type, content) in parts {
+            body.extend_from_slice(format!("--{}\r\n", BOUNDARY).as_bytes());
+            let disposition = match filename {
+                Some(name) => format!("form-data; name=\"file\"; filename=\"{}\"", name),
+                None => "form-data; name=\"note\"".to_string(),
+            };
+            body.extend_from_slice(format!("Content-Disposition: {}\r\n", disposition).as_bytes());
+            if let Some(media_type) = media_type {
+                body.extend_from_slice(format!("Content-Type: {}\r\n", media_type).as_bytes());
+            }
+            body.extend_from_slice(b"\r\n");
+            body.extend_from_slice(content);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", BOUNDARY).as_bytes());
+
+        let mut headers = HeaderMap::new();
+        let content_type = format!("multipart/form-data; boundary={}", BOUNDARY);
+        headers.insert(CONTENT_TYPE, HeaderValue::from_str(&content_type).unwrap());
+        let stream = futures_util::stream::once(async move { Ok(Bytes::from(body)) });
+        Multipart::new(&headers, stream)
+    }
+
+    // Everything left in the task's directory, finished or not
+    fn files(store: &AttachmentStore, task_id: &str) -> Vec<String> {
+        let Ok(entries) = std::fs::read_dir(store.task_dir(task_id).unwrap()) else {
+            return Vec::new();
+        };
+        entries.map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect()
+    }
+
+    #[test]
+    fn allows_listed_types_and_whole_families() {
+        let config = store(1).config;
+        assert!(config.allows("image/png"));
+        assert!(config.allows("image/svg+xml"));
+        assert!(config.allows("text/plain"));
+        assert!(!config.allows("text/html"));
+        assert!(!config.allows("imagery/png"));
+        assert!(!config.allows("application/octet-stream"));
+    }
+
+    #[test]
+    fn guesses_generic_media_types_from_the_file_name() {
+        let octet_stream = mime::APPLICATION_OCTET_STREAM;
+        assert_eq!(media_type(None, "photo.PNG"), "image/png");
+        assert_eq!(media_type(Some(&octet_stream), "notes.txt"), "text/plain");
+        assert_eq!(media_type(Some(&mime::IMAGE_GIF), "notes.txt"), "image/gif");
+        assert_eq!(media_type(None, "no-extension"), "application/octet-stream");
+
+        assert_eq!(sanitize_filename("../../etc/passwd"), "passwd");
+        assert_eq!(sanitize_filename("C:\\Users\\me\\report.txt"), "report.txt");
+        assert_eq!(sanitize_filename("bad\u{0}\nname.txt"), "badname.txt");
+        assert_eq!(sanitize_filename(".."), "attachment");
+        assert_eq!(sanitize_filename(&"a".repeat(300)).len(), MAX_FILENAME_LENGTH);
+    }
+
+    #[actix_web::test]
+    async fn stores_files_up_to_the_size_limit() {
+        let store = store(5);
+        let task_id = Uuid::new_v4().to_string();
+        let payload = upload(&[
+            (Some("notes.txt"), None, b"12345"),
+            (None, None, b"a plain form field"),
+            (Some("dir/pixel.png"), Some("image/png"), b"png"),
+        ]);
+
+        let saved = store.save_all(&task_id, payload).await.unwrap();
+        let names: Vec<_> = saved.iter().map(|a| (a.filename.as_str(), a.size)).collect();
+        assert_eq!(names, [("notes.txt", 5), ("pixel.png", 3)]);
+        assert_eq!(saved[0].content_type, "text/plain");
+        assert_eq!(saved[0].sha256, format!("{:x}", Sha256::digest(b"12345")));
+
+        let listed = store.list(&task_id).await.unwrap();
+        let mut listed: Vec<_> = listed.into_iter().map(|a| a.id).collect();
+        let mut ids: Vec<_> = saved.iter().map(|a| a.id.clone()).collect();
+        listed.sort();
+        ids.sort();
+        assert_eq!(listed, ids);
+        assert_eq!(files(&store, &task_id).len(), 4);
+        std::fs::remove_dir_all(&store.config.dir).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn rejects_whole_uploads_over_the_limits() {
+        let store = store(5);
+        let task_id = Uuid::new_v4().to_string();
+
+        // The first file was fine, but nothing of the upload is kept
+        let payload = upload(&[
+            (Some("small.txt"), None, b"small"),
+            (Some("large.txt"), None, b"123456"),
+        ]);
+        let err = store.save_all(&task_id, payload).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert_eq!(err.to_string(), "attachments must be at most 5 bytes");
+        assert_eq!(files(&store, &task_id), Vec::<String>::new());
+
+        let payload = upload(&[
+            (Some("small.txt"), None, b"small"),
+            (Some("page.html"), Some("text/html; charset=utf-8"), b"<p>"),
+        ]);
+        let err = store.save_all(&task_id, payload).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(err.to_string(), "attachments of type text/html are not allowed");
+        assert_eq!(files(&store, &task_id), Vec::<String>::new());
+
+        let payload = upload(&[(None, None, b"no file here")]);
+        let err = store.save_all(&task_id, payload).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+
+        let too_many: Vec<_> = (0..=MAX_FILES_PER_UPLOAD)
+            .map(|_| (Some("a.txt"), None, b"a".as_slice()))
+            .collect();
+        let err = store.save_all(&task_id, upload(&too_many)).await.unwrap_err();
+        assert_eq!(err.to_string(), "invalid upload: at most 20 files can be uploaded at once");
+        assert_eq!(files(&store, &task_id), Vec::<String>::new());
+
+        let err = store.save_all("not-a-uuid", upload(&[])).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+        std::fs::remove_dir_all(&store.config.dir).unwrap();
+    }
+}
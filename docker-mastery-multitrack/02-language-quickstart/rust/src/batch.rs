@@ -0,0 +1,433 @@
+/*!
+ * Bulk create, update and delete for POST /api/tasks/batch
+ *
+ * By default every operation is applied on its own and the response reports
+ * a status per operation. With `"atomic": true` the whole batch runs under
+ * one storage transaction: if any operation cannot be applied, none are, and
+ * the batch is answered with 409.
+ *
+ * Updates change only the fields they name. Updates and deletes may carry
+ * the `version` they expect the task to be at, like If-Match.
+ */
+
+use actix_web::{http::StatusCode, ResponseError};
+use serde::{Deserialize, Serialize};
+
+use crate::models::{Task, TaskCreate, TaskUpdate};
+use crate::precondition::PreconditionError;
+use crate::storage::{Conditional, StorageResult, TaskRepository};
+
+pub const MAX_BATCH_OPERATIONS: usize = 1000;
+
+// Request body for POST /api/tasks/batch
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BatchRequest {
+    #[serde(default)]
+    pub atomic: bool,
+    pub operations: Vec<BatchItem>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case", deny_unknown_fields)]
+pub enum BatchItem {
+    Create {
+        task: TaskCreate,
+    },
+    Update {
+        id: String,
+        task: TaskUpdate,
+        version: Option<i64>,
+    },
+    Delete {
+        id: String,
+        version: Option<i64>,
+    },
+}
+
+// A batch operation as handed to storage
+#[derive(Debug)]
+pub enum BatchOperation {
+    Create(Task),
+    Update {
+        id: String,
+        changes: TaskUpdate,
+        version: Option<i64>,
+    },
+    Delete {
+        id: String,
+        version: Option<i64>,
+    },
+}
+
+impl From<BatchItem> for BatchOperation {
+    fn from(item: BatchItem) -> Self {
+        match item {
+            BatchItem::Create { task } => BatchOperation::Create(Task::new(task)),
+            BatchItem::Update { id, task, version } => BatchOperation::Update {
+                id,
+                changes: task,
+                version,
+            },
+            BatchItem::Delete { id, version } => BatchOperation::Delete { id, version },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum OperationKind {
+    Create,
+    Update,
+    Delete,
+}
+
+impl OperationKind {
+    // Status of an applied operation, as the single-task endpoints answer it
+    fn success(self) -> StatusCode {
+        match self {
+            OperationKind::Create => StatusCode::CREATED,
+            OperationKind::Update => StatusCode::OK,
+            OperationKind::Delete => StatusCode::NO_CONTENT,
+        }
+    }
+}
+
+impl BatchOperation {
+    // What a result needs to know about the operation once storage has
+    // consumed it
+    fn target(&self) -> (OperationKind, String) {
+        match self {
+            BatchOperation::Create(task) => (OperationKind::Create, task.id.clone()),
+            BatchOperation::Update { id, .. } => (OperationKind::Update, id.clone()),
+            BatchOperation::Delete { id, .. } => (OperationKind::Delete, id.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ItemResult {
+    pub index: usize,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub task: Option<Task>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl ItemResult {
+    fn new(index: usize, (kind, id): &(OperationKind, String), outcome: Conditional) -> Self {
+        match outcome {
+            Conditional::Applied(task) => ItemResult {
+                index,
+                status: kind.success().as_u16(),
+                // Like DELETE, a delete answers without a body
+                task: match kind {
+                    OperationKind::Delete => None,
+                    _ => Some(task),
+                },
+                error: None,
+            },
+            Conditional::NotFound => Self::failed(
+                index,
+                StatusCode::NOT_FOUND,
+                format!("Task with id {} not found", id),
+            ),
+            Conditional::Conflict => {
+                let err = PreconditionError::Failed;
+                Self::failed(index, err.status_code(), err.to_string())
+            }
+        }
+    }
+
+    fn failed(index: usize, status: StatusCode, error: String) -> Self {
+        ItemResult {
+            index,
+            status: status.as_u16(),
+            task: None,
+            error: Some(error),
+        }
+    }
+
+    pub fn succeeded(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchResponse {
+    pub atomic: bool,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub results: Vec<ItemResult>,
+}
+
+impl BatchResponse {
+    fn new(atomic: bool, results: Vec<ItemResult>) -> Self {
+        let succeeded = results.iter().filter(|result| result.succeeded()).count();
+        BatchResponse {
+            atomic,
+            succeeded,
+            failed: results.len() - succeeded,
+            results,
+        }
+    }
+}
+
+// Apply each operation independently. A storage failure only fails the
+// operation it happened in.
+pub async fn apply_each(
+    storage: &dyn TaskRepository,
+    operations: Vec<BatchOperation>,
+) -> BatchResponse {
+    let mut results = Vec::with_capacity(operations.len());
+
+    for (index, operation) in operations.into_iter().enumerate() {
+        let target = operation.target();
+        let outcome = match operation {
+            BatchOperation::Create(task) => storage.create(task).await.map(Conditional::Applied),
+            BatchOperation::Update { id, changes, version } => match version {
+                Some(version) => storage.update_if_version(&id, changes, version).await,
+                None => storage.update(&id, changes).await.map(applied_or_not_found),
+            },
+            BatchOperation::Delete { id, version } => match version {
+                Some(version) => storage.delete_if_version(&id, version).await,
+                None => storage.delete(&id).await.map(applied_or_not_found),
+            },
+        };
+
+        results.push(match outcome {
+            Ok(outcome) => ItemResult::new(index, &target, outcome),
+            Err(err) => ItemResult::failed(index, err.status_code(), err.to_string()),
+        });
+    }
+
+    BatchResponse::new(false, results)
+}
+
+// Apply all operations or none. Returns whether the batch was committed.
+pub async fn apply_atomically(
+    storage: &dyn TaskRepository,
+    operations: Vec<BatchOperation>,
+) -> StorageResult<(bool, BatchResponse)> {
+    let targets: Vec<_> = operations.iter().map(BatchOperation::target).collect();
+
+    let mut outcomes = storage.apply_atomically(operations).await?;
+    let committed = outcomes.len() == targets.len()
+        && outcomes.iter().all(|outcome| matches!(outcome, Conditional::Applied(_)));
+    if committed {
+        let results = outcomes
+            .into_iter()
+            .zip(&targets)
+            .enumerate()
+            .map(|(index, (outcome, target))| ItemResult::new(index, target, outcome))
+            .collect();
+        return Ok((true, BatchResponse::new(true, results)));
+    }
+
+    // Report why the failed operation failed, and every other one as not applied
+    let failed_at = outcomes.len() - 1;
+    let mut failure = outcomes.pop();
+    let mut results = Vec::with_capacity(targets.len());
+    for (index, target) in targets.iter().enumerate() {
+        results.push(match failure.take_if(|_| index == failed_at) {
+            Some(outcome) => ItemResult::new(index, target, outcome),
+            None => ItemResult::failed(
+                index,
+                StatusCode::FAILED_DEPENDENCY,
+                format!("not applied because operation {} failed", failed_at),
+            ),
+        });
+    }
+    Ok((false, BatchResponse::new(true, results)))
+}
+
+fn applied_or_not_found(task: Option<Task>) -> Conditional {
+    task.map_or(Conditional::NotFound, Conditional::Applied)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App};
+    use std::sync::Arc;
+
+    use crate::handlers::{batch_tasks, TaskStorage};
+    use crate::storage::InMemoryTaskRepository;
+
+    fn task(title: &str) -> Task {
+        Task::new(TaskCreate {
+            title: title.to_string(),
+            description: String::new(),
+            priority: Default::default(),
+            tags: Vec::new(),
+            due_at: None,
+        })
+    }
+
+    fn create(title: &str) -> BatchOperation {
+        BatchOperation::Create(task(title))
+    }
+
+    fn complete(id: &str, version: Option<i64>) -> BatchOperation {
+        BatchOperation::Update {
+            id: id.to_string(),
+            changes: TaskUpdate {
+                completed: Some(true),
+                ..Default::default()
+            },
+            version,
+        }
+    }
+
+    fn delete(id: &str, version: Option<i64>) -> BatchOperation {
+        BatchOperation::Delete {
+            id: id.to_string(),
+            version,
+        }
+    }
+
+    fn statuses(response: &BatchResponse) -> Vec<u16> {
+        response.results.iter().map(|result| result.status).collect()
+    }
+
+    async fn existing(storage: &InMemoryTaskRepository, title: &str) -> Task {
+        storage.create(task(title)).await.unwrap()
+    }
+
+    #[actix_web::test]
+    async fn applies_each_operation_on_its_own() {
+        let storage = InMemoryTaskRepository::new();
+        let first = existing(&storage, "First").await;
+        let second = existing(&storage, "Second").await;
+        let missing = uuid::Uuid::new_v4().to_string();
+
+        let response = apply_each(
+            &storage,
+            vec![
+                create("New"),
+                complete(&first.id, Some(1)),
+                delete(&second.id, None),
+                complete(&missing, None),
+                complete(&first.id, Some(1)),
+                delete(&missing, Some(1)),
+            ],
+        )
+        .await;
+
+        assert_eq!(statuses(&response), [201, 200, 204, 404, 412, 404]);
+        assert_eq!((response.atomic, response.succeeded, response.failed), (false, 3, 3));
+        assert_eq!(response.results[0].task.as_ref().unwrap().title, "New");
+        assert!(response.results[1].task.as_ref().unwrap().completed);
+        assert!(response.results[2].task.is_none() && response.results[2].succeeded());
+        let error = response.results[3].error.as_deref().unwrap();
+        assert_eq!(error, format!("Task with id {} not found", missing));
+        let error = response.results[4].error.as_deref().unwrap();
+        assert_eq!(error, "task has been modified since it was read");
+        let indexes: Vec<_> = response.results.iter().map(|result| result.index).collect();
+        assert_eq!(indexes, [0, 1, 2, 3, 4, 5]);
+
+        // The failures did not undo the operations that succeeded
+        assert_eq!(storage.list().await.unwrap().len(), 2);
+        assert!(storage.get(&first.id).await.unwrap().unwrap().completed);
+    }
+
+    #[actix_web::test]
+    async fn reports_the_failed_operation_and_every_other_as_not_applied() {
+        let storage = InMemoryTaskRepository::new();
+        let task = existing(&storage, "Existing").await;
+
+        let (committed, response) = apply_atomically(
+            &storage,
+            vec![
+                create("New"),
+                complete(&task.id, None),
+                complete(&task.id, Some(1)),
+                delete(&task.id, None),
+            ],
+        )
+        .await
+        .unwrap();
+
+        assert!(!committed);
+        assert_eq!(statuses(&response), [424, 424, 412, 424]);
+        assert_eq!((response.atomic, response.succeeded, response.failed), (true, 0, 4));
+        for index in [0, 1, 3] {
+            let error = response.results[index].error.as_deref().unwrap();
+            assert_eq!(error, "not applied because operation 2 failed");
+        }
+        let error = response.results[2].error.as_deref().unwrap();
+        assert_eq!(error, "task has been modified since it was read");
+
+        let tasks = storage.list().await.unwrap();
+        assert_eq!(tasks.len(), 1);
+        assert!(!tasks[0].completed);
+    }
+
+    #[actix_web::test]
+    async fn commits_an_atomic_batch_that_applies_in_full() {
+        let storage = InMemoryTaskRepository::new();
+        let task = existing(&storage, "Existing").await;
+
+        let (committed, response) = apply_atomically(
+            &storage,
+            vec![create("New"), complete(&task.id, Some(1)), delete(&task.id, Some(2))],
+        )
+        .await
+        .unwrap();
+
+        assert!(committed);
+        assert_eq!(statuses(&response), [201, 200, 204]);
+        assert_eq!((response.succeeded, response.failed), (3, 0));
+        let tasks = storage.list().await.unwrap();
+        let titles: Vec<_> = tasks.iter().map(|task| task.title.as_str()).collect();
+        assert_eq!(titles, ["New"]);
+    }
+
+    #[actix_web::test]
+    async fn answers_batches_over_http() {
+        let memory = Arc::new(InMemoryTaskRepository::new());
+        let task = existing(&memory, "Existing").await;
+        let storage: TaskStorage = web::Data::from(memory as Arc<dyn TaskRepository>);
+        let app = test::init_service(
+            App::new().app_data(storage.clone()).route("/batch", web::post().to(batch_tasks)),
+        )
+        .await;
+        let batch = |atomic: bool, operations: Vec<serde_json::Value>| {
+            test::TestRequest::post()
+                .uri("/batch")
+                .set_json(serde_json::json!({ "atomic": atomic, "operations": operations }))
+                .to_request()
+        };
+        let new = serde_json::json!({
+            "op": "create",
+            "task": { "title": "New", "description": "" }
+        });
+        let stale = serde_json::json!({ "op": "delete", "id": task.id, "version": 7 });
+
+        let request = batch(true, vec![new.clone(), stale.clone()]);
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body["results"][0]["status"], 424);
+        assert_eq!(body["results"][1]["status"], 412);
+        assert_eq!(storage.list().await.unwrap().len(), 1);
+
+        // Without atomic, the same failure is reported per operation
+        let response = test::call_service(&app, batch(false, vec![new.clone(), stale])).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!((&body["succeeded"], &body["failed"]), (&1.into(), &1.into()));
+        assert_eq!(storage.list().await.unwrap().len(), 2);
+
+        for count in [0, MAX_BATCH_OPERATIONS + 1] {
+            for atomic in [false, true] {
+                let request = batch(atomic, vec![new.clone(); count]);
+                let response = test::call_service(&app, request).await;
+                assert_eq!(response.status(), StatusCode::BAD_REQUEST, "{} operations", count);
+                let body: serde_json::Value = test::read_body_json(response).await;
+                assert_eq!(body["error"], "a batch must have between 1 and 1000 operations");
+            }
+        }
+        assert_eq!(storage.list().await.unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,208 @@
+/*!
+ * Command-line subcommands of the task-api binary
+ *
+ * `task-api import [OPTIONS] <FILE|->` loads tasks into the configured
+ * storage without starting the server, with the same parsing and validation
+ * as POST /api/tasks/import. The report is printed as JSON, and the command
+ * fails if any record is invalid. Their history records the tasks as created
+ * by `cli`. With the in-memory store's write-ahead log, stop the server
+ * first: both would write the same log.
+ */
+
+use log::warn;
+use std::io::{self, Read};
+
+use crate::history;
+use crate::import::{self, ColumnMapping, ImportFormat};
+use crate::storage::{self, StorageConfig};
+
+const USAGE: &str = "usage: task-api import [--dry-run] [--format json|ndjson|csv|todotxt] \
+    [--title-column NAME] [--description-column NAME] [--completed-column NAME] \
+    [--priority-column NAME] [--tags-column NAME] [--due-column NAME] <FILE|->";
+
+// Who the history names for tasks imported from the command line
+const CLI_ACTOR: &str = "cli";
+
+#[derive(Debug, Default)]
+struct ImportArgs {
+    // `-` reads standard input
+    path: String,
+    format: Option<ImportFormat>,
+    dry_run: bool,
+    columns: ColumnMapping,
+}
+
+fn parse_import_args(mut args: impl Iterator<Item = String>) -> Result<ImportArgs, String> {
+    let mut parsed = ImportArgs::default();
+    let mut path = None;
+
+    while let Some(arg) = args.next() {
+        let mut value = |flag: &str| args.next().ok_or_else(|| format!("{} needs a value", flag));
+        match arg.as_str() {
+            "--dry-run" => parsed.dry_run = true,
+            "--format" => parsed.format = Some(value(&arg)?.parse()?),
+            "--title-column" => parsed.columns.title = Some(value(&arg)?),
+            "--description-column" => parsed.columns.description = Some(value(&arg)?),
+            "--completed-column" => parsed.columns.completed = Some(value(&arg)?),
+            "--priority-column" => parsed.columns.priority = Some(value(&arg)?),
+            "--tags-column" => parsed.columns.tags = Some(value(&arg)?),
+            "--due-column" => parsed.columns.due = Some(value(&arg)?),
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+            _ if path.is_some() => return Err("only one input file can be imported".to_string()),
+            _ => path = Some(arg),
+        }
+    }
+
+    parsed.path = path.ok_or("missing input file")?;
+    Ok(parsed)
+}
+
+// Run the subcommand named by the command-line arguments. `None` means there
+// is none and the server should start.
+pub async fn run(args: &[String]) -> Option<io::Result<()>> {
+    let (command, rest) = args.split_first()?;
+    Some(match command.as_str() {
+        "import" => match parse_import_args(rest.iter().cloned()) {
+            Ok(args) => run_import(args).await,
+            Err(msg) => Err(usage_error(&msg)),
+        },
+        other => Err(usage_error(&format!("unknown command {:?}", other))),
+    })
+}
+
+fn usage_error(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, format!("{}\n{}", msg, USAGE))
+}
+
+async fn run_import(args: ImportArgs) -> io::Result<()> {
+    let format = args
+        .format
+        .or_else(|| ImportFormat::from_file_name(&args.path))
+        .ok_or_else(|| usage_error("cannot tell the format from the file name; pass --format"))?;
+
+    let mut input = Vec::new();
+    match args.path.as_str() {
+        "-" => {
+            io::stdin().read_to_end(&mut input)?;
+        }
+        path => input = std::fs::read(path)?,
+    }
+
+    let parsed = import::parse(format, &input, &args.columns).map_err(io::Error::other)?;
+
+    let config = StorageConfig::from_env();
+    if !args.dry_run && config.database_url.is_none() && config.wal.is_none() {
+        warn!("No DATABASE_URL or write-ahead log configured: imported tasks are lost on exit");
+    }
+    let storage = storage::connect(&config).await.map_err(io::Error::other)?;
+
+    let report = history::with_actor(
+        CLI_ACTOR.to_string(),
+        import::import(&*storage, format, parsed, args.dry_run),
+    )
+    .await
+    .map_err(io::Error::other)?;
+    println!("{}", serde_json::to_string_pretty(&report)?);
+
+    if report.succeeded() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} of {} records are invalid", report.errors.len(), report.records),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<ImportArgs, String> {
+        parse_import_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn maps_every_import_column() {
+        let args = parse(&[
+            "--title-column",
+            "Name",
+            "--description-column",
+            "Notes",
+            "--completed-column",
+            "Done",
+            "--priority-column",
+            "Prio",
+            "--tags-column",
+            "Labels",
+            "--due-column",
+            "Deadline",
+            "tasks.csv",
+        ])
+        .unwrap();
+        let columns = args.columns;
+        let mapped = [
+            columns.title,
+            columns.description,
+            columns.completed,
+            columns.priority,
+            columns.tags,
+            columns.due,
+        ];
+        let expected = ["Name", "Notes", "Done", "Prio", "Labels", "Deadline"];
+        assert_eq!(mapped, expected.map(|name| Some(name.to_string())));
+        assert_eq!(args.path, "tasks.csv");
+    }
+
+    #[test]
+    fn parses_flags_and_one_input() {
+        let args = parse(&["--dry-run", "-", "--format", "todotxt"]).unwrap();
+        assert!(args.dry_run);
+        assert_eq!(args.format, Some(ImportFormat::TodoTxt));
+        assert_eq!(args.path, "-");
+        let args = parse(&["tasks.json"]).unwrap();
+        assert!(!args.dry_run);
+        assert_eq!(args.format, None);
+    }
+
+    #[test]
+    fn rejects_malformed_arguments() {
+        let cases: [(&[&str], &str); 6] = [
+            (&["tasks.csv", "--title-column"], "--title-column needs a value"),
+            (&["--due-column"], "--due-column needs a value"),
+            (&["--force", "tasks.csv"], "unknown option --force"),
+            (&["a.csv", "b.csv"], "only one input file can be imported"),
+            (&["--dry-run"], "missing input file"),
+            (&[], "missing input file"),
+        ];
+        for (args, expected) in cases {
+            assert_eq!(parse(args).unwrap_err(), expected, "{:?}", args);
+        }
+        let err = parse(&["--format", "xml", "tasks.xml"]).unwrap_err();
+        assert!(err.contains("xml"), "{}", err);
+    }
+
+    #[actix_web::test]
+    async fn runs_only_known_commands() {
+        let run = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+            async move { run(&args).await }
+        };
+        assert!(run(&[]).await.is_none(), "no command starts the server");
+
+        let err = run(&["export", "tasks.csv"]).await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().starts_with("unknown command \"export\"\nusage: "));
+
+        let err = run(&["import", "a.csv", "b.csv"]).await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().ends_with(USAGE));
+
+        // Both fail before any storage is opened
+        let err = run(&["import", "tasks.txt.bak"]).await.unwrap().unwrap_err();
+        assert!(err.to_string().starts_with("cannot tell the format"), "{}", err);
+        let missing = format!("/nonexistent/{}.csv", uuid::Uuid::new_v4());
+        let err = run(&["import", &missing]).await.unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
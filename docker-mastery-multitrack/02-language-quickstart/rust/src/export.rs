@@ -0,0 +1,558 @@
+/*!
+ * Task export as JSON Lines, CSV or a Markdown checklist
+ *
+ * Exports take the same filter and sort parameters as the task list and walk
+ * it a page at a time, so even a large export never holds every task in
+ * memory. An export is either streamed to the client or written into the
+ * exports directory (EXPORT_DIR, by default `exports` under the working
+ * directory, i.e. the `/app/exports` volume). Both are off unless
+ * ENABLE_TASK_EXPORT=true.
+ */
+
+use actix_web::web::Bytes;
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use chrono::{DateTime, SecondsFormat, Utc};
+use futures_util::stream::{self, Stream};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::models::{Priority, Task};
+use crate::query::{Cursor, ListParams, QueryError, TaskQuery};
+use crate::storage::{StorageError, StorageResult, TaskRepository};
+
+// Tasks fetched from storage per step of an export
+const EXPORT_PAGE_SIZE: usize = 500;
+
+const CSV_COLUMNS: [&str; 10] = [
+    "id",
+    "title",
+    "description",
+    "completed",
+    "priority",
+    "tags",
+    "due_at",
+    "created_at",
+    "updated_at",
+    "version",
+];
+
+// Export settings read from the environment
+#[derive(Debug, Clone)]
+pub struct ExportConfig {
+    pub enabled: bool,
+    pub dir: PathBuf,
+}
+
+impl ExportConfig {
+    pub fn from_env() -> Self {
+        ExportConfig {
+            enabled: std::env::var("ENABLE_TASK_EXPORT")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(false),
+            dir: std::env::var("EXPORT_DIR")
+                .ok()
+                .filter(|dir| !dir.is_empty())
+                .map_or_else(|| PathBuf::from("exports"), PathBuf::from),
+        }
+    }
+
+    pub fn check_enabled(&self) -> Result<(), ExportError> {
+        if self.enabled {
+            Ok(())
+        } else {
+            Err(ExportError::Disabled)
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ExportError {
+    Disabled,
+    Storage(StorageError),
+    Io(io::Error),
+}
+
+impl fmt::Display for ExportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExportError::Disabled => {
+                write!(f, "task export is disabled (set ENABLE_TASK_EXPORT=true)")
+            }
+            ExportError::Storage(err) => write!(f, "{}", err),
+            ExportError::Io(err) => write!(f, "could not write export: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+impl From<StorageError> for ExportError {
+    fn from(err: StorageError) -> Self {
+        ExportError::Storage(err)
+    }
+}
+
+impl From<io::Error> for ExportError {
+    fn from(err: io::Error) -> Self {
+        ExportError::Io(err)
+    }
+}
+
+impl ResponseError for ExportError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ExportError::Disabled => StatusCode::NOT_FOUND,
+            ExportError::Storage(err) => err.status_code(),
+            ExportError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(serde_json::json!({
+            "error": self.to_string()
+        }))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    #[serde(alias = "jsonl")]
+    Ndjson,
+    Csv,
+    #[serde(alias = "md")]
+    Markdown,
+}
+
+impl ExportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Ndjson => "ndjson",
+            ExportFormat::Csv => "csv",
+            ExportFormat::Markdown => "md",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Ndjson => "application/x-ndjson",
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Markdown => "text/markdown; charset=utf-8",
+        }
+    }
+
+    // Written once, before any task
+    fn header(self) -> Option<Bytes> {
+        match self {
+            ExportFormat::Ndjson => None,
+            ExportFormat::Csv => Some(csv_rows(std::iter::once(CSV_COLUMNS.map(String::from)))),
+            ExportFormat::Markdown => Some(Bytes::from_static(b"# Tasks\n\n")),
+        }
+    }
+
+    fn encode(self, tasks: &[Task]) -> Bytes {
+        match self {
+            ExportFormat::Ndjson => {
+                let mut out = Vec::new();
+                for task in tasks {
+                    serde_json::to_writer(&mut out, task).expect("tasks serialize to JSON");
+                    out.push(b'\n');
+                }
+                Bytes::from(out)
+            }
+            ExportFormat::Csv => csv_rows(tasks.iter().map(|task| {
+                [
+                    task.id.clone(),
+                    task.title.clone(),
+                    task.description.clone(),
+                    task.completed.to_string(),
+                    task.priority.name().to_string(),
+                    task.tags.join(","),
+                    task.due_at.map_or_else(String::new, timestamp),
+                    timestamp(task.created_at),
+                    timestamp(task.updated_at),
+                    task.version.to_string(),
+                ]
+            })),
+            ExportFormat::Markdown => {
+                let mut out = String::new();
+                for task in tasks {
+                    out.push_str(if task.completed { "- [x] " } else { "- [ ] " });
+                    out.push_str(&escape_markdown(&task.title));
+                    if !task.description.is_empty() {
+                        out.push_str(" — ");
+                        out.push_str(&escape_markdown(&task.description));
+                    }
+                    let mut details = Vec::new();
+                    if task.priority != Priority::default() {
+                        details.push(format!("{} priority", task.priority.name()));
+                    }
+                    if let Some(due_at) = task.due_at {
+                        details.push(format!("due {}", timestamp(due_at)));
+                    }
+                    if !details.is_empty() {
+                        out.push_str(&format!(" ({})", details.join(", ")));
+                    }
+                    for tag in &task.tags {
+                        out.push_str(" #");
+                        out.push_str(&escape_markdown(tag));
+                    }
+                    out.push('\n');
+                }
+                Bytes::from(out)
+            }
+        }
+    }
+}
+
+fn timestamp(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::AutoSi, true)
+}
+
+fn csv_rows(rows: impl Iterator<Item = [String; CSV_COLUMNS.len()]>) -> Bytes {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for row in rows {
+        writer.write_record(&row).expect("writing CSV to memory cannot fail");
+    }
+    Bytes::from(writer.into_inner().expect("flushing CSV to memory cannot fail"))
+}
+
+// Keep task text from turning into Markdown markup or breaking the list
+fn escape_markdown(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '#' | '|' => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            '\r' | '\n' => escaped.push(' '),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Query string parameters for /api/tasks/export: the list filters and sort,
+// plus the format
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ExportParams {
+    #[serde(default)]
+    pub format: ExportFormat,
+    pub completed: Option<bool>,
+    pub created_after: Option<chrono::DateTime<Utc>>,
+    pub created_before: Option<chrono::DateTime<Utc>>,
+    pub updated_after: Option<chrono::DateTime<Utc>>,
+    pub updated_before: Option<chrono::DateTime<Utc>>,
+    pub due_before: Option<chrono::DateTime<Utc>>,
+    pub overdue: Option<bool>,
+    pub priority: Option<String>,
+    pub tags_any: Option<String>,
+    pub tags_all: Option<String>,
+    pub sort: Option<String>,
+    pub filter: Option<String>,
+}
+
+impl ExportParams {
+    pub fn into_query(self) -> Result<(ExportFormat, TaskQuery), QueryError> {
+        let mut query = TaskQuery::try_from(ListParams {
+            limit: None,
+            cursor: None,
+            completed: self.completed,
+            created_after: self.created_after,
+            created_before: self.created_before,
+            updated_after: self.updated_after,
+            updated_before: self.updated_before,
+            due_before: self.due_before,
+            overdue: self.overdue,
+            priority: self.priority,
+            tags_any: self.tags_any,
+            tags_all: self.tags_all,
+            sort: self.sort,
+            filter: self.filter,
+            fields: None,
+        })?;
+        query.limit = EXPORT_PAGE_SIZE;
+        Ok((self.format, query))
+    }
+}
+
+// Suggested name for an export, unique enough not to overwrite another
+pub fn file_name(format: ExportFormat) -> String {
+    let id = Uuid::new_v4().simple().to_string();
+    format!(
+        "tasks-{}-{}.{}",
+        Utc::now().format("%Y%m%dT%H%M%SZ"),
+        &id[..8],
+        format.extension()
+    )
+}
+
+// Every task matching the query, a page at a time
+fn pages(
+    storage: Arc<dyn TaskRepository>,
+    query: TaskQuery,
+) -> impl Stream<Item = StorageResult<Vec<Task>>> {
+    stream::unfold(Some(query), move |query| {
+        let storage = storage.clone();
+        async move {
+            let mut query = query?;
+            match storage.list_page(&query).await {
+                Ok(page) => {
+                    let next = match (&page.next_cursor, page.tasks.last()) {
+                        (Some(_), Some(last)) => {
+                            query.after = Some(Cursor::after(last, &query.sort));
+                            Some(query)
+                        }
+                        _ => None,
+                    };
+                    Some((Ok(page.tasks), next))
+                }
+                // Stop after reporting the failure
+                Err(err) => Some((Err(err), None)),
+            }
+        }
+    })
+}
+
+// The export as a stream of encoded chunks, for a streaming response
+pub fn stream_export(
+    storage: Arc<dyn TaskRepository>,
+    query: TaskQuery,
+    format: ExportFormat,
+) -> impl Stream<Item = StorageResult<Bytes>> {
+    use futures_util::StreamExt;
+
+    let header = stream::iter(format.header().map(Ok));
+    header.chain(pages(storage, query).map(move |page| page.map(|tasks| format.encode(&tasks))))
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportSummary {
+    pub file: String,
+    pub path: PathBuf,
+    pub format: ExportFormat,
+    pub tasks: usize,
+    pub bytes: u64,
+}
+
+// Write the export into `dir`. It only appears under its final name once
+// complete, so a reader of the directory never sees half an export.
+pub async fn write_export(
+    storage: Arc<dyn TaskRepository>,
+    query: TaskQuery,
+    format: ExportFormat,
+    dir: &Path,
+) -> Result<ExportSummary, ExportError> {
+    use futures_util::StreamExt;
+
+    tokio::fs::create_dir_all(dir).await?;
+    let file = file_name(format);
+    let path = dir.join(&file);
+    let tmp_path = dir.join(format!(".{}.tmp", file));
+
+    let result: Result<(usize, u64), ExportError> = async {
+        let mut out = tokio::fs::File::create(&tmp_path).await?;
+        let mut tasks = 0;
+        let mut bytes = 0;
+        if let Some(header) = format.header() {
+            out.write_all(&header).await?;
+            bytes += header.len() as u64;
+        }
+
+        let mut pages = std::pin::pin!(pages(storage, query));
+        while let Some(page) = pages.next().await {
+            let page = page?;
+            let chunk = format.encode(&page);
+            out.write_all(&chunk).await?;
+            tasks += page.len();
+            bytes += chunk.len() as u64;
+        }
+
+        out.sync_all().await?;
+        Ok((tasks, bytes))
+    }
+    .await;
+
+    let (tasks, bytes) = match result {
+        Ok(written) => written,
+        Err(err) => {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+            return Err(err);
+        }
+    };
+    tokio::fs::rename(&tmp_path, &path).await?;
+
+    Ok(ExportSummary {
+        file,
+        path,
+        format,
+        tasks,
+        bytes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::TaskCreate;
+    use crate::storage::InMemoryTaskRepository;
+
+    fn task(title: &str) -> Task {
+        Task::new(TaskCreate {
+            title: title.to_string(),
+            description: String::new(),
+            priority: Default::default(),
+            tags: Vec::new(),
+            due_at: None,
+        })
+    }
+
+    fn encode(format: ExportFormat, tasks: &[Task]) -> String {
+        String::from_utf8(format.encode(tasks).to_vec()).unwrap()
+    }
+
+    #[test]
+    fn exports_priorities() {
+        let mut urgent = task("Urgent");
+        urgent.priority = Priority::Urgent;
+        urgent.due_at = Some("2030-01-02T03:04:05Z".parse().unwrap());
+        let tasks = [urgent, task("Normal")];
+
+        let csv = encode(ExportFormat::Csv, &tasks);
+        let column: Vec<_> = csv.lines().map(|row| row.split(',').nth(4).unwrap()).collect();
+        assert_eq!(column, ["urgent", "normal"]);
+
+        let markdown = encode(ExportFormat::Markdown, &tasks);
+        assert_eq!(
+            markdown,
+            "- [ ] Urgent (urgent priority, due 2030-01-02T03:04:05Z)\n- [ ] Normal\n"
+        );
+    }
+
+    #[test]
+    fn exports_tags() {
+        let mut tagged = task("Tagged");
+        tagged.tags = vec!["docker".to_string(), "rust_lang".to_string()];
+        let tasks = [tagged, task("Untagged")];
+
+        let csv = encode(ExportFormat::Csv, &tasks);
+        let mut reader = csv::ReaderBuilder::new().has_headers(false).from_reader(csv.as_bytes());
+        let column: Vec<_> = reader.records().map(|row| row.unwrap()[5].to_string()).collect();
+        assert_eq!(column, ["docker,rust_lang", ""]);
+
+        let markdown = encode(ExportFormat::Markdown, &tasks);
+        assert_eq!(markdown, "- [ ] Tagged #docker #rust\\_lang\n- [ ] Untagged\n");
+    }
+
+    #[test]
+    fn exports_due_dates() {
+        let mut due = task("Due");
+        due.due_at = Some("2030-01-02T03:04:05Z".parse().unwrap());
+        let undated = task("Undated");
+        let tasks = [due, undated];
+
+        let header = String::from_utf8(ExportFormat::Csv.header().unwrap().to_vec()).unwrap();
+        assert!(header.starts_with("id,title,description,completed,priority,tags,due_at,"));
+        let csv = encode(ExportFormat::Csv, &tasks);
+        let due_column: Vec<_> = csv.lines().map(|row| row.split(',').nth(6).unwrap()).collect();
+        assert_eq!(due_column, ["2030-01-02T03:04:05Z", ""]);
+
+        let markdown = encode(ExportFormat::Markdown, &tasks);
+        assert_eq!(markdown, "- [ ] Due (due 2030-01-02T03:04:05Z)\n- [ ] Undated\n");
+    }
+
+    #[test]
+    fn ndjson_writes_one_task_per_line() {
+        let mut done = task("Done");
+        done.completed = true;
+        let tasks = [task("Open"), done];
+        assert!(ExportFormat::Ndjson.header().is_none());
+
+        let ndjson = encode(ExportFormat::Ndjson, &tasks);
+        assert!(ndjson.ends_with('\n'));
+        let lines: Vec<serde_json::Value> =
+            ndjson.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        let expected: Vec<_> =
+            tasks.iter().map(|task| serde_json::to_value(task).unwrap()).collect();
+        assert_eq!(lines, expected);
+        assert_eq!(encode(ExportFormat::Ndjson, &[]), "");
+    }
+
+    #[test]
+    fn csv_quotes_separators_quotes_and_newlines() {
+        let mut awkward = task("Say \"hi\", then leave");
+        awkward.description = "first line\nsecond line".to_string();
+        let tasks = [awkward.clone()];
+
+        let mut csv = ExportFormat::Csv.header().unwrap().to_vec();
+        csv.extend_from_slice(&ExportFormat::Csv.encode(&tasks));
+        let mut reader = csv::Reader::from_reader(csv.as_slice());
+        assert_eq!(reader.headers().unwrap(), CSV_COLUMNS.as_slice());
+        let rows: Vec<_> = reader.records().map(Result::unwrap).collect();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(&rows[0][0], awkward.id);
+        assert_eq!(&rows[0][1], awkward.title);
+        assert_eq!(&rows[0][2], awkward.description);
+        assert_eq!(&rows[0][3], "false");
+        assert_eq!(&rows[0][9], "1");
+    }
+
+    #[test]
+    fn markdown_escapes_markup_and_line_breaks() {
+        let mut done = task("*Ship* [v2](http://x) #1 | <b>`now`</b>");
+        done.completed = true;
+        done.description = "line one\r\nline_two \\ end".to_string();
+        let tasks = [done, task("Plain")];
+
+        let header = ExportFormat::Markdown.header().unwrap();
+        assert_eq!(header, "# Tasks\n\n");
+        assert_eq!(
+            encode(ExportFormat::Markdown, &tasks),
+            "- [x] \\*Ship\\* \\[v2\\](http://x) \\#1 \\| \\<b\\>\\`now\\`\\</b\\> \
+             — line one  line\\_two \\\\ end\n\
+             - [ ] Plain\n"
+        );
+    }
+
+    #[actix_web::test]
+    async fn writes_every_page_into_the_export_directory() {
+        let storage = Arc::new(InMemoryTaskRepository::new());
+        for n in 0..EXPORT_PAGE_SIZE + 2 {
+            storage.create(task(&format!("Task {}", n))).await.unwrap();
+        }
+        let dir = std::env::temp_dir().join(format!("task-api-export-{}", Uuid::new_v4()));
+        let params = actix_web::web::Query::<ExportParams>::from_query("format=ndjson&sort=title")
+            .unwrap()
+            .into_inner();
+        let (format, query) = params.into_query().unwrap();
+
+        let summary = write_export(storage, query, format, &dir).await.unwrap();
+        assert_eq!(summary.tasks, EXPORT_PAGE_SIZE + 2);
+        assert!(summary.file.ends_with(".ndjson"));
+        let contents = std::fs::read_to_string(&summary.path).unwrap();
+        assert_eq!(contents.len() as u64, summary.bytes);
+        let titles: Vec<String> = contents
+            .lines()
+            .map(|line| serde_json::from_str::<Task>(line).unwrap().title)
+            .collect();
+        let mut expected: Vec<_> =
+            (0..EXPORT_PAGE_SIZE + 2).map(|n| format!("Task {}", n)).collect();
+        expected.sort();
+        assert_eq!(titles, expected);
+
+        // Only the finished export is left behind
+        let files: Vec<_> =
+            std::fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().path()).collect();
+        assert_eq!(files, [summary.path]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
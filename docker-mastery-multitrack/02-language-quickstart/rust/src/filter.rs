@@ -0,0 +1,648 @@
+/*!
+ * Filter expression language for task queries
+ *
+ *   completed:false AND (title~"docker" OR created>2026-01-01)
+ *
+ *   expr       := term (OR term)*
+ *   term       := factor (AND factor)*
+ *   factor     := NOT factor | "(" expr ")" | comparison
+ *   comparison := field operator value
+ *
+ * Fields are id, title, description, completed, created (or created_at),
+ * updated (or updated_at), due (or due_at), priority and tags (or tag). `:`
+ * tests equality, `!=` inequality, `~` whether a text field contains the
+ * value ignoring case (ASCII letters only in SQLite), and `>`, `>=`, `<`, `<=`
+ * order timestamps and priorities (low < normal < high < urgent). For tags,
+ * `tags:docker` matches tasks with the tag docker and `tags!=docker` tasks
+ * without it. Keywords are case-insensitive.
+ *
+ * Values are bare words or double-quoted strings with `\"` and `\\` escapes.
+ * Timestamps are RFC 3339 or a YYYY-MM-DD date, which stands for midnight UTC,
+ * or for the whole day with `:` and `!=`. A task without a due date only
+ * matches `due:none` and `due!=` anything else; `due:none` and `due!=none`
+ * are the only comparisons with `none`.
+ *
+ * Expressions are type checked while they are parsed, and every error carries
+ * the (zero-based, in characters) position it refers to.
+ */
+
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use uuid::Uuid;
+
+use crate::models::{Priority, Task};
+use crate::query::QueryError;
+use crate::tags;
+
+const MAX_LENGTH: usize = 2000;
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Id,
+    Title,
+    Description,
+    Completed,
+    CreatedAt,
+    UpdatedAt,
+    DueAt,
+    Priority,
+    Tags,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Id,
+    Text,
+    Bool,
+    Time,
+    Priority,
+    // Compared by whether a task has the tag
+    Tag,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "id" => Some(Field::Id),
+            "title" => Some(Field::Title),
+            "description" => Some(Field::Description),
+            "completed" => Some(Field::Completed),
+            "created" | "created_at" => Some(Field::CreatedAt),
+            "updated" | "updated_at" => Some(Field::UpdatedAt),
+            "due" | "due_at" => Some(Field::DueAt),
+            "priority" => Some(Field::Priority),
+            "tags" | "tag" => Some(Field::Tags),
+            _ => None,
+        }
+    }
+
+    pub fn column(self) -> &'static str {
+        match self {
+            Field::Id => "id",
+            Field::Title => "title",
+            Field::Description => "description",
+            Field::Completed => "completed",
+            Field::CreatedAt => "created_at",
+            Field::UpdatedAt => "updated_at",
+            Field::DueAt => "due_at",
+            Field::Priority => "priority",
+            Field::Tags => "tag",
+        }
+    }
+
+    // Whether a task may have no value, compared as `none`
+    pub fn nullable(self) -> bool {
+        self == Field::DueAt
+    }
+
+    fn kind(self) -> Kind {
+        match self {
+            Field::Id => Kind::Id,
+            Field::Title | Field::Description => Kind::Text,
+            Field::Completed => Kind::Bool,
+            Field::CreatedAt | Field::UpdatedAt | Field::DueAt => Kind::Time,
+            Field::Priority => Kind::Priority,
+            Field::Tags => Kind::Tag,
+        }
+    }
+
+    fn value(self, task: &Task) -> Value {
+        match self {
+            Field::Id => Value::Text(task.id.clone()),
+            Field::Title => Value::Text(task.title.clone()),
+            Field::Description => Value::Text(task.description.clone()),
+            Field::Completed => Value::Bool(task.completed),
+            Field::CreatedAt => Value::Time(task.created_at),
+            Field::UpdatedAt => Value::Time(task.updated_at),
+            Field::DueAt => task.due_at.map_or(Value::None, Value::Time),
+            Field::Priority => Value::Priority(task.priority),
+            Field::Tags => unreachable!("tags are compared by membership"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Eq,
+    Ne,
+    Contains,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+}
+
+impl Operator {
+    fn symbol(self) -> &'static str {
+        match self {
+            Operator::Eq => ":",
+            Operator::Ne => "!=",
+            Operator::Contains => "~",
+            Operator::Gt => ">",
+            Operator::Ge => ">=",
+            Operator::Lt => "<",
+            Operator::Le => "<=",
+        }
+    }
+
+    fn supports(self, kind: Kind) -> bool {
+        match self {
+            Operator::Eq | Operator::Ne => true,
+            Operator::Contains => kind == Kind::Text,
+            Operator::Gt | Operator::Ge | Operator::Lt | Operator::Le => {
+                matches!(kind, Kind::Time | Kind::Priority)
+            }
+        }
+    }
+}
+
+// A type-checked value; always the same variant as its field's value
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub enum Value {
+    Text(String),
+    Bool(bool),
+    Time(DateTime<Utc>),
+    Priority(Priority),
+    // No value, for a nullable field
+    None,
+}
+
+#[derive(Debug, Clone)]
+pub enum FilterExpr {
+    And(Box<FilterExpr>, Box<FilterExpr>),
+    Or(Box<FilterExpr>, Box<FilterExpr>),
+    Not(Box<FilterExpr>),
+    Compare {
+        field: Field,
+        op: Operator,
+        value: Value,
+    },
+}
+
+impl FilterExpr {
+    pub fn parse(input: &str) -> Result<Self, QueryError> {
+        Parser::new(input).parse()
+    }
+
+    pub fn matches(&self, task: &Task) -> bool {
+        match self {
+            FilterExpr::And(left, right) => left.matches(task) && right.matches(task),
+            FilterExpr::Or(left, right) => left.matches(task) || right.matches(task),
+            FilterExpr::Not(inner) => !inner.matches(task),
+            FilterExpr::Compare {
+                field: Field::Tags,
+                op,
+                value: Value::Text(tag),
+            } => {
+                let has = task.tags.contains(tag);
+                match op {
+                    Operator::Ne => !has,
+                    _ => has,
+                }
+            }
+            FilterExpr::Compare { field, op, value } => {
+                let actual = field.value(task);
+                match op {
+                    Operator::Eq => actual == *value,
+                    Operator::Ne => actual != *value,
+                    // A missing value is neither before nor after anything
+                    _ if actual == Value::None => false,
+                    Operator::Contains => match (&actual, value) {
+                        (Value::Text(text), Value::Text(needle)) => {
+                            text.to_lowercase().contains(&needle.to_lowercase())
+                        }
+                        _ => false,
+                    },
+                    Operator::Gt => actual > *value,
+                    Operator::Ge => actual >= *value,
+                    Operator::Lt => actual < *value,
+                    Operator::Le => actual <= *value,
+                }
+            }
+        }
+    }
+
+    fn compare(field: Field, op: Operator, value: Value) -> Self {
+        FilterExpr::Compare { field, op, value }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn new(input: &str) -> Self {
+        Parser {
+            chars: input.chars().collect(),
+            pos: 0,
+            depth: 0,
+        }
+    }
+
+    fn parse(mut self) -> Result<FilterExpr, QueryError> {
+        if self.chars.len() > MAX_LENGTH {
+            return Err(QueryError::new(format!(
+                "filter is longer than {} characters",
+                MAX_LENGTH
+            )));
+        }
+        self.skip_whitespace();
+        if self.peek().is_none() {
+            return Err(QueryError::at(self.pos, "filter is empty"));
+        }
+
+        let expr = self.parse_or()?;
+
+        self.skip_whitespace();
+        match self.peek() {
+            None => Ok(expr),
+            Some(')') => Err(QueryError::at(self.pos, "unmatched ')'")),
+            Some(_) => Err(QueryError::at(
+                self.pos,
+                format!("expected AND, OR or the end of the filter, found '{}'", self.word_at(self.pos)),
+            )),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<FilterExpr, QueryError> {
+        let mut left = self.parse_and()?;
+        while self.keyword("OR") {
+            let right = self.parse_and()?;
+            left = FilterExpr::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<FilterExpr, QueryError> {
+        let mut left = self.parse_factor()?;
+        while self.keyword("AND") {
+            let right = self.parse_factor()?;
+            left = FilterExpr::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_factor(&mut self) -> Result<FilterExpr, QueryError> {
+        self.skip_whitespace();
+        let start = self.pos;
+
+        if self.keyword("NOT") {
+            self.enter(start)?;
+            let inner = self.parse_factor()?;
+            self.depth -= 1;
+            return Ok(FilterExpr::Not(Box::new(inner)));
+        }
+
+        if self.peek() == Some('(') {
+            self.enter(start)?;
+            self.pos += 1;
+            let inner = self.parse_or()?;
+            self.skip_whitespace();
+            if self.peek() != Some(')') {
+                return Err(QueryError::at(start, "unclosed '('"));
+            }
+            self.pos += 1;
+            self.depth -= 1;
+            return Ok(inner);
+        }
+
+        self.parse_comparison()
+    }
+
+    fn enter(&mut self, position: usize) -> Result<(), QueryError> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(QueryError::at(
+                position,
+                format!("filter is nested more than {} levels deep", MAX_DEPTH),
+            ));
+        }
+        Ok(())
+    }
+
+    fn parse_comparison(&mut self) -> Result<FilterExpr, QueryError> {
+        let field_pos = self.pos;
+        let name: String = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+        if name.is_empty() {
+            return Err(match self.peek() {
+                None => QueryError::at(field_pos, "expected a field name"),
+                Some(_) => QueryError::at(
+                    field_pos,
+                    format!("expected a field name, found '{}'", self.word_at(field_pos)),
+                ),
+            });
+        }
+        let field = Field::parse(&name).ok_or_else(|| {
+            QueryError::at(
+                field_pos,
+                format!(
+                    "unknown field '{}' \
+                     (expected id, title, description, completed, created, updated, due, \
+                     priority or tags)",
+                    name
+                ),
+            )
+        })?;
+
+        self.skip_whitespace();
+        let op_pos = self.pos;
+        let op = self.parse_operator().ok_or_else(|| {
+            QueryError::at(
+                op_pos,
+                format!("expected an operator (:, !=, ~, >, >=, <, <=) after '{}'", name),
+            )
+        })?;
+        if !op.supports(field.kind()) {
+            return Err(QueryError::at(
+                op_pos,
+                format!("operator '{}' cannot be used with field '{}'", op.symbol(), name),
+            ));
+        }
+
+        self.skip_whitespace();
+        let value_pos = self.pos;
+        let raw = self.parse_raw_value()?.ok_or_else(|| {
+            QueryError::at(
+                value_pos,
+                format!("expected a value after '{}{}'", name, op.symbol()),
+            )
+        })?;
+
+        typed_comparison(field, op, &raw).map_err(|message| QueryError::at(value_pos, message))
+    }
+
+    fn parse_operator(&mut self) -> Option<Operator> {
+        let (op, len) = match (self.peek(), self.chars.get(self.pos + 1)) {
+            (Some(':'), _) | (Some('='), _) => (Operator::Eq, 1),
+            (Some('~'), _) => (Operator::Contains, 1),
+            (Some('!'), Some('=')) => (Operator::Ne, 2),
+            (Some('>'), Some('=')) => (Operator::Ge, 2),
+            (Some('>'), _) => (Operator::Gt, 1),
+            (Some('<'), Some('=')) => (Operator::Le, 2),
+            (Some('<'), _) => (Operator::Lt, 1),
+            _ => return None,
+        };
+        self.pos += len;
+        Some(op)
+    }
+
+    // A quoted string or a bare word; `None` when there is no value at all
+    fn parse_raw_value(&mut self) -> Result<Option<String>, QueryError> {
+        if self.peek() != Some('"') {
+            let word = self.take_while(|c| !c.is_whitespace() && !matches!(c, '(' | ')' | '"'));
+            return Ok(Some(word).filter(|word| !word.is_empty()));
+        }
+
+        let start = self.pos;
+        self.pos += 1;
+        let mut value = String::new();
+        loop {
+            match self.peek() {
+                None => return Err(QueryError::at(start, "unterminated string")),
+                Some('"') => {
+                    self.pos += 1;
+                    return Ok(Some(value));
+                }
+                Some('\\') => {
+                    match self.chars.get(self.pos + 1) {
+                        Some(&c @ ('"' | '\\')) => value.push(c),
+                        _ => {
+                            return Err(QueryError::at(
+                                self.pos,
+                                "only \\\" and \\\\ escapes are allowed in strings",
+                            ))
+                        }
+                    }
+                    self.pos += 2;
+                }
+                Some(c) => {
+                    value.push(c);
+                    self.pos += 1;
+                }
+            }
+        }
+    }
+
+    // Consume `word` if it comes next as a whole word, ignoring case
+    fn keyword(&mut self, word: &str) -> bool {
+        self.skip_whitespace();
+        let end = self.pos + word.len();
+        let matches = end <= self.chars.len()
+            && self.chars[self.pos..end]
+                .iter()
+                .zip(word.chars())
+                .all(|(a, b)| a.eq_ignore_ascii_case(&b))
+            && self
+                .chars
+                .get(end)
+                .is_none_or(|c| !c.is_ascii_alphanumeric() && *c != '_');
+        if matches {
+            self.pos = end;
+        }
+        matches
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.take_while(char::is_whitespace);
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> String {
+        let start = self.pos;
+        while self.peek().is_some_and(&predicate) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos].iter().collect()
+    }
+
+    // The run of non-space characters at `position`, for error messages
+    fn word_at(&self, position: usize) -> String {
+        let word: String = self.chars[position..]
+            .iter()
+            .take_while(|c| !c.is_whitespace())
+            .take(20)
+            .collect();
+        if word.is_empty() {
+            self.chars[position].to_string()
+        } else {
+            word
+        }
+    }
+}
+
+// Check `raw` against the field's type and build the comparison
+fn typed_comparison(field: Field, op: Operator, raw: &str) -> Result<FilterExpr, String> {
+    if field.nullable() && raw.eq_ignore_ascii_case("none") {
+        return match op {
+            Operator::Eq | Operator::Ne => Ok(FilterExpr::compare(field, op, Value::None)),
+            _ => Err("'none' can only be compared with ':' or '!='".to_string()),
+        };
+    }
+
+    let value = match field.kind() {
+        Kind::Text => Value::Text(raw.to_string()),
+        Kind::Id => match Uuid::parse_str(raw) {
+            // Ids are stored in their hyphenated lowercase form
+            Ok(id) => Value::Text(id.to_string()),
+            Err(_) => return Err(format!("'{}' is not a valid task id", raw)),
+        },
+        Kind::Bool => match raw.to_ascii_lowercase().as_str() {
+            "true" => Value::Bool(true),
+            "false" => Value::Bool(false),
+            _ => return Err(format!("expected true or false, found '{}'", raw)),
+        },
+        Kind::Priority => Value::Priority(raw.to_ascii_lowercase().parse()?),
+        Kind::Tag => Value::Text(tags::normalize(raw)?),
+        Kind::Time => {
+            if let Ok(time) = DateTime::parse_from_rfc3339(raw) {
+                Value::Time(time.with_timezone(&Utc))
+            } else if let Ok(date) = NaiveDate::parse_from_str(raw, "%Y-%m-%d") {
+                return Ok(date_comparison(field, op, date));
+            } else {
+                return Err(format!(
+                    "expected an RFC 3339 timestamp or a YYYY-MM-DD date, found '{}'",
+                    raw
+                ));
+            }
+        }
+    };
+
+    Ok(FilterExpr::compare(field, op, value))
+}
+
+// A bare date compares against midnight UTC, except that `:` and `!=` match
+// (or exclude) the whole day
+fn date_comparison(field: Field, op: Operator, date: NaiveDate) -> FilterExpr {
+    let midnight = |date: NaiveDate| Value::Time(date.and_time(Default::default()).and_utc());
+    let day_start = midnight(date);
+    let day_end = date.checked_add_days(Days::new(1)).map(midnight);
+
+    match (op, day_end) {
+        (Operator::Eq, Some(day_end)) => FilterExpr::And(
+            Box::new(FilterExpr::compare(field, Operator::Ge, day_start)),
+            Box::new(FilterExpr::compare(field, Operator::Lt, day_end)),
+        ),
+        // Not within the day, which a task without a due date isn't either
+        (Operator::Ne, Some(_)) => {
+            FilterExpr::Not(Box::new(date_comparison(field, Operator::Eq, date)))
+        }
+        // The last representable day has no end; fall back to its start
+        _ => FilterExpr::compare(field, op, day_start),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::TaskCreate;
+
+    fn task(title: &str, completed: bool) -> Task {
+        let mut task = Task::new(TaskCreate {
+            title: title.to_string(),
+            description: "Notes on the setup".to_string(),
+            priority: Default::default(),
+            tags: Vec::new(),
+            due_at: None,
+        });
+        task.completed = completed;
+        task.created_at = "2026-03-04T05:06:07Z".parse().unwrap();
+        task
+    }
+
+    fn matches(filter: &str, task: &Task) -> bool {
+        FilterExpr::parse(filter).unwrap().matches(task)
+    }
+
+    fn error(filter: &str) -> (String, Option<usize>) {
+        let err = FilterExpr::parse(filter).unwrap_err();
+        (err.message, err.position)
+    }
+
+    #[test]
+    fn binds_and_tighter_than_or() {
+        let open = task("Docker", false);
+        let done = task("Rust", true);
+
+        let filter = "title:Rust OR title:Docker AND completed:true";
+        assert!(!matches(filter, &open));
+        assert!(matches(filter, &done));
+        assert!(!matches("(title:Rust OR title:Docker) AND completed:true", &open));
+        assert!(matches("not completed:TRUE and title~DOCK", &open));
+        assert!(matches("NOT NOT completed:true", &done));
+        assert!(matches(r#"description~"ON THE""#, &open));
+        assert!(!matches(r#"title:"Dock""#, &open));
+    }
+
+    #[test]
+    fn compares_dates_as_days_or_instants() {
+        let task = task("Dated", false);
+        assert!(matches("created:2026-03-04", &task));
+        assert!(!matches("created!=2026-03-04", &task));
+        assert!(matches("created>2026-03-04", &task));
+        assert!(matches("created<2026-03-05 AND created>=2026-03-04T05:06:07Z", &task));
+        assert!(!matches("created>2026-03-04T05:06:07+00:00", &task));
+        assert!(matches("updated>2026-01-01", &task));
+    }
+
+    #[test]
+    fn reads_quoted_strings_with_escapes() {
+        let task = task(r#"Say "hi" \ bye"#, false);
+        assert!(matches(r#"title:"Say \"hi\" \\ bye""#, &task));
+        let message = "only \\\" and \\\\ escapes are allowed in strings".to_string();
+        assert_eq!(error(r#"title:"a\n""#), (message, Some(8)));
+    }
+
+    #[test]
+    fn points_errors_at_where_they_are() {
+        let cases = [
+            ("", "filter is empty", Some(0)),
+            ("   ", "filter is empty", Some(3)),
+            ("done:true", "unknown field 'done' (expected id, title, description, completed, \
+                created, updated, due, priority or tags)", Some(0)),
+            ("title", "expected an operator (:, !=, ~, >, >=, <, <=) after 'title'", Some(5)),
+            ("title:", "expected a value after 'title:'", Some(6)),
+            ("completed~true", "operator '~' cannot be used with field 'completed'", Some(9)),
+            ("title>a", "operator '>' cannot be used with field 'title'", Some(5)),
+            ("completed:yes", "expected true or false, found 'yes'", Some(10)),
+            ("id:42", "'42' is not a valid task id", Some(3)),
+            ("created>soon", "expected an RFC 3339 timestamp or a YYYY-MM-DD date, found 'soon'",
+                Some(8)),
+            ("due>none", "'none' can only be compared with ':' or '!='", Some(4)),
+            ("tags:a,b", "tag 'a,b' must not contain commas or whitespace", Some(5)),
+            ("(title:a", "unclosed '('", Some(0)),
+            ("title:a)", "unmatched ')'", Some(7)),
+            ("title:a title:b", "expected AND, OR or the end of the filter, found 'title:b'",
+                Some(8)),
+            ("title:a AND", "expected a field name", Some(11)),
+            ("title:a AND )", "expected a field name, found ')'", Some(12)),
+            (r#"title:"open"#, "unterminated string", Some(6)),
+            // Positions count characters, not bytes
+            ("title:é OR ?", "expected a field name, found '?'", Some(11)),
+        ];
+        for (filter, message, position) in cases {
+            assert_eq!(error(filter), (message.to_string(), position), "{}", filter);
+        }
+    }
+
+    #[test]
+    fn limits_length_and_nesting() {
+        let long = format!("title:{}", "a".repeat(MAX_LENGTH));
+        assert_eq!(error(&long), ("filter is longer than 2000 characters".to_string(), None));
+
+        let nested = format!("{}title:a{}", "(".repeat(MAX_DEPTH), ")".repeat(MAX_DEPTH));
+        assert!(FilterExpr::parse(&nested).is_ok());
+        let too_deep = format!("{}title:a{}", "(".repeat(MAX_DEPTH + 1), ")".repeat(MAX_DEPTH + 1));
+        assert_eq!(
+            error(&too_deep),
+            ("filter is nested more than 32 levels deep".to_string(), Some(MAX_DEPTH))
+        );
+        let nots = format!("{}completed:true", "NOT ".repeat(MAX_DEPTH + 1));
+        assert_eq!(error(&nots).1, Some(4 * MAX_DEPTH));
+    }
+}
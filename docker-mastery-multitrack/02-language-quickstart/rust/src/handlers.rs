@@ -0,0 +1,929 @@
+use actix_web::http::header::{self, ContentDisposition, HeaderValue, IfMatch};
+use actix_multipart::Multipart;
+use actix_web::{web, HttpMessage, HttpRequest, HttpResponse, Result};
+use chrono::Utc;
+use log::{info, warn};
+
+use crate::attachments::AttachmentStore;
+use crate::batch::{self, BatchOperation, BatchRequest, MAX_BATCH_OPERATIONS};
+use crate::export::{self, ExportConfig, ExportParams};
+use crate::history;
+use crate::idempotency::{self, Claim, IdempotencyStore, IDEMPOTENT_REPLAYED};
+use crate::import::{self, ImportError, ImportFormat, ImportParams, MAX_IMPORT_BYTES};
+use crate::models::{Task, TaskCreate, TaskReplace};
+use crate::patch::TaskPatch;
+use crate::precondition::{self, etag, PreconditionConfig, PreconditionError};
+use crate::projection::{FieldsParams, Projection};
+use crate::query::{self, ListParams, TaskQuery};
+use crate::search::{SearchParams, SearchQuery};
+use crate::storage::{Conditional, TaskRepository};
+use crate::tags::{self, MergeTags, RenameTag};
+use crate::trash;
+
+pub type TaskStorage = web::Data<dyn TaskRepository>;
+
+fn tag_not_found(tag: &str) -> HttpResponse {
+    HttpResponse::NotFound().json(serde_json::json!({
+        "error": format!("Tag {} not found", tag)
+    }))
+}
+
+// A PATCH racing other writers is re-applied this many times
+const PATCH_ATTEMPTS: usize = 5;
+
+fn task_not_found(task_id: &str) -> HttpResponse {
+    HttpResponse::NotFound().json(serde_json::json!({
+        "error": format!("Task with id {} not found", task_id)
+    }))
+}
+
+fn trashed_task_not_found(task_id: &str) -> HttpResponse {
+    HttpResponse::NotFound().json(serde_json::json!({
+        "error": format!("Task with id {} not found in trash", task_id)
+    }))
+}
+
+// The version a write conditioned on `condition` expects the task to be at,
+// or `None` when there is no such task
+async fn matched_version(
+    data: &TaskStorage,
+    task_id: &str,
+    condition: &IfMatch,
+) -> Result<Option<i64>> {
+    match data.get(task_id).await? {
+        Some(current) => {
+            precondition::check(condition, &current)?;
+            Ok(Some(current.version))
+        }
+        None => Ok(None),
+    }
+}
+
+// List tasks a page at a time
+pub async fn list_tasks(
+    params: web::Query<ListParams>,
+    data: TaskStorage,
+) -> Result<HttpResponse> {
+    let mut params = params.into_inner();
+    let projection = Projection::from_param(params.fields.take().as_deref())?;
+    let query = TaskQuery::try_from(params)?;
+    let page = data.list_page(&query).await?;
+
+    info!("Fetching {} of {} tasks", page.tasks.len(), page.total);
+
+    Ok(match projection {
+        None => HttpResponse::Ok().json(page),
+        Some(projection) => HttpResponse::Ok().json(serde_json::json!({
+            "tasks": page.tasks.iter().map(|task| projection.apply(task)).collect::<Vec<_>>(),
+            "total": page.total,
+            "next_cursor": page.next_cursor
+        })),
+    })
+}
+
+// Full-text search over titles and descriptions
+pub async fn search_tasks(
+    params: web::Query<SearchParams>,
+    data: TaskStorage,
+) -> Result<HttpResponse> {
+    let query = SearchQuery::try_from(params.into_inner())?;
+    let results = data.search(&query).await?;
+
+    info!("Search for {:?} matched {} tasks", query.text, results.total);
+
+    Ok(HttpResponse::Ok().json(results))
+}
+
+// Download all (or the filtered) tasks as NDJSON, CSV or a Markdown checklist
+pub async fn export_tasks(
+    params: web::Query<ExportParams>,
+    config: web::Data<ExportConfig>,
+    data: TaskStorage,
+) -> Result<HttpResponse> {
+    config.check_enabled()?;
+    let (format, query) = params.into_inner().into_query()?;
+    let file = export::file_name(format);
+
+    info!("Streaming {} task export {}", format.extension(), file);
+
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(ContentDisposition::attachment(file))
+        .streaming(export::stream_export(data.into_inner(), query, format)))
+}
+
+// Write all (or the filtered) tasks to a file in the exports directory
+pub async fn write_export(
+    params: web::Query<ExportParams>,
+    config: web::Data<ExportConfig>,
+    data: TaskStorage,
+) -> Result<HttpResponse> {
+    config.check_enabled()?;
+    let (format, query) = params.into_inner().into_query()?;
+    let summary = export::write_export(data.into_inner(), query, format, &config.dir).await?;
+
+    info!("Exported {} tasks to {}", summary.tasks, summary.path.display());
+
+    Ok(HttpResponse::Created().json(summary))
+}
+
+// Bulk load tasks from JSON, NDJSON, CSV or todo.txt. With dry_run=true the
+// input is only validated.
+pub async fn import_tasks(
+    req: HttpRequest,
+    params: web::Query<ImportParams>,
+    payload: web::Payload,
+    data: TaskStorage,
+) -> Result<HttpResponse> {
+    let params = params.into_inner();
+    let format = params
+        .format
+        .or_else(|| ImportFormat::from_media_type(req.content_type()))
+        .ok_or(ImportError::UnknownFormat)?;
+    let body = payload
+        .to_bytes_limited(MAX_IMPORT_BYTES)
+        .await
+        .map_err(|_| ImportError::TooLarge)??;
+
+    let parsed = import::parse(format, &body, &params.columns())?;
+    let report = import::import(&**data, format, parsed, params.dry_run).await?;
+
+    info!(
+        "Import of {} records: {} valid, {} imported{}",
+        report.records,
+        report.valid,
+        report.imported,
+        if report.dry_run { " (dry run)" } else { "" }
+    );
+
+    Ok(match (report.succeeded(), report.dry_run) {
+        (false, _) => HttpResponse::UnprocessableEntity().json(report),
+        (true, true) => HttpResponse::Ok().json(report),
+        (true, false) => HttpResponse::Created().json(report),
+    })
+}
+
+// Create new task. With an Idempotency-Key, a retried request gets the
+// original response back instead of creating the task twice.
+pub async fn create_task(
+    req: HttpRequest,
+    task_data: web::Json<TaskCreate>,
+    idempotency_store: web::Data<IdempotencyStore>,
+    data: TaskStorage,
+) -> Result<HttpResponse> {
+    let task_data = task_data.into_inner();
+
+    let claim = match idempotency::key(&req)? {
+        Some(key) => {
+            let fingerprint = idempotency::fingerprint(&task_data);
+            match idempotency_store.claim(&key, fingerprint)? {
+                Claim::Replay(task) => {
+                    info!("Replayed task creation for idempotency key {:?}: {}", key, task.id);
+                    return Ok(HttpResponse::Created()
+                        .insert_header(etag(&task))
+                        .insert_header((IDEMPOTENT_REPLAYED, "true"))
+                        .json(task));
+                }
+                Claim::New(guard) => Some(guard),
+            }
+        }
+        None => None,
+    };
+
+    let task = data.create(Task::new(task_data)).await?;
+    if let Some(claim) = claim {
+        claim.complete(&task);
+    }
+
+    info!("Created task: {} - {}", task.id, task.title);
+
+    Ok(HttpResponse::Created().insert_header(etag(&task)).json(task))
+}
+
+// Create, update and delete many tasks in one request, either all or nothing
+// (`atomic`) or each on its own
+pub async fn batch_tasks(
+    request: web::Json<BatchRequest>,
+    data: TaskStorage,
+) -> Result<HttpResponse> {
+    let request = request.into_inner();
+    if request.operations.is_empty() || request.operations.len() > MAX_BATCH_OPERATIONS {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": format!(
+                "a batch must have between 1 and {} operations",
+                MAX_BATCH_OPERATIONS
+            )
+        })));
+    }
+
+    let operations: Vec<BatchOperation> =
+        request.operations.into_iter().map(BatchOperation::from).collect();
+
+    let response = if request.atomic {
+        let (committed, response) = batch::apply_atomically(&**data, operations).await?;
+        info!(
+            "Atomic batch of {} operations {}",
+            response.results.len(),
+            if committed { "committed" } else { "rolled back" }
+        );
+        response
+    } else {
+        let response = batch::apply_each(&**data, operations).await;
+        info!(
+            "Batch of {} operations: {} succeeded, {} failed",
+            response.results.len(),
+            response.succeeded,
+            response.failed
+        );
+        response
+    };
+
+    // A rolled back atomic batch is a conflict; a best-effort one reports
+    // failures per operation
+    if response.atomic && response.failed > 0 {
+        Ok(HttpResponse::Conflict().json(response))
+    } else {
+        Ok(HttpResponse::Ok().json(response))
+    }
+}
+
+// Get specific task
+pub async fn get_task(
+    req: HttpRequest,
+    path: web::Path<String>,
+    params: web::Query<FieldsParams>,
+    data: TaskStorage,
+) -> Result<HttpResponse> {
+    let task_id = path.into_inner();
+    let projection = Projection::from_param(params.fields.as_deref())?;
+
+    match data.get(&task_id).await? {
+        Some(task) if precondition::not_modified(&req, &task) => {
+            Ok(HttpResponse::NotModified().insert_header(etag(&task)).finish())
+        }
+        Some(task) => Ok(match projection {
+            None => HttpResponse::Ok().insert_header(etag(&task)).json(task),
+            Some(projection) => HttpResponse::Ok()
+                .insert_header(etag(&task))
+                .json(projection.apply(&task)),
+        }),
+        None => {
+            info!("Task not found: {}", task_id);
+            Ok(task_not_found(&task_id))
+        }
+    }
+}
+
+// The incomplete task to work on next, see `query::next_task`
+pub async fn next_task(
+    params: web::Query<FieldsParams>,
+    data: TaskStorage,
+) -> Result<HttpResponse> {
+    let projection = Projection::from_param(params.fields.as_deref())?;
+
+    let page = data.list_page(&query::next_task()).await?;
+    match page.tasks.into_iter().next() {
+        Some(task) => Ok(match projection {
+            None => HttpResponse::Ok().insert_header(etag(&task)).json(task),
+            Some(projection) => HttpResponse::Ok()
+                .insert_header(etag(&task))
+                .json(projection.apply(&task)),
+        }),
+        None => Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "No incomplete tasks"
+        }))),
+    }
+}
+
+// Replace a task's writable fields
+pub async fn update_task(
+    req: HttpRequest,
+    path: web::Path<String>,
+    replacement: web::Json<TaskReplace>,
+    config: web::Data<PreconditionConfig>,
+    data: TaskStorage,
+) -> Result<HttpResponse> {
+    let task_id = path.into_inner();
+    let changes = replacement.into_inner().into();
+
+    let outcome = match precondition::if_match(&req, &config)? {
+        None => data
+            .update(&task_id, changes)
+            .await?
+            .map_or(Conditional::NotFound, Conditional::Applied),
+        Some(condition) => match matched_version(&data, &task_id, &condition).await? {
+            Some(version) => data.update_if_version(&task_id, changes, version).await?,
+            None => Conditional::NotFound,
+        },
+    };
+
+    match outcome {
+        Conditional::Applied(task) => {
+            info!("Updated task: {} - {}", task.id, task.title);
+            Ok(HttpResponse::Ok().insert_header(etag(&task)).json(task))
+        }
+        Conditional::NotFound => {
+            info!("Task not found for update: {}", task_id);
+            Ok(task_not_found(&task_id))
+        }
+        Conditional::Conflict => Err(PreconditionError::Failed.into()),
+    }
+}
+
+// Apply a JSON Merge Patch or JSON Patch document to a task. The patch is
+// applied to the task as read; if it changed before the write, the patch is
+// applied again, unless the client asked for a specific version with If-Match.
+pub async fn patch_task(
+    req: HttpRequest,
+    path: web::Path<String>,
+    body: web::Bytes,
+    config: web::Data<PreconditionConfig>,
+    data: TaskStorage,
+) -> Result<HttpResponse> {
+    let task_id = path.into_inner();
+    let condition = precondition::if_match(&req, &config)?;
+    let media_type = req.mime_type()?;
+    let patch = TaskPatch::parse(media_type.as_ref().map(|mime| mime.essence_str()), &body)?;
+
+    for _ in 0..PATCH_ATTEMPTS {
+        let Some(current) = data.get(&task_id).await? else {
+            info!("Task not found for patch: {}", task_id);
+            return Ok(task_not_found(&task_id));
+        };
+        if let Some(condition) = &condition {
+            precondition::check(condition, &current)?;
+        }
+
+        let changes = patch.apply(&current)?;
+        match data.update_if_version(&task_id, changes, current.version).await? {
+            Conditional::Applied(task) => {
+                info!("Patched task: {} - {}", task.id, task.title);
+                return Ok(HttpResponse::Ok().insert_header(etag(&task)).json(task));
+            }
+            Conditional::NotFound => {
+                info!("Task not found for patch: {}", task_id);
+                return Ok(task_not_found(&task_id));
+            }
+            Conditional::Conflict if condition.is_some() => {
+                return Err(PreconditionError::Failed.into());
+            }
+            Conditional::Conflict => continue,
+        }
+    }
+
+    Ok(HttpResponse::Conflict().json(serde_json::json!({
+        "error": format!("Task with id {} kept changing while being patched, try again", task_id)
+    })))
+}
+
+// Move a task to the trash
+pub async fn delete_task(
+    req: HttpRequest,
+    path: web::Path<String>,
+    config: web::Data<PreconditionConfig>,
+    data: TaskStorage,
+) -> Result<HttpResponse> {
+    let task_id = path.into_inner();
+
+    let outcome = match precondition::if_match(&req, &config)? {
+        None => data
+            .delete(&task_id)
+            .await?
+            .map_or(Conditional::NotFound, Conditional::Applied),
+        Some(condition) => match matched_version(&data, &task_id, &condition).await? {
+            Some(version) => data.delete_if_version(&task_id, version).await?,
+            None => Conditional::NotFound,
+        },
+    };
+
+    match outcome {
+        Conditional::Applied(task) => {
+            info!("Moved task to trash: {} - {}", task.id, task.title);
+            Ok(HttpResponse::NoContent().finish())
+        }
+        Conditional::NotFound => {
+            info!("Task not found for deletion: {}", task_id);
+            Ok(task_not_found(&task_id))
+        }
+        Conditional::Conflict => Err(PreconditionError::Failed.into()),
+    }
+}
+
+// A task's revisions, oldest first, with the fields each one changed. The
+// history survives moving the task to the trash, but not purging it.
+pub async fn task_history(path: web::Path<String>, data: TaskStorage) -> Result<HttpResponse> {
+    let task_id = path.into_inner();
+    let revisions = data.history(&task_id).await?;
+    if revisions.is_empty() && data.get(&task_id).await?.is_none() {
+        return Ok(task_not_found(&task_id));
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "task_id": task_id,
+        "revisions": history::entries(&revisions)
+    })))
+}
+
+// The task as a revision left it
+pub async fn task_revision(
+    path: web::Path<(String, String)>,
+    params: web::Query<FieldsParams>,
+    data: TaskStorage,
+) -> Result<HttpResponse> {
+    let (task_id, revision) = path.into_inner();
+    let projection = Projection::from_param(params.fields.as_deref())?;
+
+    let found = match revision.parse() {
+        Ok(number) => data.revision(&task_id, number).await?,
+        Err(_) => None,
+    };
+    let Some(found) = found else {
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": format!("Task with id {} has no revision {}", task_id, revision)
+        })));
+    };
+
+    Ok(match projection {
+        None => HttpResponse::Ok().json(found.task),
+        Some(projection) => HttpResponse::Ok().json(projection.apply(&found.task)),
+    })
+}
+
+// Attach one or more files to a task (multipart/form-data)
+pub async fn upload_attachments(
+    path: web::Path<String>,
+    payload: Multipart,
+    attachments: web::Data<AttachmentStore>,
+    data: TaskStorage,
+) -> Result<HttpResponse> {
+    attachments.check_enabled()?;
+    let task_id = path.into_inner();
+    if data.get(&task_id).await?.is_none() {
+        return Ok(task_not_found(&task_id));
+    }
+
+    let saved = attachments.save_all(&task_id, payload).await?;
+
+    // The task may have been deleted while the files were uploading
+    if data.get(&task_id).await?.is_none() {
+        for attachment in &saved {
+            if let Err(err) = attachments.delete(&task_id, &attachment.id).await {
+                warn!(
+                    "Could not remove attachment {} of task {}: {}",
+                    attachment.id, task_id, err
+                );
+            }
+        }
+        return Ok(task_not_found(&task_id));
+    }
+
+    info!("Attached {} files to task {}", saved.len(), task_id);
+
+    Ok(HttpResponse::Created().json(saved))
+}
+
+// List a task's attachments
+pub async fn list_attachments(
+    path: web::Path<String>,
+    attachments: web::Data<AttachmentStore>,
+    data: TaskStorage,
+) -> Result<HttpResponse> {
+    attachments.check_enabled()?;
+    let task_id = path.into_inner();
+    if data.get(&task_id).await?.is_none() {
+        return Ok(task_not_found(&task_id));
+    }
+
+    Ok(HttpResponse::Ok().json(attachments.list(&task_id).await?))
+}
+
+// Download an attachment with its original media type and file name
+pub async fn download_attachment(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    attachments: web::Data<AttachmentStore>,
+    data: TaskStorage,
+) -> Result<HttpResponse> {
+    attachments.check_enabled()?;
+    let (task_id, attachment_id) = path.into_inner();
+    if data.get(&task_id).await?.is_none() {
+        return Ok(task_not_found(&task_id));
+    }
+
+    let file = attachments.open(&task_id, &attachment_id).await?;
+    let mut response = file.into_response(&req);
+    // Browsers must not second-guess the stored type of user content
+    response
+        .headers_mut()
+        .insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    Ok(response)
+}
+
+// Remove an attachment from a task
+pub async fn delete_attachment(
+    path: web::Path<(String, String)>,
+    attachments: web::Data<AttachmentStore>,
+    data: TaskStorage,
+) -> Result<HttpResponse> {
+    attachments.check_enabled()?;
+    let (task_id, attachment_id) = path.into_inner();
+    if data.get(&task_id).await?.is_none() {
+        return Ok(task_not_found(&task_id));
+    }
+
+    attachments.delete(&task_id, &attachment_id).await?;
+    info!("Deleted attachment {} of task {}", attachment_id, task_id);
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// List the trash a page at a time, with the same parameters as the task list
+pub async fn list_trash(
+    params: web::Query<ListParams>,
+    data: TaskStorage,
+) -> Result<HttpResponse> {
+    let mut params = params.into_inner();
+    let projection = Projection::from_param(params.fields.take().as_deref())?;
+    let mut query = TaskQuery::try_from(params)?;
+    query.filter.trashed = true;
+    let page = data.list_page(&query).await?;
+
+    info!("Fetching {} of {} trashed tasks", page.tasks.len(), page.total);
+
+    Ok(match projection {
+        None => HttpResponse::Ok().json(page),
+        Some(projection) => HttpResponse::Ok().json(serde_json::json!({
+            "tasks": page.tasks.iter().map(|task| projection.apply(task)).collect::<Vec<_>>(),
+            "total": page.total,
+            "next_cursor": page.next_cursor
+        })),
+    })
+}
+
+// Take a task back out of the trash
+pub async fn restore_task(path: web::Path<String>, data: TaskStorage) -> Result<HttpResponse> {
+    let task_id = path.into_inner();
+
+    match data.restore(&task_id).await? {
+        Some(task) => {
+            info!("Restored task: {} - {}", task.id, task.title);
+            Ok(HttpResponse::Ok().insert_header(etag(&task)).json(task))
+        }
+        None => {
+            info!("Task not found in trash: {}", task_id);
+            Ok(trashed_task_not_found(&task_id))
+        }
+    }
+}
+
+// Permanently remove a task, and its attachments, from the trash
+pub async fn purge_task(
+    path: web::Path<String>,
+    attachments: web::Data<AttachmentStore>,
+    data: TaskStorage,
+) -> Result<HttpResponse> {
+    let task_id = path.into_inner();
+
+    match trash::purge(&**data, &attachments, &task_id).await? {
+        Some(task) => {
+            info!("Purged task: {} - {}", task.id, task.title);
+            Ok(HttpResponse::NoContent().finish())
+        }
+        None => {
+            info!("Task not found in trash: {}", task_id);
+            Ok(trashed_task_not_found(&task_id))
+        }
+    }
+}
+
+// Permanently remove everything in the trash
+pub async fn empty_trash(
+    attachments: web::Data<AttachmentStore>,
+    data: TaskStorage,
+) -> Result<HttpResponse> {
+    let purged = trash::purge_before(&**data, &attachments, Utc::now()).await?;
+    info!("Emptied the trash: {} tasks purged", purged);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "purged": purged })))
+}
+
+// Every tag in use and how many tasks have it
+pub async fn list_tags(data: TaskStorage) -> Result<HttpResponse> {
+    let tags = data.tags().await?;
+    info!("Fetching {} tags", tags.len());
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "tags": tags })))
+}
+
+// Rename a tag on every task that has it. Renaming onto a tag that is already
+// in use would merge the two, which has to be asked for with `merge_tags`.
+pub async fn rename_tag(
+    path: web::Path<String>,
+    rename: web::Json<RenameTag>,
+    data: TaskStorage,
+) -> Result<HttpResponse> {
+    let tag = path.into_inner();
+    let Ok(tag) = tags::normalize(&tag) else {
+        return Ok(tag_not_found(&tag));
+    };
+    let name = rename.into_inner().name;
+    // Renaming a tag to itself changes nothing, as long as the tag exists
+    if name == tag {
+        if !data.tags().await?.iter().any(|used| used.tag == tag) {
+            return Ok(tag_not_found(&tag));
+        }
+        return Ok(HttpResponse::Ok().json(serde_json::json!({ "tag": name, "tasks": 0 })));
+    }
+
+    let Some(retagged) = data.retag(std::slice::from_ref(&tag), &name, false).await? else {
+        return Ok(HttpResponse::Conflict().json(serde_json::json!({
+            "error": format!("Tag {} already exists; merge the tags instead", name)
+        })));
+    };
+    if retagged.is_empty() {
+        info!("Tag not found: {}", tag);
+        return Ok(tag_not_found(&tag));
+    }
+    info!("Renamed tag {} to {} on {} tasks", tag, name, retagged.len());
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "tag": name, "tasks": retagged.len() })))
+}
+
+// Replace several tags with one on every task that has any of them
+pub async fn merge_tags(merge: web::Json<MergeTags>, data: TaskStorage) -> Result<HttpResponse> {
+    let MergeTags { tags, into } = merge.into_inner();
+    if tags.is_empty() {
+        return Ok(HttpResponse::BadRequest().json(serde_json::json!({
+            "error": "tags must name at least one tag to merge"
+        })));
+    }
+
+    let retagged = data.retag(&tags, &into, true).await?.unwrap_or_default();
+    if retagged.is_empty() {
+        info!("No task has any of the tags {:?}", tags);
+        return Ok(HttpResponse::NotFound().json(serde_json::json!({
+            "error": "No task has any of the tags to merge"
+        })));
+    }
+    info!("Merged tags {:?} into {} on {} tasks", tags, into, retagged.len());
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "tag": into, "tasks": retagged.len() })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, App};
+    use std::sync::Arc;
+
+    use crate::storage::InMemoryTaskRepository;
+
+    fn storage() -> TaskStorage {
+        web::Data::from(Arc::new(InMemoryTaskRepository::new()) as Arc<dyn TaskRepository>)
+    }
+
+    async fn create(storage: &TaskStorage, title: &str, tags: &[&str]) -> Task {
+        let task = Task::new(TaskCreate {
+            title: title.to_string(),
+            description: String::new(),
+            priority: Default::default(),
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            due_at: None,
+        });
+        storage.create(task).await.unwrap()
+    }
+
+    async fn tag_names(storage: &TaskStorage) -> Vec<String> {
+        storage.tags().await.unwrap().into_iter().map(|count| count.tag).collect()
+    }
+
+    #[actix_web::test]
+    async fn renames_a_tag_unless_the_new_name_is_taken() {
+        let storage = storage();
+        create(&storage, "Build image", &["docker"]).await;
+        create(&storage, "Write crate", &["rust"]).await;
+        let app = test::init_service(
+            App::new()
+                .app_data(storage.clone())
+                .route("/tags/{tag}/rename", web::post().to(rename_tag)),
+        )
+        .await;
+        let rename = |from: &str, to: &str| {
+            test::TestRequest::post()
+                .uri(&format!("/tags/{}/rename", from))
+                .set_json(serde_json::json!({ "name": to }))
+                .to_request()
+        };
+
+        let response = test::call_service(&app, rename("docker", "Rust")).await;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert_eq!(tag_names(&storage).await, ["docker", "rust"]);
+
+        // Renaming a tag to itself is allowed and changes nothing
+        let response = test::call_service(&app, rename("Docker", "docker")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body, serde_json::json!({ "tag": "docker", "tasks": 0 }));
+        let response = test::call_service(&app, rename("podman", "podman")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let response = test::call_service(&app, rename("docker", "containers")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body, serde_json::json!({ "tag": "containers", "tasks": 1 }));
+        assert_eq!(tag_names(&storage).await, ["containers", "rust"]);
+    }
+
+    #[actix_web::test]
+    async fn merges_more_tags_than_a_task_can_have() {
+        let storage = storage();
+        let tags: Vec<String> = (0..30).map(|n| format!("tag-{:02}", n)).collect();
+        for tag in &tags {
+            create(&storage, tag, &[tag]).await;
+        }
+        let app = test::init_service(
+            App::new().app_data(storage.clone()).route("/tags/merge", web::post().to(merge_tags)),
+        )
+        .await;
+        let merge = |tags: &[String]| {
+            test::TestRequest::post()
+                .uri("/tags/merge")
+                .set_json(serde_json::json!({ "tags": tags, "into": "merged" }))
+                .to_request()
+        };
+
+        let too_many: Vec<String> = (0..101).map(|n| format!("tag-{}", n)).collect();
+        let response = test::call_service(&app, merge(&too_many)).await;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = test::read_body(response).await;
+        let body = String::from_utf8_lossy(&body);
+        assert!(body.contains("at most 100 tags can be merged at once"), "{}", body);
+
+        let response = test::call_service(&app, merge(&tags)).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!(body, serde_json::json!({ "tag": "merged", "tasks": 30 }));
+        assert_eq!(tag_names(&storage).await, ["merged"]);
+
+        let response = test::call_service(&app, merge(&tags)).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn patches_by_content_type() {
+        let storage = storage();
+        let task = create(&storage, "Ship", &[]).await;
+        let config = web::Data::new(PreconditionConfig { require_if_match: false });
+        let app = test::init_service(
+            App::new()
+                .app_data(storage.clone())
+                .app_data(config)
+                .route("/tasks/{id}", web::patch().to(patch_task)),
+        )
+        .await;
+        let patch = |content_type: Option<&str>, body: &str| {
+            let mut request = test::TestRequest::patch()
+                .uri(&format!("/tasks/{}", task.id))
+                .set_payload(body.to_string());
+            if let Some(content_type) = content_type {
+                request = request.insert_header(("Content-Type", content_type));
+            }
+            request.to_request()
+        };
+
+        let merge = patch(
+            Some("application/merge-patch+json; charset=utf-8"),
+            r#"{"completed": true}"#,
+        );
+        let response = test::call_service(&app, merge).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get("ETag").unwrap(), "\"2\"");
+
+        let json = patch(
+            Some("application/json-patch+json"),
+            r#"[{"op": "replace", "path": "/title", "value": "Shipped"}]"#,
+        );
+        let response = test::call_service(&app, json).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body: serde_json::Value = test::read_body_json(response).await;
+        assert_eq!((&body["title"], &body["completed"]), (&"Shipped".into(), &true.into()));
+
+        for content_type in [Some("application/json"), Some("text/plain"), None] {
+            let response = test::call_service(&app, patch(content_type, "{}")).await;
+            let status = response.status();
+            assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE, "{:?}", content_type);
+            assert!(response.headers().contains_key("Accept-Patch"));
+        }
+        assert_eq!(storage.get(&task.id).await.unwrap().unwrap().version, 3);
+    }
+
+    #[actix_web::test]
+    async fn honours_conditional_requests() {
+        let storage = storage();
+        let task = create(&storage, "Ship", &[]).await;
+        let service = |require_if_match| {
+            test::init_service(
+                App::new()
+                    .app_data(storage.clone())
+                    .app_data(web::Data::new(PreconditionConfig { require_if_match }))
+                    .route("/tasks/{id}", web::get().to(get_task))
+                    .route("/tasks/{id}", web::put().to(update_task))
+                    .route("/tasks/{id}", web::delete().to(delete_task)),
+            )
+        };
+        let app = service(false).await;
+        let uri = format!("/tasks/{}", task.id);
+        let get = |etag: &str| {
+            test::TestRequest::get().uri(&uri).insert_header(("If-None-Match", etag)).to_request()
+        };
+        let put = |etag: &str, title: &str| {
+            test::TestRequest::put()
+                .uri(&uri)
+                .insert_header(("If-Match", etag))
+                .set_json(serde_json::json!({ "title": title }))
+                .to_request()
+        };
+
+        let response = test::call_service(&app, get("\"1\"")).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers().get("ETag").unwrap(), "\"1\"");
+        assert!(test::read_body(response).await.is_empty());
+
+        let response = test::call_service(&app, put("\"1\"", "Shipped")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers().get("ETag").unwrap(), "\"2\"");
+
+        // The first writer won; a second one holding the old ETag must not
+        // overwrite its change
+        let response = test::call_service(&app, put("\"1\"", "Lost update")).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        let current = storage.get(&task.id).await.unwrap().unwrap();
+        assert_eq!((current.title.as_str(), current.version), ("Shipped", 2));
+
+        let response = test::call_service(&app, get("\"1\"")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let delete = test::TestRequest::delete()
+            .uri(&uri)
+            .insert_header(("If-Match", "\"1\""))
+            .to_request();
+        let response = test::call_service(&app, delete).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+        assert!(!storage.get(&task.id).await.unwrap().unwrap().is_deleted());
+
+        let strict = service(true).await;
+        let delete = test::TestRequest::delete().uri(&uri).to_request();
+        let response = test::call_service(&strict, delete).await;
+        assert_eq!(response.status(), StatusCode::PRECONDITION_REQUIRED);
+        let delete = test::TestRequest::delete()
+            .uri(&uri)
+            .insert_header(("If-Match", "\"2\""))
+            .to_request();
+        let response = test::call_service(&strict, delete).await;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+    }
+
+    #[actix_web::test]
+    async fn replays_creations_with_the_same_idempotency_key() {
+        let storage = storage();
+        let app = test::init_service(
+            App::new()
+                .app_data(storage.clone())
+                .app_data(web::Data::new(IdempotencyStore::new(std::time::Duration::from_secs(60))))
+                .route("/tasks", web::post().to(create_task)),
+        )
+        .await;
+        let create = |key: Option<&str>, title: &str| {
+            let mut request = test::TestRequest::post()
+                .uri("/tasks")
+                .set_json(serde_json::json!({ "title": title, "description": "" }));
+            if let Some(key) = key {
+                request = request.insert_header(("Idempotency-Key", key));
+            }
+            request.to_request()
+        };
+
+        let response = test::call_service(&app, create(Some("retry-1"), "Ship")).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert!(!response.headers().contains_key("Idempotent-Replayed"));
+        let created: Task = test::read_body_json(response).await;
+
+        let response = test::call_service(&app, create(Some("retry-1"), "Ship")).await;
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers().get("Idempotent-Replayed").unwrap(), "true");
+        assert_eq!(response.headers().get("ETag").unwrap(), "\"1\"");
+        let replayed: Task = test::read_body_json(response).await;
+        assert_eq!(replayed.id, created.id);
+
+        let response = test::call_service(&app, create(Some("retry-1"), "Ship it")).await;
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(storage.list().await.unwrap().len(), 1);
+
+        // Without a key every request creates a task
+        for _ in 0..2 {
+            let response = test::call_service(&app, create(None, "Ship")).await;
+            assert_eq!(response.status(), StatusCode::CREATED);
+        }
+        assert_eq!(storage.list().await.unwrap().len(), 3);
+    }
+}
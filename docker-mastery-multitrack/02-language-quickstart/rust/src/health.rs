@@ -0,0 +1,481 @@
+/*!
+ * Health checks
+ *
+ * `/health` runs every registered dependency probe with a timeout and reports
+ * per-component status and latency. Any critical component being down turns
+ * the response into a 503 so the Docker HEALTHCHECK reflects reality.
+ *
+ * Orchestrator probes:
+ * - `/health/live`: the process is not wedged (restart if failing)
+ * - `/health/ready`: started, not draining and critical dependencies up
+ * - `/health/startup`: startup recovery and migrations have finished
+ */
+
+use actix_web::{web, HttpResponse, Result};
+use async_trait::async_trait;
+use chrono::Utc;
+use futures_util::future::join_all;
+use log::warn;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::handlers::TaskStorage;
+use crate::lifecycle::{Lifecycle, Phase};
+use crate::storage::TaskRepository;
+
+// A dependency the service needs in order to do useful work
+#[async_trait]
+pub trait HealthProbe: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    // Critical components make the whole service unhealthy when down
+    fn critical(&self) -> bool {
+        true
+    }
+
+    async fn check(&self) -> Result<(), String>;
+}
+
+// Probes the configured task storage backend
+pub struct StorageProbe {
+    repository: Arc<dyn TaskRepository>,
+}
+
+impl StorageProbe {
+    pub fn new(repository: Arc<dyn TaskRepository>) -> Self {
+        Self { repository }
+    }
+}
+
+#[async_trait]
+impl HealthProbe for StorageProbe {
+    fn name(&self) -> &'static str {
+        "database"
+    }
+
+    async fn check(&self) -> Result<(), String> {
+        self.repository.ping().await.map_err(|err| err.to_string())
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ComponentStatus {
+    Up,
+    Down,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ComponentHealth {
+    pub status: ComponentStatus,
+    pub critical: bool,
+    pub latency_ms: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+pub struct HealthRegistry {
+    probes: Vec<Arc<dyn HealthProbe>>,
+    timeout: Duration,
+    liveness_deadline: Duration,
+}
+
+fn millis_from_env(name: &str, default: u64) -> Duration {
+    let millis = std::env::var(name)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default);
+
+    Duration::from_millis(millis)
+}
+
+impl HealthRegistry {
+    pub fn new(timeout: Duration, liveness_deadline: Duration) -> Self {
+        Self {
+            probes: Vec::new(),
+            timeout,
+            liveness_deadline,
+        }
+    }
+
+    // Timeout per probe from HEALTH_CHECK_TIMEOUT_MS (default 2s, below the
+    // 3s Dockerfile HEALTHCHECK timeout) and LIVENESS_DEADLINE_MS (default 1s)
+    pub fn from_env() -> Self {
+        Self::new(
+            millis_from_env("HEALTH_CHECK_TIMEOUT_MS", 2000),
+            millis_from_env("LIVENESS_DEADLINE_MS", 1000),
+        )
+    }
+
+    pub fn register(mut self, probe: Arc<dyn HealthProbe>) -> Self {
+        self.probes.push(probe);
+        self
+    }
+
+    // Run every probe concurrently, each bounded by the timeout
+    pub async fn check_all(&self) -> Vec<(&'static str, ComponentHealth)> {
+        let checks = self.probes.iter().map(|probe| async move {
+            let started = Instant::now();
+            let result = match tokio::time::timeout(self.timeout, probe.check()).await {
+                Ok(result) => result,
+                Err(_) => Err(format!("timed out after {}ms", self.timeout.as_millis())),
+            };
+            let latency_ms = (started.elapsed().as_secs_f64() * 1_000_000.0).round() / 1000.0;
+
+            if let Err(err) = &result {
+                warn!("Health probe '{}' failed: {}", probe.name(), err);
+            }
+
+            let health = ComponentHealth {
+                status: if result.is_ok() {
+                    ComponentStatus::Up
+                } else {
+                    ComponentStatus::Down
+                },
+                critical: probe.critical(),
+                latency_ms,
+                error: result.err(),
+            };
+            (probe.name(), health)
+        });
+
+        join_all(checks).await
+    }
+}
+
+fn any_down(components: &[(&'static str, ComponentHealth)], critical: bool) -> bool {
+    components
+        .iter()
+        .any(|(_, c)| c.critical == critical && matches!(c.status, ComponentStatus::Down))
+}
+
+// Health check endpoint
+pub async fn health_check(
+    registry: web::Data<HealthRegistry>,
+    lifecycle: web::Data<Lifecycle>,
+) -> Result<HttpResponse> {
+    let components = registry.check_all().await;
+
+    let is_down = |critical: bool| any_down(&components, critical);
+    let status = if is_down(true) {
+        "unhealthy"
+    } else if is_down(false) {
+        "degraded"
+    } else {
+        "healthy"
+    };
+
+    let database = match components.iter().find(|(name, _)| *name == "database") {
+        Some((_, c)) if matches!(c.status, ComponentStatus::Up) => "connected",
+        _ => "disconnected",
+    };
+
+    let health_response = serde_json::json!({
+        "status": status,
+        "phase": lifecycle.phase(),
+        "version": "1.0.0",
+        "timestamp": Utc::now().to_rfc3339(),
+        "environment": std::env::var("ENV").unwrap_or_else(|_| "production".to_string()),
+        "database": database,
+        "components": components.into_iter().collect::<BTreeMap<_, _>>()
+    });
+
+    if status == "unhealthy" {
+        Ok(HttpResponse::ServiceUnavailable().json(health_response))
+    } else {
+        Ok(HttpResponse::Ok().json(health_response))
+    }
+}
+
+// Liveness probe: only fails when the process is wedged
+pub async fn liveness(
+    registry: web::Data<HealthRegistry>,
+    data: TaskStorage,
+) -> Result<HttpResponse> {
+    match data.liveness(registry.liveness_deadline).await {
+        Ok(()) => Ok(HttpResponse::Ok().json(serde_json::json!({ "status": "alive" }))),
+        Err(err) => {
+            warn!("Liveness check failed: {}", err);
+            Ok(HttpResponse::ServiceUnavailable().json(serde_json::json!({
+                "status": "wedged",
+                "error": err.to_string()
+            })))
+        }
+    }
+}
+
+// Readiness probe: fails while starting, while draining and while a critical
+// dependency is down
+pub async fn readiness(
+    registry: web::Data<HealthRegistry>,
+    lifecycle: web::Data<Lifecycle>,
+) -> Result<HttpResponse> {
+    let phase = lifecycle.phase();
+    if phase != Phase::Ready {
+        return Ok(HttpResponse::ServiceUnavailable().json(serde_json::json!({
+            "status": "not_ready",
+            "phase": phase
+        })));
+    }
+
+    let components = registry.check_all().await;
+    if any_down(&components, true) {
+        let down: BTreeMap<_, _> = components
+            .into_iter()
+            .filter(|(_, c)| matches!(c.status, ComponentStatus::Down))
+            .collect();
+
+        return Ok(HttpResponse::ServiceUnavailable().json(serde_json::json!({
+            "status": "not_ready",
+            "phase": phase,
+            "components": down
+        })));
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "status": "ready",
+        "phase": phase
+    })))
+}
+
+// Startup probe: succeeds once startup recovery and migrations are done
+pub async fn startup(lifecycle: web::Data<Lifecycle>) -> Result<HttpResponse> {
+    let phase = lifecycle.phase();
+    let body = serde_json::json!({
+        "status": if phase == Phase::Starting { "starting" } else { "started" },
+        "phase": phase
+    });
+
+    if phase == Phase::Starting {
+        Ok(HttpResponse::ServiceUnavailable().json(body))
+    } else {
+        Ok(HttpResponse::Ok().json(body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{http::StatusCode, test, App};
+
+    use crate::storage::{DeferredRepository, InMemoryTaskRepository};
+
+    // A probe that answers after `delay`, failing with `error` if set
+    struct StubProbe {
+        name: &'static str,
+        critical: bool,
+        delay: Duration,
+        error: Option<&'static str>,
+    }
+
+    #[async_trait]
+    impl HealthProbe for StubProbe {
+        fn name(&self) -> &'static str {
+            self.name
+        }
+
+        fn critical(&self) -> bool {
+            self.critical
+        }
+
+        async fn check(&self) -> Result<(), String> {
+            tokio::time::sleep(self.delay).await;
+            self.error.map_or(Ok(()), |error| Err(error.to_string()))
+        }
+    }
+
+    fn probe(name: &'static str, critical: bool, error: Option<&'static str>) -> Arc<StubProbe> {
+        Arc::new(StubProbe {
+            name,
+            critical,
+            delay: Duration::ZERO,
+            error,
+        })
+    }
+
+    fn registry() -> HealthRegistry {
+        HealthRegistry::new(Duration::from_millis(50), Duration::from_millis(50))
+    }
+
+    // GET /health against `registry`
+    async fn health(registry: HealthRegistry) -> (StatusCode, serde_json::Value) {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(registry))
+                .app_data(web::Data::new(Lifecycle::new()))
+                .route("/health", web::get().to(health_check)),
+        )
+        .await;
+        let response =
+            test::call_service(&app, test::TestRequest::get().uri("/health").to_request()).await;
+        (response.status(), test::read_body_json(response).await)
+    }
+
+    #[actix_web::test]
+    async fn is_healthy_when_every_probe_is_up() {
+        let registry = registry()
+            .register(probe("database", true, None))
+            .register(probe("cache", false, None));
+        let (status, body) = health(registry).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "healthy");
+        assert_eq!(body["database"], "connected");
+        assert_eq!(body["phase"], "starting");
+        assert_eq!(body["components"]["cache"]["status"], "up");
+        assert!(body["components"]["database"].get("error").is_none());
+    }
+
+    #[actix_web::test]
+    async fn is_unhealthy_when_a_critical_probe_fails() {
+        let registry = registry()
+            .register(probe("database", true, Some("connection refused")))
+            .register(probe("cache", false, None));
+        let (status, body) = health(registry).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "unhealthy");
+        assert_eq!(body["database"], "disconnected");
+        let database = &body["components"]["database"];
+        assert_eq!(database["status"], "down");
+        assert_eq!(database["critical"], true);
+        assert_eq!(database["error"], "connection refused");
+    }
+
+    #[actix_web::test]
+    async fn is_degraded_when_only_a_non_critical_probe_fails() {
+        let registry = registry()
+            .register(probe("database", true, None))
+            .register(probe("cache", false, Some("evicted")));
+        let (status, body) = health(registry).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "degraded");
+        assert_eq!(body["database"], "connected");
+        assert_eq!(body["components"]["cache"]["status"], "down");
+    }
+
+    #[actix_web::test]
+    async fn fails_probes_that_outlast_the_timeout() {
+        let slow = Arc::new(StubProbe {
+            name: "database",
+            critical: true,
+            delay: Duration::from_secs(10),
+            error: None,
+        });
+        let started = Instant::now();
+        let components = registry().register(slow).register(probe("cache", false, None));
+        let components = components.check_all().await;
+        assert!(started.elapsed() < Duration::from_secs(1));
+
+        let (name, database) = &components[0];
+        assert_eq!(*name, "database");
+        assert!(matches!(database.status, ComponentStatus::Down));
+        assert_eq!(database.error.as_deref(), Some("timed out after 50ms"));
+        assert!(database.latency_ms >= 50.0);
+        assert!(matches!(components[1].1.status, ComponentStatus::Up));
+    }
+
+    #[actix_web::test]
+    async fn reports_the_database_disconnected_without_a_probe_for_it() {
+        let (status, body) = health(registry().register(probe("cache", false, None))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["database"], "disconnected");
+    }
+
+    #[actix_web::test]
+    async fn probes_storage_that_is_still_starting_as_down() {
+        let storage = Arc::new(DeferredRepository::new());
+        let registry = registry().register(Arc::new(StorageProbe::new(storage)));
+        let (status, body) = health(registry).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["database"], "disconnected");
+    }
+
+    // Status and body of GET `uri` for the probe endpoints
+    async fn probe_endpoint(
+        uri: &str,
+        registry: HealthRegistry,
+        lifecycle: web::Data<Lifecycle>,
+        storage: Arc<dyn TaskRepository>,
+    ) -> (StatusCode, serde_json::Value) {
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(registry))
+                .app_data(lifecycle)
+                .app_data(TaskStorage::from(storage))
+                .route("/health/live", web::get().to(liveness))
+                .route("/health/ready", web::get().to(readiness))
+                .route("/health/startup", web::get().to(startup)),
+        )
+        .await;
+        let request = test::TestRequest::get().uri(uri).to_request();
+        let response = test::call_service(&app, request).await;
+        (response.status(), test::read_body_json(response).await)
+    }
+
+    #[actix_web::test]
+    async fn is_ready_only_between_startup_and_draining() {
+        let lifecycle = web::Data::new(Lifecycle::new());
+        let storage: Arc<dyn TaskRepository> = Arc::new(InMemoryTaskRepository::new());
+        let check = |uri: &'static str| {
+            let registry = registry().register(probe("database", true, None));
+            probe_endpoint(uri, registry, lifecycle.clone(), storage.clone())
+        };
+
+        let (status, body) = check("/health/ready").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body, serde_json::json!({ "status": "not_ready", "phase": "starting" }));
+        let (status, body) = check("/health/startup").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "starting");
+
+        lifecycle.mark_ready();
+        let (status, body) = check("/health/startup").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, serde_json::json!({ "status": "started", "phase": "ready" }));
+        let (status, body) = check("/health/ready").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["status"], "ready");
+
+        lifecycle.mark_draining();
+        let (status, body) = check("/health/ready").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["phase"], "draining");
+        // Startup stays done while draining, so the orchestrator does not
+        // restart a container that is shutting down
+        let (status, _) = check("/health/startup").await;
+        assert_eq!(status, StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn is_not_ready_while_a_critical_probe_fails() {
+        let lifecycle = web::Data::new(Lifecycle::new());
+        lifecycle.mark_ready();
+        let storage: Arc<dyn TaskRepository> = Arc::new(InMemoryTaskRepository::new());
+        let registry = registry()
+            .register(probe("database", true, Some("connection refused")))
+            .register(probe("cache", false, Some("evicted")));
+
+        let (status, body) = probe_endpoint("/health/ready", registry, lifecycle, storage).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["components"]["database"]["error"], "connection refused");
+        assert_eq!(body["components"]["cache"]["status"], "down");
+    }
+
+    #[actix_web::test]
+    async fn is_alive_while_storage_is_still_starting() {
+        let lifecycle = web::Data::new(Lifecycle::new());
+        let storage = Arc::new(DeferredRepository::new());
+        let live = |storage: Arc<DeferredRepository>| {
+            probe_endpoint("/health/live", registry(), lifecycle.clone(), storage)
+        };
+
+        let (status, body) = live(storage.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, serde_json::json!({ "status": "alive" }));
+
+        storage.set(Arc::new(InMemoryTaskRepository::new()));
+        let (status, _) = live(storage).await;
+        assert_eq!(status, StatusCode::OK);
+    }
+}
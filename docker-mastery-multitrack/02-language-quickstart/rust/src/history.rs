@@ -0,0 +1,399 @@
+/*!
+ * Revision history of tasks
+ *
+ * Every write to a task is recorded by the storage backend, together with
+ * the write itself, as an immutable revision: what was done, by whom, when,
+ * and the task as it was afterwards. A revision is numbered by the version
+ * it gave the task, so revision N is the task whose ETag was "N". Purging a
+ * task from the trash drops its history along with it. The fields a
+ * revision changed are worked out against the revision before it.
+ *
+ * The actor is whoever a request names in the X-Actor header, `anonymous` if
+ * it names no one, and `system` for writes made outside a request, like the
+ * automatic trash purge.
+ */
+
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::HeaderName;
+use actix_web::{http::StatusCode, Error, HttpResponse, ResponseError};
+use chrono::{DateTime, Utc};
+use futures_util::future::{ready, LocalBoxFuture, Ready};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::str::FromStr;
+
+use crate::models::{timestamp, Task};
+use crate::projection::TaskField;
+
+pub const ACTOR_HEADER: HeaderName = HeaderName::from_static("x-actor");
+const ANONYMOUS_ACTOR: &str = "anonymous";
+const SYSTEM_ACTOR: &str = "system";
+const MAX_ACTOR_LENGTH: usize = 255;
+
+// The fields whose changes a revision reports
+const TRACKED_FIELDS: [TaskField; 7] = [
+    TaskField::Title,
+    TaskField::Description,
+    TaskField::Completed,
+    TaskField::Priority,
+    TaskField::Tags,
+    TaskField::DueAt,
+    TaskField::DeletedAt,
+];
+
+tokio::task_local! {
+    static ACTOR: String;
+}
+
+// Who is making the current write
+pub fn current_actor() -> String {
+    ACTOR
+        .try_with(Clone::clone)
+        .unwrap_or_else(|_| SYSTEM_ACTOR.to_string())
+}
+
+// Run `future` with its writes attributed to `actor`
+pub async fn with_actor<F: Future>(actor: String, future: F) -> F::Output {
+    ACTOR.scope(actor, future).await
+}
+
+#[derive(Debug)]
+pub struct InvalidActor(String);
+
+impl fmt::Display for InvalidActor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid X-Actor: {}", self.0)
+    }
+}
+
+impl std::error::Error for InvalidActor {}
+
+impl ResponseError for InvalidActor {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(serde_json::json!({
+            "error": self.to_string()
+        }))
+    }
+}
+
+fn actor_of(req: &ServiceRequest) -> Result<String, InvalidActor> {
+    let Some(value) = req.headers().get(ACTOR_HEADER) else {
+        return Ok(ANONYMOUS_ACTOR.to_string());
+    };
+    let actor = value
+        .to_str()
+        .map_err(|_| InvalidActor("must be visible ASCII".to_string()))?
+        .trim();
+    if actor.is_empty() || actor.len() > MAX_ACTOR_LENGTH {
+        return Err(InvalidActor(format!(
+            "must be between 1 and {} characters",
+            MAX_ACTOR_LENGTH
+        )));
+    }
+    Ok(actor.to_string())
+}
+
+// Attributes every write a request makes to its X-Actor
+pub struct RecordActor;
+
+impl<S, B> Transform<S, ServiceRequest> for RecordActor
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = RecordActorMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RecordActorMiddleware { service }))
+    }
+}
+
+pub struct RecordActorMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service<ServiceRequest> for RecordActorMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        match actor_of(&req) {
+            Ok(actor) => Box::pin(with_actor(actor, self.service.call(req))),
+            Err(err) => Box::pin(ready(Err(err.into()))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Created,
+    Updated,
+    Deleted,
+    Restored,
+    // Only in logs, snapshots and tables written before purging a task
+    // dropped its history; recording one drops the history instead
+    Purged,
+}
+
+impl Action {
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Created => "created",
+            Action::Updated => "updated",
+            Action::Deleted => "deleted",
+            Action::Restored => "restored",
+            Action::Purged => "purged",
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        [
+            Action::Created,
+            Action::Updated,
+            Action::Deleted,
+            Action::Restored,
+            Action::Purged,
+        ]
+        .into_iter()
+        .find(|action| action.name() == value)
+        .ok_or_else(|| format!("unknown revision action '{}'", value))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Revision {
+    pub revision: i64,
+    pub action: Action,
+    pub actor: String,
+    pub changed_at: DateTime<Utc>,
+    // The task as this revision left it
+    pub task: Task,
+}
+
+impl Revision {
+    // A revision by the current actor. `task` is the task after the write.
+    pub fn new(action: Action, task: Task) -> Self {
+        Revision {
+            revision: task.version,
+            action,
+            actor: current_actor(),
+            changed_at: timestamp(),
+            task,
+        }
+    }
+}
+
+// Revisions of every task, by task id, oldest first
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct History(HashMap<String, Vec<Revision>>);
+
+impl History {
+    // Recording a revision twice keeps one, so replaying a log is harmless
+    pub fn record(&mut self, revision: Revision) {
+        if revision.action == Action::Purged {
+            self.forget(&revision.task.id);
+            return;
+        }
+        let revisions = self.0.entry(revision.task.id.clone()).or_default();
+        if revisions.last().is_some_and(|last| last.revision >= revision.revision) {
+            return;
+        }
+        revisions.push(revision);
+    }
+
+    pub fn of(&self, id: &str) -> Vec<Revision> {
+        self.0.get(id).cloned().unwrap_or_default()
+    }
+
+    // Drop the revisions of a purged task
+    pub fn forget(&mut self, id: &str) {
+        self.0.remove(id);
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FieldChange {
+    pub field: &'static str,
+    pub from: Value,
+    pub to: Value,
+}
+
+// A revision as listed in a task's history
+#[derive(Debug, Serialize)]
+pub struct HistoryEntry {
+    pub revision: i64,
+    pub action: Action,
+    pub actor: String,
+    pub changed_at: DateTime<Utc>,
+    pub changes: Vec<FieldChange>,
+}
+
+fn diff(before: Option<&Task>, after: &Task) -> Vec<FieldChange> {
+    TRACKED_FIELDS
+        .into_iter()
+        .filter_map(|field| {
+            let from = before.map_or(Value::Null, |task| field.value(task));
+            let to = field.value(after);
+            (from != to).then(|| FieldChange {
+                field: field.name(),
+                from,
+                to,
+            })
+        })
+        .collect()
+}
+
+// History entries for a task's revisions, oldest first
+pub fn entries(revisions: &[Revision]) -> Vec<HistoryEntry> {
+    let mut previous: Option<&Revision> = None;
+    revisions
+        .iter()
+        .map(|revision| {
+            let changes = match (previous, revision.action) {
+                (_, Action::Created) => diff(None, &revision.task),
+                (Some(previous), _) => diff(Some(&previous.task), &revision.task),
+                // The task was written before history was kept, so what
+                // this revision changed is unknown
+                (None, _) => Vec::new(),
+            };
+            previous = Some(revision);
+            HistoryEntry {
+                revision: revision.revision,
+                action: revision.action,
+                actor: revision.actor.clone(),
+                changed_at: revision.changed_at,
+                changes,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{TaskCreate, TaskUpdate};
+    use crate::storage::{InMemoryTaskRepository, TaskRepository};
+
+    fn task(title: &str) -> Task {
+        Task::new(TaskCreate {
+            title: title.to_string(),
+            description: String::new(),
+            priority: Default::default(),
+            tags: Vec::new(),
+            due_at: None,
+        })
+    }
+
+    fn changed_fields(entry: &HistoryEntry) -> Vec<&str> {
+        entry.changes.iter().map(|change| change.field).collect()
+    }
+
+    #[test]
+    fn diffs_each_revision_against_the_one_before() {
+        let created = task("Draft");
+        let mut updated = created.clone();
+        TaskUpdate {
+            title: Some("Final".to_string()),
+            completed: Some(true),
+            ..Default::default()
+        }
+        .apply(&mut updated);
+
+        let revisions = [
+            Revision::new(Action::Created, created),
+            Revision::new(Action::Updated, updated),
+        ];
+        let entries = entries(&revisions);
+
+        assert_eq!(
+            changed_fields(&entries[0]),
+            ["title", "description", "completed", "priority", "tags"]
+        );
+        assert_eq!(changed_fields(&entries[1]), ["title", "completed"]);
+        let title = &entries[1].changes[0];
+        assert_eq!((&title.from, &title.to), (&Value::from("Draft"), &Value::from("Final")));
+        assert_eq!(entries[1].revision, 2);
+    }
+
+    #[test]
+    fn reports_no_changes_without_an_earlier_revision() {
+        let mut task = task("Written before history was kept");
+        task.version = 4;
+        let entries = entries(&[Revision::new(Action::Updated, task)]);
+        assert!(entries[0].changes.is_empty());
+    }
+
+    #[test]
+    fn records_each_revision_once() {
+        let task = task("Replayed");
+        let mut history = History::default();
+        let revision = Revision::new(Action::Created, task.clone());
+        history.record(revision.clone());
+        history.record(revision);
+        assert_eq!(history.of(&task.id).len(), 1);
+
+        // A purge revision from an older log drops the history
+        history.record(Revision {
+            revision: 2,
+            action: Action::Purged,
+            actor: SYSTEM_ACTOR.to_string(),
+            changed_at: timestamp(),
+            task: task.clone(),
+        });
+        assert!(history.of(&task.id).is_empty());
+    }
+
+    #[actix_web::test]
+    async fn fetches_a_task_as_a_revision_left_it() {
+        let storage = InMemoryTaskRepository::new();
+        let task = storage.create(task("Draft")).await.unwrap();
+        let changes = TaskUpdate {
+            title: Some("Final".to_string()),
+            ..Default::default()
+        };
+        with_actor("alice".to_string(), storage.update(&task.id, changes)).await.unwrap();
+
+        let first = storage.revision(&task.id, 1).await.unwrap().unwrap();
+        assert_eq!(first.task.title, "Draft");
+        assert_eq!(first.actor, SYSTEM_ACTOR);
+        let second = storage.revision(&task.id, 2).await.unwrap().unwrap();
+        assert_eq!(second.task.title, "Final");
+        assert_eq!(second.actor, "alice");
+        assert!(storage.revision(&task.id, 3).await.unwrap().is_none());
+    }
+
+    #[actix_web::test]
+    async fn drops_the_history_of_a_purged_task() {
+        let storage = InMemoryTaskRepository::new();
+        let task = storage.create(task("Temporary")).await.unwrap();
+        storage.delete(&task.id).await.unwrap();
+        assert_eq!(storage.history(&task.id).await.unwrap().len(), 2);
+
+        storage.purge(&task.id).await.unwrap().unwrap();
+        assert!(storage.history(&task.id).await.unwrap().is_empty());
+    }
+}
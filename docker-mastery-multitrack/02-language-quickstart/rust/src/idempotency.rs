@@ -0,0 +1,315 @@
+/*!
+ * Idempotency-Key support for task creation
+ *
+ * A POST carrying an `Idempotency-Key` is recorded with a fingerprint of its
+ * body and, once it succeeds, the task it created. A retry with the same key
+ * and body gets the original 201 replayed instead of creating a duplicate;
+ * the same key with a different body is a 422. Keys expire after
+ * IDEMPOTENCY_TTL_SECONDS (default one day).
+ *
+ * Keys live in this process only, like the search index: they do not survive
+ * a restart and are not shared between replicas.
+ */
+
+use actix_web::http::header::HeaderName;
+use actix_web::{http::StatusCode, HttpRequest, HttpResponse, ResponseError};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::models::Task;
+
+pub const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+// Set on responses replayed from an earlier request
+pub const IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+const MAX_KEY_LENGTH: usize = 255;
+const DEFAULT_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug)]
+pub enum IdempotencyError {
+    InvalidKey(String),
+    // The key was already used for a request with a different body
+    Mismatch,
+    // The first request with this key has not finished yet
+    InProgress,
+}
+
+impl fmt::Display for IdempotencyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IdempotencyError::InvalidKey(msg) => write!(f, "invalid Idempotency-Key: {}", msg),
+            IdempotencyError::Mismatch => {
+                write!(f, "Idempotency-Key was already used with a different request body")
+            }
+            IdempotencyError::InProgress => {
+                write!(f, "a request with this Idempotency-Key is still in progress")
+            }
+        }
+    }
+}
+
+impl std::error::Error for IdempotencyError {}
+
+impl ResponseError for IdempotencyError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            IdempotencyError::InvalidKey(_) => StatusCode::BAD_REQUEST,
+            IdempotencyError::Mismatch => StatusCode::UNPROCESSABLE_ENTITY,
+            IdempotencyError::InProgress => StatusCode::CONFLICT,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(serde_json::json!({
+            "error": self.to_string()
+        }))
+    }
+}
+
+// The request's Idempotency-Key, if it sent one
+pub fn key(req: &HttpRequest) -> Result<Option<String>, IdempotencyError> {
+    let Some(value) = req.headers().get(IDEMPOTENCY_KEY) else {
+        return Ok(None);
+    };
+
+    let key = value
+        .to_str()
+        .map_err(|_| IdempotencyError::InvalidKey("must be visible ASCII".to_string()))?
+        .trim();
+    if key.is_empty() {
+        return Err(IdempotencyError::InvalidKey("must not be empty".to_string()));
+    }
+    if key.len() > MAX_KEY_LENGTH {
+        return Err(IdempotencyError::InvalidKey(format!(
+            "must be at most {} characters",
+            MAX_KEY_LENGTH
+        )));
+    }
+    Ok(Some(key.to_string()))
+}
+
+// SHA-256 of the request body as deserialized, so formatting and key order
+// do not matter
+pub fn fingerprint(body: &impl Serialize) -> String {
+    let json = serde_json::to_vec(body).expect("request bodies serialize to JSON");
+    Sha256::digest(json).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+struct Entry {
+    fingerprint: String,
+    // `None` while the first request is still running
+    task: Option<Task>,
+    expires_at: Instant,
+}
+
+#[derive(Default)]
+struct Entries {
+    by_key: HashMap<String, Entry>,
+    // Keys in the order they expire, since every key gets the same TTL
+    expiry: VecDeque<(Instant, String)>,
+}
+
+impl Entries {
+    fn purge_expired(&mut self, now: Instant) {
+        while let Some((expires_at, _)) = self.expiry.front() {
+            if *expires_at > now {
+                break;
+            }
+            let (expires_at, key) = self.expiry.pop_front().expect("front exists");
+            // The key may have been released and claimed again since
+            if self.by_key.get(&key).is_some_and(|entry| entry.expires_at == expires_at) {
+                self.by_key.remove(&key);
+            }
+        }
+    }
+}
+
+pub struct IdempotencyStore {
+    ttl: Duration,
+    entries: Mutex<Entries>,
+}
+
+// What to do with a request carrying an Idempotency-Key
+pub enum Claim<'a> {
+    // First use of the key: run the request, then `complete` the claim
+    New(ClaimGuard<'a>),
+    // Answer with the task created by the original request
+    Replay(Task),
+}
+
+impl IdempotencyStore {
+    pub fn new(ttl: Duration) -> Self {
+        IdempotencyStore {
+            ttl,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+
+    pub fn from_env() -> Self {
+        let ttl = std::env::var("IDEMPOTENCY_TTL_SECONDS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .map_or(DEFAULT_TTL, Duration::from_secs);
+
+        Self::new(ttl)
+    }
+
+    // Nothing here can be left inconsistent by a panic
+    fn entries(&self) -> MutexGuard<'_, Entries> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn claim(&self, key: &str, fingerprint: String) -> Result<Claim<'_>, IdempotencyError> {
+        let now = Instant::now();
+        let mut entries = self.entries();
+        entries.purge_expired(now);
+
+        if let Some(entry) = entries.by_key.get(key) {
+            if entry.fingerprint != fingerprint {
+                return Err(IdempotencyError::Mismatch);
+            }
+            return match &entry.task {
+                Some(task) => Ok(Claim::Replay(task.clone())),
+                None => Err(IdempotencyError::InProgress),
+            };
+        }
+
+        let expires_at = now + self.ttl;
+        entries.by_key.insert(
+            key.to_string(),
+            Entry {
+                fingerprint,
+                task: None,
+                expires_at,
+            },
+        );
+        entries.expiry.push_back((expires_at, key.to_string()));
+
+        Ok(Claim::New(ClaimGuard {
+            store: self,
+            key: key.to_string(),
+            completed: false,
+        }))
+    }
+}
+
+// A claimed key. Dropping it without `complete`, e.g. because creation
+// failed, releases the key so the client can retry.
+pub struct ClaimGuard<'a> {
+    store: &'a IdempotencyStore,
+    key: String,
+    completed: bool,
+}
+
+impl ClaimGuard<'_> {
+    pub fn complete(mut self, task: &Task) {
+        if let Some(entry) = self.store.entries().by_key.get_mut(&self.key) {
+            entry.task = Some(task.clone());
+        }
+        self.completed = true;
+    }
+}
+
+impl Drop for ClaimGuard<'_> {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.store.entries().by_key.remove(&self.key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    use crate::models::TaskCreate;
+
+    fn task() -> Task {
+        Task::new(TaskCreate {
+            title: "Ship".to_string(),
+            description: String::new(),
+            priority: Default::default(),
+            tags: Vec::new(),
+            due_at: None,
+        })
+    }
+
+    fn claim_new<'a>(
+        store: &'a IdempotencyStore,
+        key: &str,
+        fingerprint: &str,
+    ) -> ClaimGuard<'a> {
+        match store.claim(key, fingerprint.to_string()) {
+            Ok(Claim::New(guard)) => guard,
+            Ok(Claim::Replay(task)) => panic!("expected a new claim, replayed {}", task.id),
+            Err(err) => panic!("expected a new claim, got {}", err),
+        }
+    }
+
+    #[test]
+    fn replays_a_completed_request_with_the_same_body() {
+        let store = IdempotencyStore::new(DEFAULT_TTL);
+        let task = task();
+        claim_new(&store, "key", "body").complete(&task);
+
+        match store.claim("key", "body".to_string()) {
+            Ok(Claim::Replay(replayed)) => assert_eq!(replayed.id, task.id),
+            _ => panic!("expected a replay"),
+        }
+        let err = store.claim("key", "other body".to_string()).err().unwrap();
+        assert!(matches!(err, IdempotencyError::Mismatch));
+        assert_eq!(err.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        // Keys are independent of each other
+        claim_new(&store, "other key", "body");
+    }
+
+    #[test]
+    fn releases_a_key_whose_request_did_not_complete() {
+        let store = IdempotencyStore::new(DEFAULT_TTL);
+        let guard = claim_new(&store, "key", "body");
+        let err = store.claim("key", "body".to_string()).err().unwrap();
+        assert_eq!(err.status_code(), StatusCode::CONFLICT);
+
+        drop(guard);
+        claim_new(&store, "key", "other body").complete(&task());
+    }
+
+    #[test]
+    fn forgets_keys_once_they_expire() {
+        let store = IdempotencyStore::new(Duration::ZERO);
+        claim_new(&store, "key", "body").complete(&task());
+        claim_new(&store, "key", "other body").complete(&task());
+        assert_eq!(store.entries().by_key.len(), 1);
+        assert_eq!(store.entries().expiry.len(), 1);
+    }
+
+    #[test]
+    fn validates_keys_and_fingerprints_bodies() {
+        let key_of = |value: &str| {
+            key(&TestRequest::default().insert_header((IDEMPOTENCY_KEY, value)).to_http_request())
+        };
+        assert_eq!(key_of(" abc ").unwrap().as_deref(), Some("abc"));
+        assert!(key(&TestRequest::default().to_http_request()).unwrap().is_none());
+        for invalid in ["  ".to_string(), "k".repeat(MAX_KEY_LENGTH + 1)] {
+            let err = key_of(&invalid).unwrap_err();
+            assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+        }
+
+        let body = |title: &str| TaskCreate {
+            title: title.to_string(),
+            description: String::new(),
+            priority: Default::default(),
+            tags: Vec::new(),
+            due_at: None,
+        };
+        assert_eq!(fingerprint(&body("Ship")), fingerprint(&body("Ship")));
+        assert_ne!(fingerprint(&body("Ship")), fingerprint(&body("Ship it")));
+        assert_eq!(fingerprint(&body("Ship")).len(), 64);
+    }
+}
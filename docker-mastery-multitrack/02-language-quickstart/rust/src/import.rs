@@ -0,0 +1,761 @@
+/*!
+ * Bulk task import from JSON, NDJSON, CSV and todo.txt
+ *
+ * Every record is parsed and validated before anything is written. A dry run
+ * stops there and reports the problems; a real import with any invalid
+ * record writes nothing, and otherwise creates all tasks in one atomic batch.
+ *
+ * - JSON: an array of objects with `title`, optional `description`,
+ *   `completed`, `priority`, `tags` and `due_at`. Other fields (say `id`
+ *   from an export) are ignored.
+ * - NDJSON: one such object per line, as written by the export.
+ * - CSV: a header row names the columns. `title`, `description`,
+ *   `completed`, `priority`, `tags` and `due_at` are found under common names
+ *   (`name`, `notes`, `done`, `labels`, `deadline`, ...) unless mapped
+ *   explicitly. Tags are separated by commas or spaces.
+ * - todo.txt: one task per line. `x ` marks it done, a priority `(A)` is
+ *   urgent, `(B)` high, `(C)` normal and anything lower low, `+project` and
+ *   `@context` become tags, and a `due:` tag sets the due date. The other
+ *   dates are validated and dropped; the rest of the line becomes the title.
+ *
+ * Due dates are RFC 3339 timestamps or plain `YYYY-MM-DD` dates, which mean
+ * midnight UTC.
+ */
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::batch::BatchOperation;
+use crate::models::{validate_title, Priority, Task, TaskCreate};
+use crate::storage::{Conditional, StorageError, TaskRepository};
+use crate::tags;
+
+pub const MAX_IMPORT_BYTES: usize = 10 * 1024 * 1024;
+pub const MAX_IMPORT_TASKS: usize = 10_000;
+
+// Header names recognised for each field when no column is given
+const TITLE_COLUMNS: &[&str] = &["title", "name", "task", "summary", "subject", "content"];
+const DESCRIPTION_COLUMNS: &[&str] = &["description", "notes", "note", "details", "body"];
+const COMPLETED_COLUMNS: &[&str] = &["completed", "done", "complete", "status", "checked"];
+const PRIORITY_COLUMNS: &[&str] = &["priority", "prio", "importance"];
+const TAGS_COLUMNS: &[&str] = &["tags", "tag", "labels", "categories"];
+const DUE_COLUMNS: &[&str] = &["due_at", "due", "due_date", "deadline"];
+
+#[derive(Debug)]
+pub enum ImportError {
+    // No format given and none could be told from the request
+    UnknownFormat,
+    // The input as a whole cannot be read, as opposed to a single bad record
+    Unreadable(String),
+    TooLarge,
+    TooManyTasks(usize),
+    Storage(StorageError),
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ImportError::UnknownFormat => write!(
+                f,
+                "unknown import format: pass format=json|ndjson|csv|todotxt or a matching Content-Type"
+            ),
+            ImportError::Unreadable(msg) => write!(f, "cannot read import: {}", msg),
+            ImportError::TooLarge => {
+                write!(f, "an import must be at most {} bytes", MAX_IMPORT_BYTES)
+            }
+            ImportError::TooManyTasks(count) => write!(
+                f,
+                "an import must have at most {} tasks, got {}",
+                MAX_IMPORT_TASKS, count
+            ),
+            ImportError::Storage(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+impl From<StorageError> for ImportError {
+    fn from(err: StorageError) -> Self {
+        ImportError::Storage(err)
+    }
+}
+
+impl ResponseError for ImportError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            ImportError::UnknownFormat => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ImportError::Unreadable(_) | ImportError::TooManyTasks(_) => StatusCode::BAD_REQUEST,
+            ImportError::TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ImportError::Storage(err) => err.status_code(),
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(serde_json::json!({
+            "error": self.to_string()
+        }))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(try_from = "String", rename_all = "lowercase")]
+pub enum ImportFormat {
+    Json,
+    Ndjson,
+    Csv,
+    TodoTxt,
+}
+
+impl FromStr for ImportFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "json" => Ok(ImportFormat::Json),
+            "ndjson" | "jsonl" => Ok(ImportFormat::Ndjson),
+            "csv" => Ok(ImportFormat::Csv),
+            "todotxt" | "todo.txt" | "txt" => Ok(ImportFormat::TodoTxt),
+            _ => Err(format!(
+                "unknown import format {:?}, expected json, ndjson, csv or todotxt",
+                name
+            )),
+        }
+    }
+}
+
+impl TryFrom<String> for ImportFormat {
+    type Error = String;
+
+    fn try_from(name: String) -> Result<Self, Self::Error> {
+        name.parse()
+    }
+}
+
+impl ImportFormat {
+    // The format a media type (without parameters) stands for
+    pub fn from_media_type(media_type: &str) -> Option<Self> {
+        match media_type {
+            "application/json" => Some(ImportFormat::Json),
+            "application/x-ndjson" | "application/jsonl" => Some(ImportFormat::Ndjson),
+            "text/csv" => Some(ImportFormat::Csv),
+            "text/plain" => Some(ImportFormat::TodoTxt),
+            _ => None,
+        }
+    }
+
+    // The format a file name's extension stands for
+    pub fn from_file_name(name: &str) -> Option<Self> {
+        let (_, extension) = name.rsplit_once('.')?;
+        extension.parse().ok()
+    }
+}
+
+// Which CSV columns hold which field, when their headers are not the usual ones
+#[derive(Debug, Default, Clone)]
+pub struct ColumnMapping {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub completed: Option<String>,
+    pub priority: Option<String>,
+    pub tags: Option<String>,
+    pub due: Option<String>,
+}
+
+// Query string parameters for POST /api/tasks/import
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ImportParams {
+    pub format: Option<ImportFormat>,
+    #[serde(default)]
+    pub dry_run: bool,
+    pub title_column: Option<String>,
+    pub description_column: Option<String>,
+    pub completed_column: Option<String>,
+    pub priority_column: Option<String>,
+    pub tags_column: Option<String>,
+    pub due_column: Option<String>,
+}
+
+impl ImportParams {
+    pub fn columns(&self) -> ColumnMapping {
+        ColumnMapping {
+            title: self.title_column.clone(),
+            description: self.description_column.clone(),
+            completed: self.completed_column.clone(),
+            priority: self.priority_column.clone(),
+            tags: self.tags_column.clone(),
+            due: self.due_column.clone(),
+        }
+    }
+}
+
+// A task as read from an import, before it is created
+#[derive(Debug, Deserialize)]
+pub struct ImportRecord {
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub completed: bool,
+    #[serde(default)]
+    pub priority: Priority,
+    #[serde(default, deserialize_with = "tags::deserialize")]
+    pub tags: Vec<String>,
+    #[serde(default, deserialize_with = "deserialize_due")]
+    pub due_at: Option<DateTime<Utc>>,
+}
+
+fn deserialize_due<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<DateTime<Utc>>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(value) => parse_due(&value).map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+// An RFC 3339 timestamp or a `YYYY-MM-DD` date (midnight UTC); empty is none
+fn parse_due(value: &str) -> Result<Option<DateTime<Utc>>, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(Some(time.with_timezone(&Utc)));
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|date| Some(date.and_time(Default::default()).and_utc()))
+        .map_err(|_| format!("invalid due date {:?}", value))
+}
+
+impl ImportRecord {
+    fn validate(self) -> Result<Self, String> {
+        let title = self.title.trim();
+        if title.is_empty() {
+            return Err("title must not be empty".to_string());
+        }
+        validate_title(title)?;
+        Ok(ImportRecord {
+            title: title.to_string(),
+            ..self
+        })
+    }
+
+    pub fn into_task(self) -> Task {
+        let mut task = Task::new(TaskCreate {
+            title: self.title,
+            description: self.description,
+            priority: self.priority,
+            tags: self.tags,
+            due_at: self.due_at,
+        });
+        task.completed = self.completed;
+        task
+    }
+}
+
+// A record that failed validation. `record` counts records from 1; `line` is
+// where it starts in the input, for the line-based formats.
+#[derive(Debug, Serialize)]
+pub struct ImportIssue {
+    pub record: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub line: Option<u64>,
+    pub error: String,
+}
+
+// The input after parsing: the valid records and what was wrong with the rest
+#[derive(Debug, Default)]
+pub struct ParsedImport {
+    pub records: Vec<ImportRecord>,
+    pub errors: Vec<ImportIssue>,
+}
+
+impl ParsedImport {
+    fn push(&mut self, line: Option<u64>, record: Result<ImportRecord, String>) {
+        let number = self.records.len() + self.errors.len() + 1;
+        match record.and_then(ImportRecord::validate) {
+            Ok(record) => self.records.push(record),
+            Err(error) => self.errors.push(ImportIssue {
+                record: number,
+                line,
+                error,
+            }),
+        }
+    }
+
+    fn total(&self) -> usize {
+        self.records.len() + self.errors.len()
+    }
+}
+
+pub fn parse(
+    format: ImportFormat,
+    input: &[u8],
+    columns: &ColumnMapping,
+) -> Result<ParsedImport, ImportError> {
+    let parsed = match format {
+        ImportFormat::Json => parse_json(input)?,
+        ImportFormat::Ndjson => parse_ndjson(text(input)?),
+        ImportFormat::Csv => parse_csv(input, columns)?,
+        ImportFormat::TodoTxt => parse_todo_txt(text(input)?),
+    };
+
+    if parsed.total() > MAX_IMPORT_TASKS {
+        return Err(ImportError::TooManyTasks(parsed.total()));
+    }
+    Ok(parsed)
+}
+
+fn text(input: &[u8]) -> Result<&str, ImportError> {
+    let text = std::str::from_utf8(input)
+        .map_err(|err| ImportError::Unreadable(format!("input is not UTF-8: {}", err)))?;
+    Ok(text.strip_prefix('\u{feff}').unwrap_or(text))
+}
+
+fn parse_json(input: &[u8]) -> Result<ParsedImport, ImportError> {
+    let values: Vec<serde_json::Value> = serde_json::from_slice(input)
+        .map_err(|err| ImportError::Unreadable(format!("expected a JSON array: {}", err)))?;
+
+    let mut parsed = ParsedImport::default();
+    for value in values {
+        parsed.push(None, serde_json::from_value(value).map_err(|err| err.to_string()));
+    }
+    Ok(parsed)
+}
+
+fn parse_ndjson(input: &str) -> ParsedImport {
+    let mut parsed = ParsedImport::default();
+    for (index, line) in input.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record = serde_json::from_str(line).map_err(|err| err.to_string());
+        parsed.push(Some(index as u64 + 1), record);
+    }
+    parsed
+}
+
+// Index of the column holding a field: the mapped header if there is one,
+// otherwise the first header with one of the usual names
+fn find_column(
+    headers: &csv::StringRecord,
+    mapped: Option<&str>,
+    usual: &[&str],
+) -> Result<Option<usize>, ImportError> {
+    let position = |name: &str| headers.iter().position(|header| header.eq_ignore_ascii_case(name));
+
+    match mapped {
+        Some(name) => position(name)
+            .map(Some)
+            .ok_or_else(|| ImportError::Unreadable(format!("CSV has no column {:?}", name))),
+        None => Ok(usual.iter().find_map(|name| position(name))),
+    }
+}
+
+fn parse_csv(input: &[u8], columns: &ColumnMapping) -> Result<ParsedImport, ImportError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(true)
+        .trim(csv::Trim::Headers)
+        .from_reader(input);
+    let headers = reader
+        .headers()
+        .map_err(|err| ImportError::Unreadable(err.to_string()))?
+        .clone();
+
+    let title = find_column(&headers, columns.title.as_deref(), TITLE_COLUMNS)?.ok_or_else(|| {
+        ImportError::Unreadable(format!(
+            "CSV has no title column (headers: {}); name one with title_column",
+            headers.iter().collect::<Vec<_>>().join(", ")
+        ))
+    })?;
+    let description =
+        find_column(&headers, columns.description.as_deref(), DESCRIPTION_COLUMNS)?;
+    let completed = find_column(&headers, columns.completed.as_deref(), COMPLETED_COLUMNS)?;
+    let priority = find_column(&headers, columns.priority.as_deref(), PRIORITY_COLUMNS)?;
+    let tags = find_column(&headers, columns.tags.as_deref(), TAGS_COLUMNS)?;
+    let due = find_column(&headers, columns.due.as_deref(), DUE_COLUMNS)?;
+
+    let mut parsed = ParsedImport::default();
+    for row in reader.records() {
+        let row = match row {
+            Ok(row) => row,
+            // Malformed rows (bad UTF-8, say) are reported like invalid ones
+            Err(err) => {
+                let line = err.position().map(|position| position.line());
+                parsed.push(line, Err(err.to_string()));
+                continue;
+            }
+        };
+        let line = row.position().map(|position| position.line());
+        if row.iter().all(|field| field.trim().is_empty()) {
+            continue;
+        }
+
+        let field = |column: Option<usize>| column.and_then(|index| row.get(index)).unwrap_or("");
+        let record = parse_completed(field(completed)).and_then(|completed| {
+            Ok(ImportRecord {
+                title: field(Some(title)).to_string(),
+                description: field(description).to_string(),
+                completed,
+                priority: parse_priority(field(priority))?,
+                tags: parse_tags(field(tags))?,
+                due_at: parse_due(field(due))?,
+            })
+        });
+        parsed.push(line, record);
+    }
+    Ok(parsed)
+}
+
+// Spreadsheets and other tools spell "done" in many ways
+fn parse_completed(value: &str) -> Result<bool, String> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "yes" | "y" | "1" | "x" | "done" | "completed" | "complete" | "closed" => Ok(true),
+        "" | "false" | "no" | "n" | "0" | "open" | "todo" | "pending" | "in progress" => {
+            Ok(false)
+        }
+        other => Err(format!("cannot tell whether {:?} means completed", other)),
+    }
+}
+
+// A priority by name, in any case; empty is the default
+fn parse_priority(value: &str) -> Result<Priority, String> {
+    match value.trim() {
+        "" => Ok(Priority::default()),
+        name => name.to_ascii_lowercase().parse(),
+    }
+}
+
+// Tags separated by commas or whitespace
+fn parse_tags(value: &str) -> Result<Vec<String>, String> {
+    let tags = value.split(|c: char| c == ',' || c.is_whitespace());
+    tags::normalize_all(tags.filter(|tag| !tag.is_empty()).map(String::from).collect())
+}
+
+fn parse_todo_txt(input: &str) -> ParsedImport {
+    let mut parsed = ParsedImport::default();
+    for (index, line) in input.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        parsed.push(Some(index as u64 + 1), parse_todo_line(line));
+    }
+    parsed
+}
+
+// One todo.txt task: `[x ][(A) ][completion date ][creation date ]text`,
+// where the text may hold `+project` and `@context` tags and a
+// `due:YYYY-MM-DD` tag
+fn parse_todo_line(line: &str) -> Result<ImportRecord, String> {
+    let mut rest = line.trim();
+
+    let completed = match rest.strip_prefix("x ") {
+        Some(after) => {
+            rest = after.trim_start();
+            true
+        }
+        None => false,
+    };
+
+    let priority = match strip_priority(rest) {
+        Some((letter, after)) => {
+            rest = after.trim_start();
+            match letter {
+                'A' => Priority::Urgent,
+                'B' => Priority::High,
+                'C' => Priority::Normal,
+                _ => Priority::Low,
+            }
+        }
+        None => Priority::default(),
+    };
+
+    // A completed task may carry its completion date before the creation date
+    for _ in 0..if completed { 2 } else { 1 } {
+        let (token, after) = rest.split_once(' ').unwrap_or((rest, ""));
+        if !looks_like_date(token) {
+            break;
+        }
+        NaiveDate::parse_from_str(token, "%Y-%m-%d")
+            .map_err(|_| format!("invalid date {:?}", token))?;
+        rest = after.trim_start();
+    }
+
+    let mut due_at = None;
+    let mut tags = Vec::new();
+    let mut words = Vec::new();
+    for word in rest.split_whitespace() {
+        if let Some(date) = word.strip_prefix("due:").filter(|date| looks_like_date(date)) {
+            due_at = parse_due(date)?;
+        } else if let Some(tag) = word.strip_prefix(['+', '@']).filter(|tag| !tag.is_empty()) {
+            tags.push(tag.to_string());
+        } else {
+            words.push(word);
+        }
+    }
+
+    Ok(ImportRecord {
+        title: words.join(" "),
+        description: String::new(),
+        completed,
+        priority,
+        tags: tags::normalize_all(tags)?,
+        due_at,
+    })
+}
+
+// A leading `(A) ` priority and the line after it, if it has one
+fn strip_priority(line: &str) -> Option<(char, &str)> {
+    let rest = line.strip_prefix('(')?;
+    let mut chars = rest.chars();
+    let letter = chars.next().filter(char::is_ascii_uppercase)?;
+    Some((letter, chars.as_str().strip_prefix(") ")?))
+}
+
+fn looks_like_date(token: &str) -> bool {
+    token.len() == 10
+        && token.char_indices().all(|(index, c)| match index {
+            4 | 7 => c == '-',
+            _ => c.is_ascii_digit(),
+        })
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportReport {
+    pub format: ImportFormat,
+    pub dry_run: bool,
+    // Records read, valid or not
+    pub records: usize,
+    pub valid: usize,
+    pub imported: usize,
+    pub errors: Vec<ImportIssue>,
+}
+
+impl ImportReport {
+    pub fn succeeded(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+// Create the parsed tasks, unless this is a dry run or any record is invalid
+pub async fn import(
+    storage: &dyn TaskRepository,
+    format: ImportFormat,
+    parsed: ParsedImport,
+    dry_run: bool,
+) -> Result<ImportReport, ImportError> {
+    let mut report = ImportReport {
+        format,
+        dry_run,
+        records: parsed.total(),
+        valid: parsed.records.len(),
+        imported: 0,
+        errors: parsed.errors,
+    };
+    if dry_run || !report.succeeded() || parsed.records.is_empty() {
+        return Ok(report);
+    }
+
+    let operations: Vec<BatchOperation> = parsed
+        .records
+        .into_iter()
+        .map(|record| BatchOperation::Create(record.into_task()))
+        .collect();
+    let outcomes = storage.apply_atomically(operations).await?;
+
+    // Creating new tasks cannot conflict, so anything else is a storage fault
+    if !outcomes.iter().all(|outcome| matches!(outcome, Conditional::Applied(_))) {
+        return Err(StorageError::Internal("import was not applied".to_string()).into());
+    }
+    report.imported = outcomes.len();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::InMemoryTaskRepository;
+
+    fn parse_records(format: ImportFormat, input: &str) -> ParsedImport {
+        parse(format, input.as_bytes(), &ColumnMapping::default()).unwrap()
+    }
+
+    fn due(value: &str) -> Option<DateTime<Utc>> {
+        Some(value.parse().unwrap())
+    }
+
+    #[test]
+    fn imports_priorities_from_every_format() {
+        let json = r#"[{"title": "A", "priority": "urgent"}, {"title": "B"}]"#;
+        let parsed = parse_records(ImportFormat::Json, json);
+        let priorities: Vec<_> = parsed.records.iter().map(|record| record.priority).collect();
+        assert_eq!(priorities, [Priority::Urgent, Priority::Normal]);
+
+        let csv = "title,prio\nA,High\nB,\nC,someday\n";
+        let parsed = parse_records(ImportFormat::Csv, csv);
+        let priorities: Vec<_> = parsed.records.iter().map(|record| record.priority).collect();
+        assert_eq!(priorities, [Priority::High, Priority::Normal]);
+        assert_eq!(parsed.errors[0].record, 3);
+
+        let todo = "(A) A\n(B) B\n(C) C\n(D) D\n(Z) Z\nNone\nx (A) 2026-01-02 Done\n";
+        let parsed = parse_records(ImportFormat::TodoTxt, todo);
+        let priorities: Vec<_> = parsed.records.iter().map(|record| record.priority).collect();
+        use Priority::*;
+        assert_eq!(priorities, [Urgent, High, Normal, Low, Low, Normal, Urgent]);
+        assert_eq!(parsed.records[6].title, "Done");
+    }
+
+    #[test]
+    fn imports_tags_from_every_format() {
+        let json = r#"[{"title": "A", "tags": ["Rust", "docker", "rust"]}, {"title": "B"}]"#;
+        let parsed = parse_records(ImportFormat::Json, json);
+        let tags: Vec<_> = parsed.records.iter().map(|record| record.tags.clone()).collect();
+        assert_eq!(tags, [vec!["docker", "rust"], vec![]]);
+
+        let csv = "title,labels\nA,\"docker, rust\"\nB,ops\nC,\n";
+        let parsed = parse_records(ImportFormat::Csv, csv);
+        let tags: Vec<_> = parsed.records.iter().map(|record| record.tags.clone()).collect();
+        assert_eq!(tags, [vec!["docker", "rust"], vec!["ops"], vec![]]);
+
+        let todo = "(B) Ship +Release @work the build + due:2030-01-02\n";
+        let parsed = parse_records(ImportFormat::TodoTxt, todo);
+        let record = &parsed.records[0];
+        assert_eq!(record.title, "Ship the build +");
+        assert_eq!(record.tags, ["release", "work"]);
+        assert_eq!(record.priority, Priority::High);
+
+        let too_many: String = (0..21).map(|n| format!(" +t{}", n)).collect();
+        let parsed = parse_records(ImportFormat::TodoTxt, &format!("Busy{}", too_many));
+        assert_eq!(parsed.errors[0].error, "a task can have at most 20 tags");
+    }
+
+    #[test]
+    fn imports_due_dates_from_every_format() {
+        let json = r#"[{"title": "A", "due_at": "2030-01-02T03:04:05Z"},
+            {"title": "B", "due_at": "2030-01-02"}, {"title": "C", "due_at": null}]"#;
+        let parsed = parse_records(ImportFormat::Json, json);
+        let dues: Vec<_> = parsed.records.iter().map(|record| record.due_at).collect();
+        assert_eq!(dues, [due("2030-01-02T03:04:05Z"), due("2030-01-02T00:00:00Z"), None]);
+
+        let csv = "name,deadline\nA,2030-01-02\nB,\nC,soon\n";
+        let parsed = parse_records(ImportFormat::Csv, csv);
+        let dues: Vec<_> = parsed.records.iter().map(|record| record.due_at).collect();
+        assert_eq!(dues, [due("2030-01-02T00:00:00Z"), None]);
+        assert_eq!(parsed.errors[0].record, 3);
+        assert_eq!(parsed.errors[0].error, "invalid due date \"soon\"");
+
+        let parsed = parse_records(ImportFormat::TodoTxt, "x 2026-01-02 Ship due:2030-01-02 it\n");
+        let task = parsed.records.into_iter().next().unwrap().into_task();
+        assert_eq!(task.title, "Ship it");
+        assert!(task.completed);
+        assert_eq!(task.due_at, due("2030-01-02T00:00:00Z"));
+    }
+
+    // (record, line, error) for each invalid record
+    fn issues(parsed: &ParsedImport) -> Vec<(usize, Option<u64>, &str)> {
+        let issues = parsed.errors.iter();
+        issues.map(|issue| (issue.record, issue.line, issue.error.as_str())).collect()
+    }
+
+    #[test]
+    fn reports_each_invalid_record_with_its_line() {
+        let ndjson = "{\"title\": \"A\"}\n\n{\"title\": \"  \"}\nnot json\n{\"title\": \"B\"}\n";
+        let parsed = parse_records(ImportFormat::Ndjson, ndjson);
+        assert_eq!(parsed.records.len(), 2);
+        let found = issues(&parsed);
+        assert_eq!(found[0], (2, Some(3), "title must not be empty"));
+        assert_eq!((found[1].0, found[1].1), (3, Some(4)));
+
+        // Quoted fields may span lines; the line is where the record starts
+        let csv = "title,done\n\"Two\nlines\",no\nB,maybe\nC,yes\n";
+        let parsed = parse_records(ImportFormat::Csv, csv);
+        assert_eq!(parsed.records[0].title, "Two\nlines");
+        assert!(parsed.records[1].completed);
+        let error = "cannot tell whether \"maybe\" means completed";
+        assert_eq!(issues(&parsed), [(2, Some(4), error)]);
+
+        let json = r#"[{"title": "A"}, {"description": "no title"}, {"title": "x"}]"#;
+        let parsed = parse_records(ImportFormat::Json, json);
+        assert_eq!(parsed.records.len(), 2);
+        assert_eq!(issues(&parsed), [(2, None, "missing field `title`")]);
+
+        let todo = "Fine\n\n2026-13-40 Bad date\n";
+        let parsed = parse_records(ImportFormat::TodoTxt, todo);
+        assert_eq!(issues(&parsed), [(2, Some(3), "invalid date \"2026-13-40\"")]);
+    }
+
+    #[test]
+    fn rejects_unreadable_inputs_as_a_whole() {
+        let unreadable = |format, input: &[u8], columns: &ColumnMapping| {
+            let err = parse(format, input, columns).unwrap_err();
+            assert_eq!(err.status_code(), StatusCode::BAD_REQUEST, "{}", err);
+            err.to_string()
+        };
+        let columns = ColumnMapping::default();
+        let message = unreadable(ImportFormat::Json, br#"{"title": "A"}"#, &columns);
+        assert!(message.starts_with("cannot read import: expected a JSON array"), "{}", message);
+        let message = unreadable(ImportFormat::Ndjson, b"{\"title\": \"\xff\"}", &columns);
+        assert!(message.starts_with("cannot read import: input is not UTF-8"), "{}", message);
+        let message = unreadable(ImportFormat::Csv, b"id,owner\n1,me\n", &columns);
+        assert_eq!(
+            message,
+            "cannot read import: CSV has no title column (headers: id, owner); \
+             name one with title_column"
+        );
+
+        let mapped = ColumnMapping {
+            title: Some("Owner".to_string()),
+            due: Some("when".to_string()),
+            ..Default::default()
+        };
+        let message = unreadable(ImportFormat::Csv, b"id,owner\n1,me\n", &mapped);
+        assert_eq!(message, "cannot read import: CSV has no column \"when\"");
+        let mapped = ColumnMapping {
+            title: Some("Owner".to_string()),
+            ..Default::default()
+        };
+        let parsed = parse(ImportFormat::Csv, b"id,owner\n1,me\n", &mapped).unwrap();
+        assert_eq!(parsed.records[0].title, "me");
+
+        let lines = "Task\n".repeat(MAX_IMPORT_TASKS + 1);
+        let message = unreadable(ImportFormat::TodoTxt, lines.as_bytes(), &columns);
+        assert_eq!(message, "an import must have at most 10000 tasks, got 10001");
+    }
+
+    #[actix_web::test]
+    async fn writes_nothing_on_a_dry_run_or_any_invalid_record() {
+        let storage = InMemoryTaskRepository::new();
+        let csv = "title,done\nA,no\nB,yes\n";
+
+        let parsed = parse_records(ImportFormat::Csv, csv);
+        let report = import(&storage, ImportFormat::Csv, parsed, true).await.unwrap();
+        assert!(report.succeeded() && report.dry_run);
+        assert_eq!((report.records, report.valid, report.imported), (2, 2, 0));
+        assert!(storage.list().await.unwrap().is_empty());
+
+        let parsed = parse_records(ImportFormat::Csv, &format!("{}C,maybe\n", csv));
+        let report = import(&storage, ImportFormat::Csv, parsed, false).await.unwrap();
+        assert!(!report.succeeded());
+        assert_eq!((report.records, report.valid, report.imported), (3, 2, 0));
+        assert!(storage.list().await.unwrap().is_empty());
+
+        let parsed = parse_records(ImportFormat::Csv, csv);
+        let report = import(&storage, ImportFormat::Csv, parsed, false).await.unwrap();
+        assert!(report.succeeded());
+        assert_eq!(report.imported, 2);
+        let mut tasks: Vec<_> = storage
+            .list()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|task| (task.title, task.completed))
+            .collect();
+        tasks.sort();
+        assert_eq!(tasks, [("A".to_string(), false), ("B".to_string(), true)]);
+    }
+}
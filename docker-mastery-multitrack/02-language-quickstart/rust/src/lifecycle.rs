@@ -0,0 +1,139 @@
+/*!
+ * Process lifecycle for the probe endpoints
+ *
+ * starting -> ready -> draining
+ *
+ * The server binds while still `starting` (storage recovery and migrations run
+ * in the background), becomes `ready` once storage is usable, and switches to
+ * `draining` on SIGTERM so readiness fails before the listener is closed.
+ */
+
+use actix_web::dev::ServerHandle;
+use log::info;
+use serde::Serialize;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::Duration;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    Starting,
+    Ready,
+    Draining,
+}
+
+pub struct Lifecycle {
+    phase: AtomicU8,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lifecycle {
+    pub fn new() -> Self {
+        Self {
+            phase: AtomicU8::new(Phase::Starting as u8),
+        }
+    }
+
+    pub fn phase(&self) -> Phase {
+        match self.phase.load(Ordering::Acquire) {
+            0 => Phase::Starting,
+            1 => Phase::Ready,
+            _ => Phase::Draining,
+        }
+    }
+
+    pub fn mark_ready(&self) {
+        // Never go back from draining to ready
+        let _ = self.phase.compare_exchange(
+            Phase::Starting as u8,
+            Phase::Ready as u8,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        );
+    }
+
+    pub fn mark_draining(&self) {
+        self.phase.store(Phase::Draining as u8, Ordering::Release);
+    }
+}
+
+// Delay between failing readiness and closing the listener, so load balancers
+// and compose dependants stop routing here first (SHUTDOWN_DRAIN_SECONDS)
+pub fn drain_delay_from_env() -> Duration {
+    let seconds = std::env::var("SHUTDOWN_DRAIN_SECONDS")
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(5);
+
+    Duration::from_secs(seconds)
+}
+
+// Wait for SIGTERM/SIGINT, fail readiness, then stop the server gracefully
+pub async fn shutdown_on_signal(
+    lifecycle: actix_web::web::Data<Lifecycle>,
+    server: ServerHandle,
+    drain_delay: Duration,
+) {
+    wait_for_signal().await;
+
+    info!("Shutdown signal received; draining for {}s", drain_delay.as_secs());
+    lifecycle.mark_draining();
+    tokio::time::sleep(drain_delay).await;
+
+    info!("Stopping HTTP server");
+    server.stop(true).await;
+}
+
+#[cfg(unix)]
+async fn wait_for_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            tokio::select! {
+                _ = terminate.recv() => {}
+                _ = tokio::signal::ctrl_c() => {}
+            }
+        }
+        Err(_) => {
+            let _ = tokio::signal::ctrl_c().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_signal() {
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn moves_from_starting_to_ready_to_draining() {
+        let lifecycle = Lifecycle::new();
+        assert_eq!(lifecycle.phase(), Phase::Starting);
+        lifecycle.mark_ready();
+        assert_eq!(lifecycle.phase(), Phase::Ready);
+        lifecycle.mark_ready();
+        assert_eq!(lifecycle.phase(), Phase::Ready);
+        lifecycle.mark_draining();
+        assert_eq!(lifecycle.phase(), Phase::Draining);
+    }
+
+    #[test]
+    fn never_goes_back_from_draining() {
+        // A signal during startup recovery drains before storage is ready
+        let lifecycle = Lifecycle::default();
+        lifecycle.mark_draining();
+        lifecycle.mark_ready();
+        assert_eq!(lifecycle.phase(), Phase::Draining);
+        assert_eq!(serde_json::to_value(lifecycle.phase()).unwrap(), "draining");
+    }
+}
@@ -6,171 +6,53 @@
  */
 
 use actix_web::{web, App, HttpServer, HttpResponse, Result, middleware::Logger};
-use serde::{Deserialize, Serialize};
-use std::sync::Mutex;
-use uuid::Uuid;
-use chrono::{DateTime, Utc};
-use std::collections::HashMap;
-use log::info;
-
-// Task models
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct Task {
-    id: String,
-    title: String,
-    description: String,
-    completed: bool,
-    created_at: DateTime<Utc>,
-    updated_at: DateTime<Utc>,
-}
-
-#[derive(Debug, Deserialize)]
-struct TaskCreate {
-    title: String,
-    description: String,
-}
-
-#[derive(Debug, Deserialize)]
-struct TaskUpdate {
-    title: Option<String>,
-    description: Option<String>,
-    completed: Option<bool>,
-}
-
-// Application state
-type TaskStorage = Mutex<HashMap<String, Task>>;
-
-// Health check endpoint
-async fn health_check() -> Result<HttpResponse> {
-    let health_response = serde_json::json!({
-        "status": "healthy",
-        "version": "1.0.0",
-        "timestamp": Utc::now().to_rfc3339(),
-        "environment": std::env::var("ENV").unwrap_or_else(|_| "production".to_string()),
-        "database": "connected"
-    });
-
-    Ok(HttpResponse::Ok().json(health_response))
-}
-
-// List all tasks
-async fn list_tasks(data: web::Data<TaskStorage>) -> Result<HttpResponse> {
-    let tasks = data.lock().unwrap();
-    let task_list: Vec<&Task> = tasks.values().collect();
-
-    info!("Fetching {} tasks", task_list.len());
-
-    let response = serde_json::json!({
-        "tasks": task_list,
-        "total": task_list.len()
-    });
-
-    Ok(HttpResponse::Ok().json(response))
-}
-
-// Create new task
-async fn create_task(
-    task_data: web::Json<TaskCreate>,
-    data: web::Data<TaskStorage>,
-) -> Result<HttpResponse> {
-    let mut tasks = data.lock().unwrap();
-
-    let task = Task {
-        id: Uuid::new_v4().to_string(),
-        title: task_data.title.clone(),
-        description: task_data.description.clone(),
-        completed: false,
-        created_at: Utc::now(),
-        updated_at: Utc::now(),
-    };
-
-    info!("Created task: {} - {}", task.id, task.title);
-    tasks.insert(task.id.clone(), task.clone());
-
-    Ok(HttpResponse::Created().json(task))
-}
-
-// Get specific task
-async fn get_task(
-    path: web::Path<String>,
-    data: web::Data<TaskStorage>,
-) -> Result<HttpResponse> {
-    let tasks = data.lock().unwrap();
-    let task_id = path.into_inner();
-
-    match tasks.get(&task_id) {
-        Some(task) => Ok(HttpResponse::Ok().json(task)),
-        None => {
-            info!("Task not found: {}", task_id);
-            Ok(HttpResponse::NotFound().json(serde_json::json!({
-                "error": format!("Task with id {} not found", task_id)
-            })))
-        }
-    }
-}
-
-// Update task
-async fn update_task(
-    path: web::Path<String>,
-    task_update: web::Json<TaskUpdate>,
-    data: web::Data<TaskStorage>,
-) -> Result<HttpResponse> {
-    let mut tasks = data.lock().unwrap();
-    let task_id = path.into_inner();
-
-    match tasks.get_mut(&task_id) {
-        Some(task) => {
-            if let Some(title) = &task_update.title {
-                task.title = title.clone();
-            }
-            if let Some(description) = &task_update.description {
-                task.description = description.clone();
-            }
-            if let Some(completed) = task_update.completed {
-                task.completed = completed;
-            }
-            task.updated_at = Utc::now();
-
-            info!("Updated task: {} - {}", task.id, task.title);
-            Ok(HttpResponse::Ok().json(task.clone()))
-        }
-        None => {
-            info!("Task not found for update: {}", task_id);
-            Ok(HttpResponse::NotFound().json(serde_json::json!({
-                "error": format!("Task with id {} not found", task_id)
-            })))
-        }
-    }
-}
-
-// Delete task
-async fn delete_task(
-    path: web::Path<String>,
-    data: web::Data<TaskStorage>,
-) -> Result<HttpResponse> {
-    let mut tasks = data.lock().unwrap();
-    let task_id = path.into_inner();
-
-    match tasks.remove(&task_id) {
-        Some(task) => {
-            info!("Deleted task: {} - {}", task.id, task.title);
-            Ok(HttpResponse::NoContent().finish())
-        }
-        None => {
-            info!("Task not found for deletion: {}", task_id);
-            Ok(HttpResponse::NotFound().json(serde_json::json!({
-                "error": format!("Task with id {} not found", task_id)
-            })))
-        }
-    }
-}
+use chrono::Utc;
+use log::{error, info};
+use std::sync::Arc;
+
+mod attachments;
+mod batch;
+mod cli;
+mod export;
+mod filter;
+mod handlers;
+mod health;
+mod history;
+mod idempotency;
+mod import;
+mod lifecycle;
+mod middleware;
+mod models;
+mod patch;
+mod precondition;
+mod projection;
+mod query;
+mod search;
+mod storage;
+mod tags;
+mod trash;
+
+use attachments::AttachmentStore;
+use export::ExportConfig;
+use handlers::{TaskStorage, list_tasks, search_tasks, batch_tasks, export_tasks, write_export, import_tasks, next_task, upload_attachments, list_attachments, download_attachment, delete_attachment, task_history, task_revision, list_trash, restore_task, purge_task, empty_trash, list_tags, rename_tag, merge_tags, create_task, get_task, update_task, patch_task, delete_task};
+use history::RecordActor;
+use health::{health_check, liveness, readiness, startup, HealthRegistry, StorageProbe};
+use idempotency::IdempotencyStore;
+use lifecycle::Lifecycle;
+use middleware::CatchPanic;
+use precondition::PreconditionConfig;
+use query::QueryError;
+use storage::{DeferredRepository, StorageConfig, TaskRepository};
+use trash::TrashConfig;
 
 // Metrics endpoint
-async fn metrics(data: web::Data<TaskStorage>) -> Result<HttpResponse> {
-    let tasks = data.lock().unwrap();
+async fn metrics(data: TaskStorage) -> Result<HttpResponse> {
+    let tasks = data.list().await?;
     let total_tasks = tasks.len();
-    let completed_tasks = tasks.values().filter(|t| t.completed).count();
+    let completed_tasks = tasks.iter().filter(|t| t.completed).count();
     let pending_tasks = total_tasks - completed_tasks;
+    let now = Utc::now();
+    let overdue_tasks = tasks.iter().filter(|t| t.is_overdue(now)).count();
 
     let metrics_data = format!(
         "# HELP tasks_total Total number of tasks\n\
@@ -181,8 +63,11 @@ async fn metrics(data: web::Data<TaskStorage>) -> Result<HttpResponse> {
          tasks_completed {}\n\n\
          # HELP tasks_pending Number of pending tasks\n\
          # TYPE tasks_pending gauge\n\
-         tasks_pending {}\n",
-        total_tasks, completed_tasks, pending_tasks
+         tasks_pending {}\n\n\
+         # HELP tasks_overdue Number of pending tasks past their due date\n\
+         # TYPE tasks_overdue gauge\n\
+         tasks_overdue {}\n",
+        total_tasks, completed_tasks, pending_tasks, overdue_tasks
     );
 
     Ok(HttpResponse::Ok()
@@ -198,7 +83,18 @@ async fn root() -> Result<HttpResponse> {
         "docker_track": "rust",
         "endpoints": {
             "health": "/health",
+            "liveness": "/health/live",
+            "readiness": "/health/ready",
+            "startup": "/health/startup",
             "tasks": "/api/tasks",
+            "search": "/api/tasks/search?q=",
+            "batch": "/api/tasks/batch",
+            "export": "/api/tasks/export?format=",
+            "import": "/api/tasks/import?format=",
+            "next": "/api/tasks/next",
+            "trash": "/api/trash",
+            "history": "/api/tasks/{id}/history",
+            "tags": "/api/tags",
             "metrics": "/metrics"
         },
         "quick_start": {
@@ -216,28 +112,131 @@ async fn main() -> std::io::Result<()> {
     // Initialize logger
     env_logger::init_from_env(env_logger::Env::new().default_filter_or("info"));
 
-    // Initialize task storage
-    let task_storage = web::Data::new(TaskStorage::new(HashMap::new()));
+    // `task-api import ...` and other subcommands run instead of the server
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(result) = cli::run(&args).await {
+        if let Err(err) = result {
+            eprintln!("task-api: {}", err);
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    let lifecycle = web::Data::new(Lifecycle::new());
+
+    // Task storage is filled in once startup recovery has finished; until then
+    // task requests get a 503 while the probe endpoints already answer
+    let repository = Arc::new(DeferredRepository::new());
+    let task_storage: TaskStorage = web::Data::from(repository.clone() as Arc<dyn TaskRepository>);
+
+    // Dependencies probed by /health
+    let health_registry = web::Data::new(
+        HealthRegistry::from_env().register(Arc::new(StorageProbe::new(repository.clone()))),
+    );
 
     info!("Starting Rust Task API server on 0.0.0.0:8080");
 
-    HttpServer::new(move || {
+    let preconditions = web::Data::new(PreconditionConfig::from_env());
+    let idempotency_store = web::Data::new(IdempotencyStore::from_env());
+    let export_config = web::Data::new(ExportConfig::from_env());
+    let attachment_store = web::Data::new(AttachmentStore::from_env());
+
+    let trash_config = TrashConfig::from_env();
+    let purge_attachments = attachment_store.clone().into_inner();
+
+    let app_lifecycle = lifecycle.clone();
+    let server = HttpServer::new(move || {
         App::new()
             .app_data(task_storage.clone())
+            .app_data(health_registry.clone())
+            .app_data(app_lifecycle.clone())
+            .app_data(preconditions.clone())
+            .app_data(idempotency_store.clone())
+            .app_data(export_config.clone())
+            .app_data(attachment_store.clone())
+            // Malformed query strings get the same JSON 400 as invalid values
+            .app_data(
+                web::QueryConfig::default()
+                    .error_handler(|err, _| QueryError::new(err.to_string()).into()),
+            )
+            .wrap(RecordActor)
+            .wrap(CatchPanic)
             .wrap(Logger::default())
             .route("/", web::get().to(root))
             .route("/health", web::get().to(health_check))
+            .route("/health/live", web::get().to(liveness))
+            .route("/health/ready", web::get().to(readiness))
+            .route("/health/startup", web::get().to(startup))
             .route("/metrics", web::get().to(metrics))
             .service(
                 web::scope("/api")
                     .route("/tasks", web::get().to(list_tasks))
                     .route("/tasks", web::post().to(create_task))
+                    // Registered before /tasks/{id} so "search" isn't taken as an id
+                    .route("/tasks/search", web::get().to(search_tasks))
+                    .route("/tasks/batch", web::post().to(batch_tasks))
+                    .route("/tasks/export", web::get().to(export_tasks))
+                    .route("/tasks/export", web::post().to(write_export))
+                    .route("/tasks/import", web::post().to(import_tasks))
+                    .route("/tasks/next", web::get().to(next_task))
                     .route("/tasks/{id}", web::get().to(get_task))
                     .route("/tasks/{id}", web::put().to(update_task))
+                    .route("/tasks/{id}", web::patch().to(patch_task))
                     .route("/tasks/{id}", web::delete().to(delete_task))
+                    .route("/tasks/{id}/attachments", web::get().to(list_attachments))
+                    .route("/tasks/{id}/attachments", web::post().to(upload_attachments))
+                    .route("/tasks/{id}/attachments/{attachment_id}", web::get().to(download_attachment))
+                    .route("/tasks/{id}/attachments/{attachment_id}", web::delete().to(delete_attachment))
+                    .route("/tasks/{id}/history", web::get().to(task_history))
+                    .route("/tasks/{id}/history/{revision}", web::get().to(task_revision))
+                    .route("/trash", web::get().to(list_trash))
+                    .route("/trash", web::delete().to(empty_trash))
+                    .route("/trash/{id}", web::delete().to(purge_task))
+                    .route("/trash/{id}/restore", web::post().to(restore_task))
+                    .route("/tags", web::get().to(list_tags))
+                    .route("/tags/merge", web::post().to(merge_tags))
+                    .route("/tags/{tag}/rename", web::post().to(rename_tag))
             )
     })
+    .disable_signals()
     .bind("0.0.0.0:8080")?
-    .run()
-    .await
+    .run();
+
+    let server_handle = server.handle();
+    actix_web::rt::spawn(lifecycle::shutdown_on_signal(
+        lifecycle.clone(),
+        server_handle.clone(),
+        lifecycle::drain_delay_from_env(),
+    ));
+
+    // Recover storage (WAL replay, migrations) while the probes report "starting"
+    let init = actix_web::rt::spawn(async move {
+        let result = storage::connect(&StorageConfig::from_env()).await;
+        match &result {
+            Ok(backend) => {
+                repository.set(backend.clone());
+                lifecycle.mark_ready();
+                info!("Task storage ready");
+                if let Some(retention) = trash_config.retention {
+                    actix_web::rt::spawn(trash::purge_expired(
+                        backend.clone(),
+                        purge_attachments,
+                        retention,
+                    ));
+                }
+            }
+            Err(err) => {
+                error!("Task storage failed to start: {}", err);
+                server_handle.stop(false).await;
+            }
+        }
+        result.map(|_| ())
+    });
+
+    server.await?;
+
+    match init.await {
+        Ok(Err(err)) => Err(std::io::Error::other(err.to_string())),
+        _ => Ok(()),
+    }
 }
@@ -0,0 +1,309 @@
+use chrono::{DateTime, DurationRound, TimeDelta, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::str::FromStr;
+use uuid::Uuid;
+
+use crate::tags;
+
+// Task models
+#[derive(Debug, Clone, Deserialize)]
+pub struct Task {
+    pub id: String,
+    pub title: String,
+    pub description: String,
+    pub completed: bool,
+    // Tasks logged before priorities existed are normal priority
+    #[serde(default)]
+    pub priority: Priority,
+    // Normalized and sorted, see src/tags.rs
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // Clients may send any UTC offset; the instant is kept and shown in UTC
+    #[serde(default)]
+    pub due_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+    // Incremented on every write; the task's ETag. Tasks logged before
+    // versioning start out at the first version.
+    #[serde(default = "first_version")]
+    pub version: i64,
+    // When the task was moved to the trash; `None` for live tasks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+fn first_version() -> i64 {
+    1
+}
+
+// Matches the VARCHAR(255) title column of the PostgreSQL schema
+pub const MAX_TITLE_LENGTH: usize = 255;
+
+pub fn validate_title(title: &str) -> Result<(), String> {
+    if title.chars().count() > MAX_TITLE_LENGTH {
+        Err(format!("title must be at most {} characters", MAX_TITLE_LENGTH))
+    } else {
+        Ok(())
+    }
+}
+
+// Serde `deserialize_with` for a task's title
+fn deserialize_title<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let title = String::deserialize(deserializer)?;
+    validate_title(&title).map_err(serde::de::Error::custom)?;
+    Ok(title)
+}
+
+// Serde `deserialize_with` for a title that may be left unchanged
+fn deserialize_title_some<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<String>, D::Error> {
+    deserialize_title(deserializer).map(Some)
+}
+
+// How urgent a task is, from least to most
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+    Urgent,
+}
+
+pub const PRIORITIES: [Priority; 4] =
+    [Priority::Low, Priority::Normal, Priority::High, Priority::Urgent];
+
+impl Priority {
+    pub fn name(self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+            Priority::Urgent => "urgent",
+        }
+    }
+
+    // Stored in the database as a number so it sorts from low to urgent
+    pub fn rank(self) -> i16 {
+        match self {
+            Priority::Low => 0,
+            Priority::Normal => 1,
+            Priority::High => 2,
+            Priority::Urgent => 3,
+        }
+    }
+
+    pub fn from_rank(rank: i16) -> Option<Self> {
+        PRIORITIES.into_iter().find(|priority| priority.rank() == rank)
+    }
+}
+
+impl FromStr for Priority {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        PRIORITIES
+            .into_iter()
+            .find(|priority| priority.name() == value)
+            .ok_or_else(|| {
+                format!("unknown priority '{}' (expected low, normal, high or urgent)", value)
+            })
+    }
+}
+
+// A task as the API shows it: its fields plus the computed `overdue` flag.
+// Must list the same fields as `Task`.
+#[derive(Serialize)]
+struct TaskJson<'a> {
+    id: &'a str,
+    title: &'a str,
+    description: &'a str,
+    completed: bool,
+    priority: Priority,
+    tags: &'a [String],
+    due_at: Option<DateTime<Utc>>,
+    overdue: bool,
+    created_at: DateTime<Utc>,
+    updated_at: DateTime<Utc>,
+    version: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<DateTime<Utc>>,
+}
+
+impl Serialize for Task {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        TaskJson {
+            id: &self.id,
+            title: &self.title,
+            description: &self.description,
+            completed: self.completed,
+            priority: self.priority,
+            tags: &self.tags,
+            due_at: self.due_at,
+            overdue: self.is_overdue(Utc::now()),
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            version: self.version,
+            deleted_at: self.deleted_at,
+        }
+        .serialize(serializer)
+    }
+}
+
+// Tells an explicit `null` (`Some(None)`) apart from a missing field (`None`)
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TaskCreate {
+    #[serde(deserialize_with = "deserialize_title")]
+    pub title: String,
+    pub description: String,
+    #[serde(default)]
+    pub priority: Priority,
+    #[serde(default, deserialize_with = "tags::deserialize")]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub due_at: Option<DateTime<Utc>>,
+}
+
+// Full replacement of a task's writable fields (PUT). Omitted optional fields
+// go back to their defaults.
+#[derive(Debug, Deserialize)]
+pub struct TaskReplace {
+    #[serde(deserialize_with = "deserialize_title")]
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub completed: bool,
+    #[serde(default)]
+    pub priority: Priority,
+    #[serde(default, deserialize_with = "tags::deserialize")]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub due_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct TaskUpdate {
+    #[serde(default, deserialize_with = "deserialize_title_some")]
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub completed: Option<bool>,
+    pub priority: Option<Priority>,
+    #[serde(default, deserialize_with = "tags::deserialize_some")]
+    pub tags: Option<Vec<String>>,
+    // `Some(None)` clears the due date
+    #[serde(default, deserialize_with = "present")]
+    pub due_at: Option<Option<DateTime<Utc>>>,
+}
+
+// Current time at microsecond precision, matching what PostgreSQL stores
+pub fn timestamp() -> DateTime<Utc> {
+    let now = Utc::now();
+    now.duration_trunc(TimeDelta::microseconds(1)).unwrap_or(now)
+}
+
+impl Task {
+    pub fn new(task_data: TaskCreate) -> Self {
+        let now = timestamp();
+        Task {
+            id: Uuid::new_v4().to_string(),
+            title: task_data.title,
+            description: task_data.description,
+            completed: false,
+            priority: task_data.priority,
+            tags: task_data.tags,
+            due_at: task_data.due_at,
+            created_at: now,
+            updated_at: now,
+            version: first_version(),
+            deleted_at: None,
+        }
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+
+    // Past its due date at `now` and still open
+    pub fn is_overdue(&self, now: DateTime<Utc>) -> bool {
+        !self.completed && self.due_at.is_some_and(|due_at| due_at < now)
+    }
+
+    // Move the task to the trash or back out of it. Like any write, this
+    // bumps updated_at and the version.
+    pub fn set_deleted(&mut self, deleted: bool) {
+        let now = timestamp();
+        self.deleted_at = deleted.then_some(now);
+        self.updated_at = now;
+        self.version += 1;
+    }
+}
+
+impl From<TaskReplace> for TaskUpdate {
+    fn from(replacement: TaskReplace) -> Self {
+        TaskUpdate {
+            title: Some(replacement.title),
+            description: Some(replacement.description),
+            completed: Some(replacement.completed),
+            priority: Some(replacement.priority),
+            tags: Some(replacement.tags),
+            due_at: Some(replacement.due_at),
+        }
+    }
+}
+
+impl TaskUpdate {
+    // Apply the provided fields to an existing task
+    pub fn apply(&self, task: &mut Task) {
+        if let Some(title) = &self.title {
+            task.title = title.clone();
+        }
+        if let Some(description) = &self.description {
+            task.description = description.clone();
+        }
+        if let Some(completed) = self.completed {
+            task.completed = completed;
+        }
+        if let Some(priority) = self.priority {
+            task.priority = priority;
+        }
+        if let Some(tags) = &self.tags {
+            task.tags = tags.clone();
+        }
+        if let Some(due_at) = self.due_at {
+            task.due_at = due_at;
+        }
+        task.updated_at = timestamp();
+        task.version += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_titles_longer_than_the_column() {
+        assert!(validate_title(&"é".repeat(MAX_TITLE_LENGTH)).is_ok());
+        let title = "x".repeat(MAX_TITLE_LENGTH + 1);
+        assert_eq!(validate_title(&title).unwrap_err(), "title must be at most 255 characters");
+
+        let body = serde_json::json!({"title": title, "description": ""});
+        let err = serde_json::from_value::<TaskCreate>(body).unwrap_err();
+        assert!(err.to_string().contains("title must be at most 255 characters"));
+        let err = serde_json::from_value::<TaskUpdate>(serde_json::json!({"title": title}));
+        assert!(err.is_err());
+    }
+}
@@ -0,0 +1,353 @@
+/*!
+ * PATCH documents for tasks
+ *
+ * `application/merge-patch+json` (RFC 7396) and `application/json-patch+json`
+ * (RFC 6902) are both applied to the task's JSON representation. The result
+ * has to still be a valid task: id, created_at, updated_at, version,
+ * deleted_at and the computed overdue flag are read-only, title is required
+ * and at most 255 characters, and a removed description, completed flag,
+ * priority, tags or due date falls back to its default, as with PUT.
+ */
+
+use actix_web::{http::header, http::StatusCode, HttpResponse, ResponseError};
+use chrono::{DateTime, Utc};
+use json_patch::PatchErrorKind;
+use serde_json::Value;
+use std::fmt;
+
+use crate::models::{validate_title, Priority, Task, TaskUpdate};
+use crate::tags;
+
+pub const MERGE_PATCH: &str = "application/merge-patch+json";
+pub const JSON_PATCH: &str = "application/json-patch+json";
+
+const READ_ONLY_FIELDS: [&str; 6] = [
+    "id",
+    "created_at",
+    "updated_at",
+    "version",
+    "deleted_at",
+    "overdue",
+];
+
+#[derive(Debug)]
+pub enum PatchError {
+    // The Content-Type is not a patch format we understand
+    UnsupportedMediaType(String),
+    // The body is not a valid patch document
+    Malformed(String),
+    // A JSON Patch `test` operation did not hold
+    TestFailed(String),
+    // The patch cannot be applied, or would leave an invalid task
+    Unprocessable(String),
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PatchError::UnsupportedMediaType(msg)
+            | PatchError::Malformed(msg)
+            | PatchError::TestFailed(msg)
+            | PatchError::Unprocessable(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for PatchError {}
+
+impl ResponseError for PatchError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PatchError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            PatchError::Malformed(_) => StatusCode::BAD_REQUEST,
+            PatchError::TestFailed(_) => StatusCode::CONFLICT,
+            PatchError::Unprocessable(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if let PatchError::UnsupportedMediaType(_) = self {
+            response.insert_header(("Accept-Patch", format!("{}, {}", MERGE_PATCH, JSON_PATCH)));
+        }
+        response.json(serde_json::json!({ "error": self.to_string() }))
+    }
+}
+
+#[derive(Debug)]
+pub enum TaskPatch {
+    Merge(Value),
+    Json(json_patch::Patch),
+}
+
+impl TaskPatch {
+    // Parse a request body according to its media type (without parameters)
+    pub fn parse(media_type: Option<&str>, body: &[u8]) -> Result<Self, PatchError> {
+        match media_type {
+            Some(MERGE_PATCH) => serde_json::from_slice(body)
+                .map(TaskPatch::Merge)
+                .map_err(|err| PatchError::Malformed(format!("invalid merge patch: {}", err))),
+            Some(JSON_PATCH) => serde_json::from_slice(body)
+                .map(TaskPatch::Json)
+                .map_err(|err| PatchError::Malformed(format!("invalid JSON patch: {}", err))),
+            Some(other) => Err(PatchError::UnsupportedMediaType(format!(
+                "unsupported patch media type '{}' (expected {} or {})",
+                other, MERGE_PATCH, JSON_PATCH
+            ))),
+            None => Err(PatchError::UnsupportedMediaType(format!(
+                "{} is required (expected {} or {})",
+                header::CONTENT_TYPE,
+                MERGE_PATCH,
+                JSON_PATCH
+            ))),
+        }
+    }
+
+    // Apply the patch to `task`, returning every writable field of the result
+    pub fn apply(&self, task: &Task) -> Result<TaskUpdate, PatchError> {
+        let original = serde_json::to_value(task).expect("tasks serialize to JSON");
+        let mut patched = original.clone();
+
+        match self {
+            TaskPatch::Merge(patch) => json_patch::merge(&mut patched, patch),
+            TaskPatch::Json(patch) => {
+                json_patch::patch(&mut patched, patch).map_err(|err| match err.kind {
+                    PatchErrorKind::TestFailed => PatchError::TestFailed(err.to_string()),
+                    _ => PatchError::Unprocessable(err.to_string()),
+                })?
+            }
+        }
+
+        validate(&original, patched)
+    }
+}
+
+// Turn a patched task document back into a full update
+fn validate(original: &Value, patched: Value) -> Result<TaskUpdate, PatchError> {
+    let invalid = |msg: String| Err(PatchError::Unprocessable(msg));
+    let Value::Object(mut fields) = patched else {
+        return invalid("patched task must be a JSON object".to_string());
+    };
+
+    for name in READ_ONLY_FIELDS {
+        if fields.remove(name).as_ref() != original.get(name) {
+            return invalid(format!("field '{}' is read-only", name));
+        }
+    }
+
+    let title = match fields.remove("title") {
+        Some(Value::String(title)) => match validate_title(&title) {
+            Ok(()) => title,
+            Err(err) => return invalid(err),
+        },
+        None | Some(Value::Null) => return invalid("title is required".to_string()),
+        Some(_) => return invalid("title must be a string".to_string()),
+    };
+    let description = match fields.remove("description") {
+        Some(Value::String(description)) => description,
+        None | Some(Value::Null) => String::new(),
+        Some(_) => return invalid("description must be a string".to_string()),
+    };
+    let completed = match fields.remove("completed") {
+        Some(Value::Bool(completed)) => completed,
+        None | Some(Value::Null) => false,
+        Some(_) => return invalid("completed must be a boolean".to_string()),
+    };
+    let priority = match fields.remove("priority") {
+        Some(Value::String(priority)) => match priority.parse::<Priority>() {
+            Ok(priority) => priority,
+            Err(err) => return invalid(err),
+        },
+        None | Some(Value::Null) => Priority::default(),
+        Some(_) => return invalid("priority must be a string".to_string()),
+    };
+    let tags = match fields.remove("tags") {
+        Some(Value::Array(values)) => {
+            let names = values
+                .into_iter()
+                .map(|value| match value {
+                    Value::String(tag) => Ok(tag),
+                    _ => Err("tags must be strings".to_string()),
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .and_then(tags::normalize_all);
+            match names {
+                Ok(names) => names,
+                Err(err) => return invalid(err),
+            }
+        }
+        None | Some(Value::Null) => Vec::new(),
+        Some(_) => return invalid("tags must be an array".to_string()),
+    };
+    let due_at = match fields.remove("due_at") {
+        Some(Value::String(due_at)) => match DateTime::parse_from_rfc3339(&due_at) {
+            Ok(due_at) => Some(due_at.with_timezone(&Utc)),
+            Err(_) => {
+                return invalid("due_at must be an RFC 3339 date-time with an offset".to_string())
+            }
+        },
+        None | Some(Value::Null) => None,
+        Some(_) => return invalid("due_at must be a string".to_string()),
+    };
+
+    if let Some(name) = fields.keys().next() {
+        return invalid(format!("unknown field '{}'", name));
+    }
+
+    Ok(TaskUpdate {
+        title: Some(title),
+        description: Some(description),
+        completed: Some(completed),
+        priority: Some(priority),
+        tags: Some(tags),
+        due_at: Some(due_at),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{TaskCreate, MAX_TITLE_LENGTH};
+
+    fn task() -> Task {
+        Task::new(TaskCreate {
+            title: "Ship".to_string(),
+            description: "Cut the release".to_string(),
+            priority: Priority::High,
+            tags: vec!["release".to_string()],
+            due_at: Some("2030-01-02T03:04:05Z".parse().unwrap()),
+        })
+    }
+
+    // Parse and apply a patch, returning the task it would produce
+    fn patched(media_type: &str, body: &str) -> Result<Task, PatchError> {
+        let mut task = task();
+        let update = TaskPatch::parse(Some(media_type), body.as_bytes())?.apply(&task)?;
+        update.apply(&mut task);
+        Ok(task)
+    }
+
+    fn unprocessable(media_type: &str, body: &str) -> String {
+        match patched(media_type, body) {
+            Err(PatchError::Unprocessable(msg)) => msg,
+            other => panic!("expected 422 for {}, got {:?}", body, other),
+        }
+    }
+
+    #[test]
+    fn merge_patch_keeps_omitted_fields_and_resets_nulls() {
+        let task = patched(
+            MERGE_PATCH,
+            r#"{"title": "Ship it", "description": null, "tags": ["Docs", "release"]}"#,
+        )
+        .unwrap();
+        assert_eq!(task.title, "Ship it");
+        assert_eq!(task.description, "");
+        assert_eq!(task.priority, Priority::High);
+        assert_eq!(task.tags, ["docs", "release"]);
+        assert!(task.due_at.is_some());
+
+        let task = patched(MERGE_PATCH, r#"{"priority": null, "due_at": null}"#).unwrap();
+        assert_eq!(task.priority, Priority::Normal);
+        assert_eq!(task.due_at, None);
+    }
+
+    #[test]
+    fn json_patch_adds_replaces_and_removes() {
+        let task = patched(
+            JSON_PATCH,
+            r#"[
+                {"op": "test", "path": "/title", "value": "Ship"},
+                {"op": "replace", "path": "/completed", "value": true},
+                {"op": "add", "path": "/tags/-", "value": "Ops"},
+                {"op": "remove", "path": "/description"},
+                {"op": "replace", "path": "/due_at", "value": "2030-01-02T05:04:05+02:00"}
+            ]"#,
+        )
+        .unwrap();
+        assert!(task.completed);
+        assert_eq!(task.tags, ["ops", "release"]);
+        assert_eq!(task.description, "");
+        assert_eq!(task.due_at, Some("2030-01-02T03:04:05Z".parse().unwrap()));
+    }
+
+    #[test]
+    fn failed_test_operation_is_a_conflict() {
+        let err = patched(
+            JSON_PATCH,
+            r#"[
+                {"op": "test", "path": "/title", "value": "Something else"},
+                {"op": "replace", "path": "/title", "value": "Overwritten"}
+            ]"#,
+        )
+        .unwrap_err();
+        assert!(matches!(err, PatchError::TestFailed(_)), "{:?}", err);
+        assert_eq!(err.status_code(), StatusCode::CONFLICT);
+    }
+
+    #[test]
+    fn rejects_patches_to_read_only_fields() {
+        for (media_type, body, field) in [
+            (MERGE_PATCH, r#"{"version": 7}"#, "version"),
+            (MERGE_PATCH, r#"{"overdue": true}"#, "overdue"),
+            (JSON_PATCH, r#"[{"op": "remove", "path": "/id"}]"#, "id"),
+            (JSON_PATCH, r#"[{"op": "add", "path": "/deleted_at", "value": null}]"#, "deleted_at"),
+        ] {
+            let message = unprocessable(media_type, body);
+            assert_eq!(message, format!("field '{}' is read-only", field));
+        }
+    }
+
+    #[test]
+    fn rejects_patches_that_leave_an_invalid_task() {
+        let long_title = format!(r#"{{"title": "{}"}}"#, "x".repeat(MAX_TITLE_LENGTH + 1));
+        for (media_type, body, expected) in [
+            (MERGE_PATCH, r#"{"title": null}"#, "title is required"),
+            (MERGE_PATCH, r#"{"title": 7}"#, "title must be a string"),
+            (MERGE_PATCH, &long_title, "title must be at most 255 characters"),
+            (MERGE_PATCH, r#"{"owner": "me"}"#, "unknown field 'owner'"),
+            (MERGE_PATCH, r#"{"tags": "ops"}"#, "tags must be an array"),
+            (
+                MERGE_PATCH,
+                r#"{"due_at": "tomorrow"}"#,
+                "due_at must be an RFC 3339 date-time with an offset",
+            ),
+            (JSON_PATCH, r#"[{"op": "remove", "path": "/title"}]"#, "title is required"),
+        ] {
+            assert_eq!(unprocessable(media_type, body), expected);
+        }
+        // A patch that cannot be applied at all
+        let message = unprocessable(JSON_PATCH, r#"[{"op": "remove", "path": "/missing"}]"#);
+        assert!(message.contains("/missing"), "{}", message);
+    }
+
+    #[test]
+    fn rejects_unsupported_media_types_and_malformed_bodies() {
+        let err = TaskPatch::parse(Some("application/json"), b"{}").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "unsupported patch media type 'application/json' (expected \
+             application/merge-patch+json or application/json-patch+json)"
+        );
+        let response = err.error_response();
+        assert_eq!(response.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(
+            response.headers().get("Accept-Patch").unwrap(),
+            "application/merge-patch+json, application/json-patch+json"
+        );
+
+        let err = TaskPatch::parse(None, b"{}").unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert!(err.to_string().starts_with("content-type is required"), "{}", err);
+
+        for media_type in [MERGE_PATCH, JSON_PATCH] {
+            let err = TaskPatch::parse(Some(media_type), b"{not json").unwrap_err();
+            assert!(matches!(err, PatchError::Malformed(_)), "{:?}", err);
+            assert_eq!(err.error_response().status(), StatusCode::BAD_REQUEST);
+            assert!(err.error_response().headers().get("Accept-Patch").is_none());
+        }
+        // A JSON Patch must be an array of operations
+        let err = TaskPatch::parse(Some(JSON_PATCH), br#"{"op": "remove"}"#).unwrap_err();
+        assert!(matches!(err, PatchError::Malformed(_)), "{:?}", err);
+    }
+}
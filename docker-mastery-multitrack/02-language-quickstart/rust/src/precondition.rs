@@ -0,0 +1,189 @@
+/*!
+ * Optimistic concurrency for tasks
+ *
+ * Every write bumps a task's version, which is sent as a strong ETag. PUT,
+ * PATCH and DELETE honour `If-Match` and answer 412 once the task has moved
+ * on; with REQUIRE_IF_MATCH=true they refuse to run without it (428). GET
+ * answers a matching `If-None-Match` with 304.
+ */
+
+use actix_web::http::header::{self, EntityTag, Header, IfMatch, IfNoneMatch};
+use actix_web::{http::StatusCode, HttpRequest, HttpResponse, ResponseError};
+use std::fmt;
+
+use crate::models::Task;
+
+// Precondition settings read from the environment
+#[derive(Debug, Clone, Copy)]
+pub struct PreconditionConfig {
+    // Reject unconditional writes so clients cannot overwrite blindly
+    pub require_if_match: bool,
+}
+
+impl PreconditionConfig {
+    pub fn from_env() -> Self {
+        PreconditionConfig {
+            require_if_match: std::env::var("REQUIRE_IF_MATCH")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(false),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum PreconditionError {
+    // If-Match did not match the task's current ETag
+    Failed,
+    // REQUIRE_IF_MATCH is set and the request had no If-Match
+    Required,
+    Malformed(String),
+}
+
+impl fmt::Display for PreconditionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PreconditionError::Failed => write!(f, "task has been modified since it was read"),
+            PreconditionError::Required => write!(f, "If-Match is required to modify a task"),
+            PreconditionError::Malformed(msg) => write!(f, "invalid If-Match: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for PreconditionError {}
+
+impl ResponseError for PreconditionError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            PreconditionError::Failed => StatusCode::PRECONDITION_FAILED,
+            PreconditionError::Required => StatusCode::PRECONDITION_REQUIRED,
+            PreconditionError::Malformed(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(serde_json::json!({
+            "error": self.to_string()
+        }))
+    }
+}
+
+pub fn etag(task: &Task) -> header::ETag {
+    header::ETag(EntityTag::new_strong(task.version.to_string()))
+}
+
+// The request's If-Match condition, or `None` for an unconditional write
+pub fn if_match(
+    req: &HttpRequest,
+    config: &PreconditionConfig,
+) -> Result<Option<IfMatch>, PreconditionError> {
+    if !req.headers().contains_key(header::IF_MATCH) {
+        return if config.require_if_match {
+            Err(PreconditionError::Required)
+        } else {
+            Ok(None)
+        };
+    }
+
+    IfMatch::parse(req)
+        .map(Some)
+        .map_err(|err| PreconditionError::Malformed(err.to_string()))
+}
+
+// If-Match uses the strong comparison, so weak tags never match
+pub fn check(condition: &IfMatch, task: &Task) -> Result<(), PreconditionError> {
+    let matched = match condition {
+        IfMatch::Any => true,
+        IfMatch::Items(tags) => {
+            let current = etag(task).0;
+            tags.iter().any(|tag| tag.strong_eq(&current))
+        }
+    };
+    if matched {
+        Ok(())
+    } else {
+        Err(PreconditionError::Failed)
+    }
+}
+
+// Whether a GET can answer 304 Not Modified. If-None-Match uses the weak
+// comparison; an unparseable header is ignored.
+pub fn not_modified(req: &HttpRequest, task: &Task) -> bool {
+    if !req.headers().contains_key(header::IF_NONE_MATCH) {
+        return false;
+    }
+
+    match IfNoneMatch::parse(req) {
+        Ok(IfNoneMatch::Any) => true,
+        Ok(IfNoneMatch::Items(tags)) => {
+            let current = etag(task).0;
+            tags.iter().any(|tag| tag.weak_eq(&current))
+        }
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    use crate::models::{Priority, TaskCreate};
+
+    fn task() -> Task {
+        let mut task = Task::new(TaskCreate {
+            title: "Ship".to_string(),
+            description: String::new(),
+            priority: Priority::Normal,
+            tags: Vec::new(),
+            due_at: None,
+        });
+        task.version = 3;
+        task
+    }
+
+    fn request(name: header::HeaderName, value: &str) -> HttpRequest {
+        TestRequest::default().insert_header((name, value)).to_http_request()
+    }
+
+    fn if_match_check(value: &str) -> Result<(), PreconditionError> {
+        let config = PreconditionConfig { require_if_match: false };
+        let condition = if_match(&request(header::IF_MATCH, value), &config)?.unwrap();
+        check(&condition, &task())
+    }
+
+    #[test]
+    fn if_match_uses_the_strong_comparison() {
+        assert!(if_match_check("\"3\"").is_ok());
+        assert!(if_match_check("\"1\", \"3\"").is_ok());
+        assert!(if_match_check("*").is_ok());
+        for stale in ["\"2\"", "W/\"3\""] {
+            let err = if_match_check(stale).unwrap_err();
+            assert_eq!(err.status_code(), StatusCode::PRECONDITION_FAILED, "{}", stale);
+        }
+    }
+
+    #[test]
+    fn if_match_can_be_required() {
+        let req = TestRequest::default().to_http_request();
+        let optional = PreconditionConfig { require_if_match: false };
+        assert!(if_match(&req, &optional).unwrap().is_none());
+
+        let required = PreconditionConfig { require_if_match: true };
+        let err = if_match(&req, &required).unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::PRECONDITION_REQUIRED);
+        assert!(if_match(&request(header::IF_MATCH, "\"3\""), &required).unwrap().is_some());
+    }
+
+    #[test]
+    fn if_none_match_uses_the_weak_comparison() {
+        let task = task();
+        for value in ["\"3\"", "W/\"3\"", "\"1\", W/\"3\"", "*"] {
+            assert!(not_modified(&request(header::IF_NONE_MATCH, value), &task), "{}", value);
+        }
+        for value in ["\"2\"", "not an etag"] {
+            assert!(!not_modified(&request(header::IF_NONE_MATCH, value), &task), "{}", value);
+        }
+        assert!(!not_modified(&TestRequest::default().to_http_request(), &task));
+    }
+}
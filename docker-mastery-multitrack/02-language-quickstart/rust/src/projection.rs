@@ -0,0 +1,189 @@
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::models::Task;
+use crate::query::QueryError;
+
+// Query string parameters for GET /api/tasks/{id}
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldsParams {
+    pub fields: Option<String>,
+}
+
+// Task fields that can be selected with `fields=`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskField {
+    Id,
+    Title,
+    Description,
+    Completed,
+    Priority,
+    Tags,
+    DueAt,
+    Overdue,
+    CreatedAt,
+    UpdatedAt,
+    Version,
+    DeletedAt,
+}
+
+// In the order they appear in a serialized `Task`
+const TASK_FIELDS: [TaskField; 12] = [
+    TaskField::Id,
+    TaskField::Title,
+    TaskField::Description,
+    TaskField::Completed,
+    TaskField::Priority,
+    TaskField::Tags,
+    TaskField::DueAt,
+    TaskField::Overdue,
+    TaskField::CreatedAt,
+    TaskField::UpdatedAt,
+    TaskField::Version,
+    TaskField::DeletedAt,
+];
+
+impl TaskField {
+    pub fn name(self) -> &'static str {
+        match self {
+            TaskField::Id => "id",
+            TaskField::Title => "title",
+            TaskField::Description => "description",
+            TaskField::Completed => "completed",
+            TaskField::Priority => "priority",
+            TaskField::Tags => "tags",
+            TaskField::DueAt => "due_at",
+            TaskField::Overdue => "overdue",
+            TaskField::CreatedAt => "created_at",
+            TaskField::UpdatedAt => "updated_at",
+            TaskField::Version => "version",
+            TaskField::DeletedAt => "deleted_at",
+        }
+    }
+
+    pub fn value(self, task: &Task) -> Value {
+        match self {
+            TaskField::Id => Value::from(task.id.as_str()),
+            TaskField::Title => Value::from(task.title.as_str()),
+            TaskField::Description => Value::from(task.description.as_str()),
+            TaskField::Completed => Value::from(task.completed),
+            TaskField::Priority => Value::from(task.priority.name()),
+            TaskField::Tags => Value::from(task.tags.clone()),
+            TaskField::DueAt => serde_json::to_value(task.due_at).unwrap_or_default(),
+            TaskField::Overdue => Value::from(task.is_overdue(Utc::now())),
+            TaskField::CreatedAt => serde_json::to_value(task.created_at).unwrap_or_default(),
+            TaskField::UpdatedAt => serde_json::to_value(task.updated_at).unwrap_or_default(),
+            TaskField::Version => Value::from(task.version),
+            TaskField::DeletedAt => serde_json::to_value(task.deleted_at).unwrap_or_default(),
+        }
+    }
+}
+
+// The subset of task fields a client asked for with `fields=id,title,...`.
+// Only those fields are ever serialized, so a skipped description costs
+// nothing.
+#[derive(Debug, Clone)]
+pub struct Projection {
+    fields: Vec<TaskField>,
+}
+
+impl Projection {
+    // `None` when the parameter is absent, meaning every field
+    pub fn from_param(value: Option<&str>) -> Result<Option<Self>, QueryError> {
+        value.map(Self::parse).transpose()
+    }
+
+    fn parse(value: &str) -> Result<Self, QueryError> {
+        let mut requested = Vec::new();
+        for name in value.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            let field = TASK_FIELDS
+                .into_iter()
+                .find(|field| field.name() == name)
+                .ok_or_else(|| {
+                    let known: Vec<_> = TASK_FIELDS.iter().map(|field| field.name()).collect();
+                    QueryError::new(format!(
+                        "unknown field '{}' in fields (expected {})",
+                        name,
+                        known.join(", ")
+                    ))
+                })?;
+            requested.push(field);
+        }
+        if requested.is_empty() {
+            return Err(QueryError::new("fields must name at least one field"));
+        }
+
+        // Each field once, however often it was asked for
+        let fields = TASK_FIELDS
+            .into_iter()
+            .filter(|field| requested.contains(field))
+            .collect();
+        Ok(Projection { fields })
+    }
+
+    pub fn apply(&self, task: &Task) -> Value {
+        let object: Map<String, Value> = self
+            .fields
+            .iter()
+            .map(|field| (field.name().to_string(), field.value(task)))
+            .collect();
+        Value::Object(object)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{Priority, TaskCreate};
+
+    fn task() -> Task {
+        let mut task = Task::new(TaskCreate {
+            title: "Ship".to_string(),
+            description: "A long description nobody asked for".to_string(),
+            priority: Priority::High,
+            tags: vec!["release".to_string()],
+            due_at: Some("2020-01-02T03:04:05Z".parse().unwrap()),
+        });
+        task.set_deleted(true);
+        task
+    }
+
+    fn project(fields: &str, task: &Task) -> Value {
+        Projection::from_param(Some(fields)).unwrap().unwrap().apply(task)
+    }
+
+    #[test]
+    fn keeps_only_the_requested_fields() {
+        let task = task();
+        let projected = project(" title ,id,,title", &task);
+        assert_eq!(projected, serde_json::json!({ "id": task.id, "title": "Ship" }));
+        assert!(Projection::from_param(None).unwrap().is_none());
+    }
+
+    #[test]
+    fn projects_each_field_as_the_full_task_shows_it() {
+        let task = task();
+        let full = serde_json::to_value(&task).unwrap();
+        for field in TASK_FIELDS {
+            let projected = project(field.name(), &task);
+            assert_eq!(projected[field.name()], full[field.name()], "{}", field.name());
+        }
+
+        let every: Vec<_> = TASK_FIELDS.iter().map(|field| field.name()).collect();
+        assert_eq!(project(&every.join(","), &task), full);
+    }
+
+    #[test]
+    fn rejects_unknown_and_empty_field_lists() {
+        let err = Projection::from_param(Some("id,owner")).unwrap_err();
+        assert_eq!(
+            err.message,
+            "unknown field 'owner' in fields (expected id, title, description, completed, \
+             priority, tags, due_at, overdue, created_at, updated_at, version, deleted_at)"
+        );
+        let err = Projection::from_param(Some(" , ")).unwrap_err();
+        assert_eq!(err.message, "fields must name at least one field");
+    }
+}
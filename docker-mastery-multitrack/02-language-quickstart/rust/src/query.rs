@@ -0,0 +1,700 @@
+/*!
+ * Query model for listing tasks
+ *
+ * `list_tasks` parses the query string into `ListParams` and validates it into
+ * a `TaskQuery` (filters, sort keys and a page position), which every storage
+ * backend applies the same way. Tasks are ordered by the requested sort keys
+ * with the id as the final tie-breaker, so the order is always total.
+ *
+ * The opaque cursor holds the sort values and id of the last task on the
+ * previous page, so pages stay stable while tasks are created or deleted
+ * concurrently. A cursor is only valid for the sort order it was issued for.
+ */
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::fmt;
+use uuid::Uuid;
+
+use crate::filter::FilterExpr;
+use crate::models::{Priority, Task};
+use crate::tags;
+
+pub const DEFAULT_PAGE_SIZE: usize = 50;
+pub const MAX_PAGE_SIZE: usize = 100;
+
+// A query string that could not be turned into a valid query
+#[derive(Debug)]
+pub struct QueryError {
+    pub message: String,
+    // Character offset into a filter expression, when the error has one
+    pub position: Option<usize>,
+}
+
+impl QueryError {
+    pub fn new(message: impl Into<String>) -> Self {
+        QueryError {
+            message: message.into(),
+            position: None,
+        }
+    }
+
+    pub fn at(position: usize, message: impl Into<String>) -> Self {
+        QueryError {
+            message: message.into(),
+            position: Some(position),
+        }
+    }
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.position {
+            Some(position) => write!(f, "{} at position {}", self.message, position),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+impl ResponseError for QueryError {
+    fn status_code(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut body = serde_json::json!({ "error": self.to_string() });
+        if let Some(position) = self.position {
+            body["position"] = position.into();
+        }
+        HttpResponse::build(self.status_code()).json(body)
+    }
+}
+
+// Validate a `limit` parameter against a hard maximum
+pub fn parse_limit(limit: Option<usize>, default: usize, max: usize) -> Result<usize, QueryError> {
+    match limit {
+        None => Ok(default),
+        Some(0) => Err(QueryError::new("limit must be at least 1")),
+        Some(limit) if limit > max => Err(QueryError::new(format!("limit must be at most {}", max))),
+        Some(limit) => Ok(limit),
+    }
+}
+
+// Raw query string parameters for GET /api/tasks
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ListParams {
+    pub limit: Option<usize>,
+    pub cursor: Option<String>,
+    pub completed: Option<bool>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub updated_after: Option<DateTime<Utc>>,
+    pub updated_before: Option<DateTime<Utc>>,
+    pub due_before: Option<DateTime<Utc>>,
+    pub overdue: Option<bool>,
+    // Comma-separated priorities, any of which a task may have
+    pub priority: Option<String>,
+    // Comma-separated tags; a task needs any one of them, or all of them
+    pub tags_any: Option<String>,
+    pub tags_all: Option<String>,
+    // Comma-separated fields, each optionally prefixed with `-` for descending
+    pub sort: Option<String>,
+    // Expression in the filter language, see src/filter.rs
+    pub filter: Option<String>,
+    // Sparse fieldset for the listed tasks, see src/projection.rs
+    pub fields: Option<String>,
+}
+
+// Fields a task list can be sorted by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortField {
+    CreatedAt,
+    UpdatedAt,
+    Title,
+    Completed,
+    Priority,
+    // Tasks without a due date sort as if due at `no_due_date()`, so last
+    // in ascending order
+    DueAt,
+}
+
+// Stands in for a missing due date when sorting
+pub fn no_due_date() -> DateTime<Utc> {
+    NaiveDate::from_ymd_opt(9999, 12, 31)
+        .and_then(|date| date.and_hms_opt(23, 59, 59))
+        .expect("a valid date")
+        .and_utc()
+}
+
+impl SortField {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "created_at" => Some(SortField::CreatedAt),
+            "updated_at" => Some(SortField::UpdatedAt),
+            "title" => Some(SortField::Title),
+            "completed" => Some(SortField::Completed),
+            "priority" => Some(SortField::Priority),
+            "due_at" => Some(SortField::DueAt),
+            _ => None,
+        }
+    }
+
+    // Field name as used in the query string and as the column name
+    pub fn name(self) -> &'static str {
+        match self {
+            SortField::CreatedAt => "created_at",
+            SortField::UpdatedAt => "updated_at",
+            SortField::Title => "title",
+            SortField::Completed => "completed",
+            SortField::Priority => "priority",
+            SortField::DueAt => "due_at",
+        }
+    }
+
+    pub fn value(self, task: &Task) -> SortValue {
+        match self {
+            SortField::CreatedAt => SortValue::Time(task.created_at),
+            SortField::UpdatedAt => SortValue::Time(task.updated_at),
+            SortField::Title => SortValue::Text(task.title.clone()),
+            SortField::Completed => SortValue::Bool(task.completed),
+            SortField::Priority => SortValue::Priority(task.priority),
+            SortField::DueAt => SortValue::Time(task.due_at.unwrap_or_else(no_due_date)),
+        }
+    }
+
+    // Read back a value stored in a cursor
+    fn decode(self, value: serde_json::Value) -> Option<SortValue> {
+        match self {
+            SortField::CreatedAt | SortField::UpdatedAt | SortField::DueAt => {
+                serde_json::from_value(value).ok().map(SortValue::Time)
+            }
+            SortField::Title => serde_json::from_value(value).ok().map(SortValue::Text),
+            SortField::Completed => serde_json::from_value(value).ok().map(SortValue::Bool),
+            SortField::Priority => serde_json::from_value(value).ok().map(SortValue::Priority),
+        }
+    }
+}
+
+// Value of a sort field. Values of one field are always the same variant.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(untagged)]
+pub enum SortValue {
+    Bool(bool),
+    Time(DateTime<Utc>),
+    // Compared bytewise, which every backend is told to do as well
+    Text(String),
+    // Compared from low to urgent; stored as its rank
+    Priority(Priority),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortKey {
+    pub field: SortField,
+    pub descending: bool,
+}
+
+impl SortKey {
+    // Parse a `sort` parameter such as `-updated_at,title`
+    fn parse_list(value: &str) -> Result<Vec<SortKey>, QueryError> {
+        let mut keys: Vec<SortKey> = Vec::new();
+
+        for part in value.split(',') {
+            let (name, descending) = match part.strip_prefix('-') {
+                Some(name) => (name, true),
+                None => (part, false),
+            };
+            let field = SortField::parse(name).ok_or_else(|| {
+                QueryError::new(format!(
+                    "unknown sort field '{}' \
+                     (expected created_at, updated_at, title, completed, priority or due_at)",
+                    name
+                ))
+            })?;
+            if keys.iter().any(|key| key.field == field) {
+                return Err(QueryError::new(format!("sort field '{}' given more than once", name)));
+            }
+            keys.push(SortKey { field, descending });
+        }
+
+        Ok(keys)
+    }
+
+    fn apply(self, ordering: Ordering) -> Ordering {
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+}
+
+// Parse a `priority` parameter such as `high,urgent`
+fn parse_priorities(value: &str) -> Result<Vec<Priority>, QueryError> {
+    value
+        .split(',')
+        .map(|name| name.trim().parse::<Priority>().map_err(QueryError::new))
+        .collect()
+}
+
+fn sort_spec(keys: &[SortKey]) -> String {
+    keys.iter()
+        .map(|key| format!("{}{}", if key.descending { "-" } else { "" }, key.field.name()))
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// Conditions a task must meet to be listed. Time bounds are exclusive, and
+// a due date bound only matches tasks that have one.
+#[derive(Debug, Clone, Default)]
+pub struct TaskFilter {
+    // List the trash instead of live tasks
+    pub trashed: bool,
+    pub completed: Option<bool>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    pub updated_after: Option<DateTime<Utc>>,
+    pub updated_before: Option<DateTime<Utc>>,
+    pub due_before: Option<DateTime<Utc>>,
+    pub overdue: Option<bool>,
+    // Tasks with any of these priorities
+    pub priority: Option<Vec<Priority>>,
+    // Tasks with at least one of these tags, and tasks with every one of
+    // these tags. Normalized and without duplicates.
+    pub tags_any: Option<Vec<String>>,
+    pub tags_all: Option<Vec<String>>,
+    // The moment `overdue` is judged at, fixed when the query is made
+    pub now: DateTime<Utc>,
+    pub expression: Option<FilterExpr>,
+}
+
+impl TaskFilter {
+    pub fn matches(&self, task: &Task) -> bool {
+        task.is_deleted() == self.trashed
+            && self.completed.is_none_or(|completed| task.completed == completed)
+            && self.created_after.is_none_or(|after| task.created_at > after)
+            && self.created_before.is_none_or(|before| task.created_at < before)
+            && self.updated_after.is_none_or(|after| task.updated_at > after)
+            && self.updated_before.is_none_or(|before| task.updated_at < before)
+            && self.due_before.is_none_or(|before| task.due_at.is_some_and(|due| due < before))
+            && self.overdue.is_none_or(|overdue| task.is_overdue(self.now) == overdue)
+            && self.priority.as_ref().is_none_or(|priorities| priorities.contains(&task.priority))
+            && self
+                .tags_any
+                .as_ref()
+                .is_none_or(|tags| tags.iter().any(|tag| task.tags.contains(tag)))
+            && self
+                .tags_all
+                .as_ref()
+                .is_none_or(|tags| tags.iter().all(|tag| task.tags.contains(tag)))
+            && self.expression.as_ref().is_none_or(|expression| expression.matches(task))
+    }
+}
+
+// Position just after a task in a given sort order
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    // One value per sort key
+    pub values: Vec<SortValue>,
+    pub id: String,
+}
+
+// What actually goes over the wire, base64url-encoded
+#[derive(Serialize, Deserialize)]
+struct EncodedCursor {
+    sort: String,
+    values: Vec<serde_json::Value>,
+    id: String,
+}
+
+impl Cursor {
+    pub fn after(task: &Task, sort: &[SortKey]) -> Self {
+        Cursor {
+            values: sort.iter().map(|key| key.field.value(task)).collect(),
+            id: task.id.clone(),
+        }
+    }
+
+    fn encode(&self, sort: &[SortKey]) -> String {
+        let encoded = EncodedCursor {
+            sort: sort_spec(sort),
+            values: self
+                .values
+                .iter()
+                .map(|value| serde_json::to_value(value).expect("sort values serialize to JSON"))
+                .collect(),
+            id: self.id.clone(),
+        };
+        let json = serde_json::to_vec(&encoded).expect("cursor serializes to JSON");
+        URL_SAFE_NO_PAD.encode(json)
+    }
+
+    fn decode(value: &str, sort: &[SortKey]) -> Result<Self, QueryError> {
+        let invalid = || QueryError::new("invalid cursor");
+
+        let json = URL_SAFE_NO_PAD.decode(value).map_err(|_| invalid())?;
+        let encoded: EncodedCursor = serde_json::from_slice(&json).map_err(|_| invalid())?;
+
+        if encoded.sort != sort_spec(sort) {
+            return Err(QueryError::new(format!(
+                "cursor was issued for sort '{}' and cannot be used with sort '{}'",
+                encoded.sort,
+                sort_spec(sort)
+            )));
+        }
+        if encoded.values.len() != sort.len() {
+            return Err(invalid());
+        }
+        // Task ids are always UUIDs; PostgreSQL would reject anything else
+        Uuid::parse_str(&encoded.id).map_err(|_| invalid())?;
+
+        let values = sort
+            .iter()
+            .zip(encoded.values)
+            .map(|(key, value)| key.field.decode(value))
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(invalid)?;
+
+        Ok(Cursor {
+            values,
+            id: encoded.id,
+        })
+    }
+}
+
+// A validated list query
+#[derive(Debug, Clone)]
+pub struct TaskQuery {
+    pub filter: TaskFilter,
+    // Never empty; the id is appended as an implicit final ascending key
+    pub sort: Vec<SortKey>,
+    pub limit: usize,
+    pub after: Option<Cursor>,
+}
+
+impl TryFrom<ListParams> for TaskQuery {
+    type Error = QueryError;
+
+    fn try_from(params: ListParams) -> Result<Self, Self::Error> {
+        let limit = parse_limit(params.limit, DEFAULT_PAGE_SIZE, MAX_PAGE_SIZE)?;
+
+        let sort = match params.sort.as_deref() {
+            None => vec![SortKey {
+                field: SortField::CreatedAt,
+                descending: false,
+            }],
+            Some(value) => SortKey::parse_list(value)?,
+        };
+
+        let after = params
+            .cursor
+            .as_deref()
+            .map(|cursor| Cursor::decode(cursor, &sort))
+            .transpose()?;
+
+        Ok(TaskQuery {
+            filter: TaskFilter {
+                trashed: false,
+                completed: params.completed,
+                created_after: params.created_after,
+                created_before: params.created_before,
+                updated_after: params.updated_after,
+                updated_before: params.updated_before,
+                due_before: params.due_before,
+                overdue: params.overdue,
+                priority: params.priority.as_deref().map(parse_priorities).transpose()?,
+                tags_any: params.tags_any.as_deref().map(tags::parse_list).transpose()?,
+                tags_all: params.tags_all.as_deref().map(tags::parse_list).transpose()?,
+                now: Utc::now(),
+                expression: params.filter.as_deref().map(FilterExpr::parse).transpose()?,
+            },
+            sort,
+            limit,
+            after,
+        })
+    }
+}
+
+impl TaskQuery {
+    // Order of two tasks under this query's sort keys
+    pub fn compare(&self, a: &Task, b: &Task) -> Ordering {
+        self.sort
+            .iter()
+            .map(|key| key.apply(key.field.value(a).cmp(&key.field.value(b))))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| a.id.cmp(&b.id))
+    }
+
+    // Whether `task` sorts after the cursor position
+    fn is_after_cursor(&self, task: &Task) -> bool {
+        let Some(after) = &self.after else {
+            return true;
+        };
+
+        self.sort
+            .iter()
+            .zip(&after.values)
+            .map(|(key, value)| key.apply(key.field.value(task).cmp(value)))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| task.id.as_str().cmp(after.id.as_str()))
+            .is_gt()
+    }
+
+    // Apply the query to tasks held in memory
+    pub fn paginate(&self, tasks: impl IntoIterator<Item = Task>) -> TaskPage {
+        let mut tasks: Vec<Task> = tasks
+            .into_iter()
+            .filter(|task| self.filter.matches(task))
+            .collect();
+        let total = tasks.len();
+
+        tasks.retain(|task| self.is_after_cursor(task));
+        tasks.sort_unstable_by(|a, b| self.compare(a, b));
+        tasks.truncate(self.limit + 1);
+
+        TaskPage::new(tasks, self, total)
+    }
+}
+
+// Query for the incomplete task to work on next: the highest priority first,
+// then the earliest due date, with tasks that have none last, then the oldest
+pub fn next_task() -> TaskQuery {
+    let key = |field, descending| SortKey { field, descending };
+    TaskQuery {
+        filter: TaskFilter {
+            completed: Some(false),
+            now: Utc::now(),
+            ..Default::default()
+        },
+        sort: vec![
+            key(SortField::Priority, true),
+            key(SortField::DueAt, false),
+            key(SortField::CreatedAt, false),
+        ],
+        limit: 1,
+        after: None,
+    }
+}
+
+// One page of tasks. `total` counts every task matching the filter, not just
+// this page.
+#[derive(Debug, Serialize)]
+pub struct TaskPage {
+    pub tasks: Vec<Task>,
+    pub total: usize,
+    pub next_cursor: Option<String>,
+}
+
+impl TaskPage {
+    // `tasks` holds up to `limit + 1` sorted tasks; the extra one only tells
+    // us there is another page
+    pub fn new(mut tasks: Vec<Task>, query: &TaskQuery, total: usize) -> Self {
+        let next_cursor = if tasks.len() > query.limit {
+            tasks.truncate(query.limit);
+            tasks
+                .last()
+                .map(|task| Cursor::after(task, &query.sort).encode(&query.sort))
+        } else {
+            None
+        };
+
+        TaskPage {
+            tasks,
+            total,
+            next_cursor,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::TaskCreate;
+    use crate::storage::{InMemoryTaskRepository, TaskRepository};
+
+    fn task(title: &str, priority: Priority, due_at: Option<&str>) -> Task {
+        Task::new(TaskCreate {
+            title: title.to_string(),
+            description: String::new(),
+            priority,
+            tags: Vec::new(),
+            due_at: due_at.map(|due_at| due_at.parse().unwrap()),
+        })
+    }
+
+    // A list query as parsed from a query string
+    fn list_query(query: &str) -> Result<TaskQuery, QueryError> {
+        let params = actix_web::web::Query::<ListParams>::from_query(query).unwrap();
+        TaskQuery::try_from(params.into_inner())
+    }
+
+    // Every page of `query` over `tasks`, following cursors
+    fn all_pages(tasks: &[Task], sort: &str) -> Vec<Vec<String>> {
+        let mut pages = Vec::new();
+        let mut cursor = String::new();
+        loop {
+            let query = list_query(&format!("sort={}&limit=2{}", sort, cursor)).unwrap();
+            let page = query.paginate(tasks.iter().cloned());
+            assert_eq!(page.total, tasks.len());
+            pages.push(page.tasks.iter().map(|task| task.title.clone()).collect());
+            match page.next_cursor {
+                Some(next) => cursor = format!("&cursor={}", next),
+                None => return pages,
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_a_cursor_for_its_own_sort_only() {
+        let mut task = task("Ship", Priority::High, Some("2030-01-02T03:04:05Z"));
+        task.completed = true;
+        let sort = SortKey::parse_list("-priority,due_at,title,completed,updated_at").unwrap();
+        let cursor = Cursor::after(&task, &sort);
+
+        let encoded = cursor.encode(&sort);
+        assert!(encoded.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        assert_eq!(Cursor::decode(&encoded, &sort).unwrap(), cursor);
+
+        let other = SortKey::parse_list("-priority,title").unwrap();
+        let err = Cursor::decode(&encoded, &other).unwrap_err();
+        assert_eq!(
+            err.message,
+            "cursor was issued for sort '-priority,due_at,title,completed,updated_at' \
+             and cannot be used with sort '-priority,title'"
+        );
+    }
+
+    #[test]
+    fn rejects_cursors_that_were_not_issued() {
+        let sort = SortKey::parse_list("title").unwrap();
+        let encode = |json: serde_json::Value| URL_SAFE_NO_PAD.encode(json.to_string());
+        let id = Uuid::new_v4().to_string();
+        let cursors = [
+            "not base64!".to_string(),
+            URL_SAFE_NO_PAD.encode("not json"),
+            encode(serde_json::json!({"sort": "title", "values": [], "id": id})),
+            encode(serde_json::json!({"sort": "title", "values": [1], "id": id})),
+            encode(serde_json::json!({"sort": "title", "values": ["a"], "id": "1; DROP"})),
+        ];
+        for cursor in cursors {
+            let err = Cursor::decode(&cursor, &sort).unwrap_err();
+            assert_eq!(err.message, "invalid cursor", "{}", cursor);
+        }
+        let cursor = encode(serde_json::json!({"sort": "title", "values": ["a"], "id": id}));
+        assert!(Cursor::decode(&cursor, &sort).is_ok());
+    }
+
+    #[test]
+    fn pages_through_ties_without_gaps_or_repeats() {
+        // Equal titles and priorities leave the id to order the tasks
+        let mut tasks: Vec<Task> = (0..7)
+            .map(|n| task(&format!("Task {}", n % 3), Priority::Normal, None))
+            .collect();
+        tasks[4].priority = Priority::Urgent;
+
+        for sort in ["title", "-title", "-priority,title", "created_at"] {
+            let pages = all_pages(&tasks, sort);
+            assert_eq!(pages.iter().map(Vec::len).collect::<Vec<_>>(), [2, 2, 2, 1]);
+
+            let query = list_query(&format!("sort={}&limit=100", sort)).unwrap();
+            let expected: Vec<_> =
+                query.paginate(tasks.clone()).tasks.into_iter().map(|task| task.title).collect();
+            assert_eq!(pages.concat(), expected, "{}", sort);
+        }
+    }
+
+    #[test]
+    fn keeps_its_place_when_earlier_tasks_are_added() {
+        let tasks: Vec<Task> =
+            (0..4).map(|n| task(&format!("Task {}", n), Priority::Normal, None)).collect();
+        let first = list_query("sort=title&limit=2").unwrap().paginate(tasks.clone());
+        let cursor = first.next_cursor.unwrap();
+
+        // A task sorting before the cursor doesn't shift the next page
+        let mut later = tasks.clone();
+        later.push(task("Task 0a", Priority::Normal, None));
+        let query = list_query(&format!("sort=title&limit=2&cursor={}", cursor)).unwrap();
+        let second = query.paginate(later);
+        let titles: Vec<_> = second.tasks.iter().map(|task| task.title.as_str()).collect();
+        assert_eq!(titles, ["Task 2", "Task 3"]);
+        assert!(second.next_cursor.is_none());
+    }
+
+    #[actix_web::test]
+    async fn rejects_invalid_sorts_and_filters_with_a_400() {
+        let cases = [
+            ("sort=name", "unknown sort field 'name' \
+              (expected created_at, updated_at, title, completed, priority or due_at)"),
+            ("sort=title,-title", "sort field 'title' given more than once"),
+            ("sort=", "unknown sort field '' \
+              (expected created_at, updated_at, title, completed, priority or due_at)"),
+            ("limit=0", "limit must be at least 1"),
+            ("limit=101", "limit must be at most 100"),
+            ("priority=high,meh", "unknown priority 'meh' (expected low, normal, high or urgent)"),
+            ("tags_any=docker,", "tags must not be empty"),
+            ("filter=title", "expected an operator (:, !=, ~, >, >=, <, <=) after 'title' \
+              at position 5"),
+        ];
+        for (query, message) in cases {
+            let err = list_query(query).unwrap_err();
+            assert_eq!(err.to_string(), message, "{}", query);
+            assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+        }
+
+        let response = list_query("filter=done:true").unwrap_err().error_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body = actix_web::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["position"], 0);
+        assert!(body["error"].as_str().unwrap().starts_with("unknown field 'done'"));
+    }
+
+    #[actix_web::test]
+    async fn picks_the_next_task_by_priority_then_due_date_then_age() {
+        let storage = InMemoryTaskRepository::new();
+        let next = || async { storage.list_page(&next_task()).await.unwrap().tasks };
+
+        let mut done = task("Done", Priority::Urgent, None);
+        done.completed = true;
+        storage.create(done).await.unwrap();
+        assert!(next().await.is_empty());
+
+        // Each task added, and the task to work on next once it is
+        let steps = [
+            (task("Older, no due date", Priority::High, None), "Older, no due date"),
+            (task("Newer, no due date", Priority::High, None), "Older, no due date"),
+            (task("Due later", Priority::High, Some("2031-01-01T00:00:00Z")), "Due later"),
+            (task("Due sooner", Priority::High, Some("2030-01-01T00:00:00Z")), "Due sooner"),
+            (task("Low, overdue", Priority::Low, Some("2020-01-01T00:00:00Z")), "Due sooner"),
+            (task("Urgent", Priority::Urgent, None), "Urgent"),
+        ];
+        for (task, expected) in steps {
+            storage.create(task).await.unwrap();
+            let next = next().await;
+            assert_eq!(next.len(), 1);
+            assert_eq!(next[0].title, expected);
+        }
+
+        let mut query = next_task();
+        query.limit = 10;
+        let page = storage.list_page(&query).await.unwrap();
+        let titles: Vec<_> = page.tasks.iter().map(|task| task.title.as_str()).collect();
+        assert_eq!(
+            titles,
+            [
+                "Urgent",
+                "Due sooner",
+                "Due later",
+                "Older, no due date",
+                "Newer, no due date",
+                "Low, overdue"
+            ]
+        );
+    }
+}
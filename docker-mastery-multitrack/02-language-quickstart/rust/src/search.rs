@@ -0,0 +1,509 @@
+/*!
+ * Full-text search over task titles and descriptions
+ *
+ * The inverted index maps every token to the tasks containing it, with a
+ * per-field count. Text is split into lowercase alphanumeric tokens; a query
+ * token matches any indexed token it is a prefix of, and every query token
+ * has to match somewhere in a task for it to be a hit.
+ *
+ * Hits are ranked with BM25, counting title matches double and prefix-only
+ * matches half. Snippets are HTML-escaped with matches wrapped in `<mark>`.
+ */
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use crate::models::Task;
+use crate::query::{parse_limit, QueryError};
+
+pub const DEFAULT_SEARCH_LIMIT: usize = 20;
+pub const MAX_SEARCH_LIMIT: usize = 100;
+
+const TITLE_WEIGHT: f64 = 2.0;
+const PREFIX_WEIGHT: f64 = 0.5;
+const BM25_K1: f64 = 1.2;
+const BM25_B: f64 = 0.75;
+// Longest description snippet, in characters
+const SNIPPET_CHARS: usize = 160;
+
+// Raw query string parameters for GET /api/tasks/search
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SearchParams {
+    pub q: String,
+    pub limit: Option<usize>,
+}
+
+// A validated search: the distinct query tokens, in query order
+#[derive(Debug, Clone)]
+pub struct SearchQuery {
+    pub text: String,
+    pub terms: Vec<String>,
+    pub limit: usize,
+}
+
+impl TryFrom<SearchParams> for SearchQuery {
+    type Error = QueryError;
+
+    fn try_from(params: SearchParams) -> Result<Self, Self::Error> {
+        let mut terms: Vec<String> = Vec::new();
+        for token in tokenize(&params.q) {
+            if !terms.contains(&token.term) {
+                terms.push(token.term);
+            }
+        }
+        if terms.is_empty() {
+            return Err(QueryError::new("q must contain at least one word"));
+        }
+
+        Ok(SearchQuery {
+            text: params.q,
+            terms,
+            limit: parse_limit(params.limit, DEFAULT_SEARCH_LIMIT, MAX_SEARCH_LIMIT)?,
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Highlights {
+    pub title: String,
+    pub description: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchHit {
+    pub task: Task,
+    pub score: f64,
+    pub highlights: Highlights,
+}
+
+// The best `limit` hits. `total` counts every matching task.
+#[derive(Debug, Serialize)]
+pub struct SearchResults {
+    pub query: String,
+    pub results: Vec<SearchHit>,
+    pub total: usize,
+}
+
+struct Token {
+    term: String,
+    // Byte range in the original text
+    start: usize,
+    end: usize,
+}
+
+// Split text into lowercase runs of alphanumeric characters
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+
+    for (offset, c) in text.char_indices().chain(std::iter::once((text.len(), ' '))) {
+        match (start, c.is_alphanumeric()) {
+            (None, true) => start = Some(offset),
+            (Some(begin), false) => {
+                tokens.push(Token {
+                    term: text[begin..offset].to_lowercase(),
+                    start: begin,
+                    end: offset,
+                });
+                start = None;
+            }
+            _ => {}
+        }
+    }
+
+    tokens
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct TermCounts {
+    title: u32,
+    description: u32,
+}
+
+struct Document {
+    task: Task,
+    title_len: usize,
+    description_len: usize,
+}
+
+#[derive(Default)]
+pub struct SearchIndex {
+    // Token -> task id -> occurrences per field
+    terms: BTreeMap<String, HashMap<String, TermCounts>>,
+    documents: HashMap<String, Document>,
+    total_title_len: usize,
+    total_description_len: usize,
+    // Version each task was deleted at, so an update racing a delete can't
+    // re-add it while a later restore can
+    deleted: HashMap<String, i64>,
+}
+
+impl SearchIndex {
+    pub fn from_tasks(tasks: impl IntoIterator<Item = Task>) -> Self {
+        let mut index = SearchIndex::default();
+        for task in tasks {
+            index.insert(task);
+        }
+        index
+    }
+
+    // Add or re-index a task. Older versions than the one indexed are ignored,
+    // since concurrent writes may report back out of order.
+    pub fn insert(&mut self, task: Task) {
+        if let Some(&deleted) = self.deleted.get(&task.id) {
+            if task.version <= deleted {
+                return;
+            }
+            self.deleted.remove(&task.id);
+        }
+        if let Some(existing) = self.documents.get(&task.id) {
+            if existing.task.version > task.version {
+                return;
+            }
+        }
+        self.unindex(&task.id);
+
+        let title = tokenize(&task.title);
+        let description = tokenize(&task.description);
+        for token in &title {
+            self.counts(&token.term, &task.id).title += 1;
+        }
+        for token in &description {
+            self.counts(&token.term, &task.id).description += 1;
+        }
+
+        self.total_title_len += title.len();
+        self.total_description_len += description.len();
+        self.documents.insert(
+            task.id.clone(),
+            Document {
+                task,
+                title_len: title.len(),
+                description_len: description.len(),
+            },
+        );
+    }
+
+    // Drop a deleted task. `task` is the task as deleted, at the version the
+    // deletion gave it.
+    pub fn remove(&mut self, task: &Task) {
+        self.unindex(&task.id);
+        self.deleted.insert(task.id.clone(), task.version);
+    }
+
+    // Drop everything kept about a purged task. It can't come back, so no
+    // later write needs to be told apart from it.
+    pub fn forget(&mut self, id: &str) {
+        self.unindex(id);
+        self.deleted.remove(id);
+    }
+
+    fn counts(&mut self, term: &str, id: &str) -> &mut TermCounts {
+        self.terms
+            .entry(term.to_string())
+            .or_default()
+            .entry(id.to_string())
+            .or_default()
+    }
+
+    fn unindex(&mut self, id: &str) {
+        let Some(document) = self.documents.remove(id) else {
+            return;
+        };
+
+        let terms = tokenize(&document.task.title)
+            .into_iter()
+            .chain(tokenize(&document.task.description))
+            .map(|token| token.term)
+            .collect::<HashSet<_>>();
+        for term in terms {
+            if let Some(postings) = self.terms.get_mut(&term) {
+                postings.remove(id);
+                if postings.is_empty() {
+                    self.terms.remove(&term);
+                }
+            }
+        }
+
+        self.total_title_len -= document.title_len;
+        self.total_description_len -= document.description_len;
+    }
+
+    pub fn search(&self, query: &SearchQuery) -> SearchResults {
+        let mut scores: Option<HashMap<&str, f64>> = None;
+
+        // Intersect the matches of each query term, summing their scores
+        for term in &query.terms {
+            let matches = self.term_scores(term);
+            scores = Some(match scores {
+                None => matches,
+                Some(previous) => previous
+                    .into_iter()
+                    .filter_map(|(id, score)| matches.get(id).map(|extra| (id, score + extra)))
+                    .collect(),
+            });
+        }
+
+        let mut ranked: Vec<(&Document, f64)> = scores
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|(id, score)| self.documents.get(id).map(|document| (document, score)))
+            .collect();
+        ranked.sort_by(|(a, a_score), (b, b_score)| {
+            b_score
+                .total_cmp(a_score)
+                .then_with(|| b.task.updated_at.cmp(&a.task.updated_at))
+                .then_with(|| a.task.id.cmp(&b.task.id))
+        });
+
+        let total = ranked.len();
+        let results = ranked
+            .into_iter()
+            .take(query.limit)
+            .map(|(document, score)| SearchHit {
+                task: document.task.clone(),
+                score,
+                highlights: Highlights {
+                    title: highlight(&document.task.title, &query.terms, None),
+                    description: highlight(
+                        &document.task.description,
+                        &query.terms,
+                        Some(SNIPPET_CHARS),
+                    ),
+                },
+            })
+            .collect();
+
+        SearchResults {
+            query: query.text.clone(),
+            results,
+            total,
+        }
+    }
+
+    // Score of every task matching one query term. Where the term is a prefix
+    // of several tokens in a task, its best match counts.
+    fn term_scores(&self, term: &str) -> HashMap<&str, f64> {
+        let count = self.documents.len().max(1) as f64;
+        let avg_title_len = (self.total_title_len as f64 / count).max(1.0);
+        let avg_description_len = (self.total_description_len as f64 / count).max(1.0);
+
+        let mut scores: HashMap<&str, f64> = HashMap::new();
+        let expansions = self
+            .terms
+            .range(term.to_string()..)
+            .take_while(|(token, _)| token.starts_with(term));
+
+        for (token, postings) in expansions {
+            let weight = if token == term { 1.0 } else { PREFIX_WEIGHT };
+            let idf = (1.0 + (count - postings.len() as f64 + 0.5) / (postings.len() as f64 + 0.5)).ln();
+
+            for (id, counts) in postings {
+                let Some(document) = self.documents.get(id) else {
+                    continue;
+                };
+                let tf = TITLE_WEIGHT * bm25_tf(counts.title, document.title_len, avg_title_len)
+                    + bm25_tf(counts.description, document.description_len, avg_description_len);
+                let score = weight * idf * tf;
+
+                let best = scores.entry(id.as_str()).or_insert(0.0);
+                *best = best.max(score);
+            }
+        }
+
+        scores
+    }
+}
+
+fn bm25_tf(occurrences: u32, len: usize, avg_len: f64) -> f64 {
+    if occurrences == 0 {
+        return 0.0;
+    }
+    let tf = occurrences as f64;
+    tf * (BM25_K1 + 1.0) / (tf + BM25_K1 * (1.0 - BM25_B + BM25_B * len as f64 / avg_len))
+}
+
+// Escape `text` for HTML and wrap tokens matching a query term in <mark>.
+// With `max_chars`, only a window around the first match is kept.
+fn highlight(text: &str, terms: &[String], max_chars: Option<usize>) -> String {
+    let tokens = tokenize(text);
+    let is_match = |token: &Token| terms.iter().any(|term| token.term.starts_with(term.as_str()));
+
+    let (start, end) = match max_chars {
+        Some(max_chars) if text.chars().count() > max_chars => {
+            // Start a little before the first match so it has some context
+            let first = tokens.iter().find(|token| is_match(token)).map_or(0, |token| token.start);
+            let lead = text[..first].chars().count().min(max_chars / 4);
+            let start = match lead {
+                0 => first,
+                _ => text[..first].char_indices().rev().nth(lead - 1).map_or(0, |(offset, _)| offset),
+            };
+            let end = text[start..]
+                .char_indices()
+                .nth(max_chars)
+                .map_or(text.len(), |(offset, _)| start + offset);
+            (start, end)
+        }
+        _ => (0, text.len()),
+    };
+
+    let mut snippet = String::new();
+    if start > 0 {
+        snippet.push('…');
+    }
+    let mut position = start;
+    for token in tokens.iter().filter(|token| token.start >= start && token.end <= end) {
+        if !is_match(token) {
+            continue;
+        }
+        snippet.push_str(&escape_html(&text[position..token.start]));
+        snippet.push_str("<mark>");
+        snippet.push_str(&escape_html(&text[token.start..token.end]));
+        snippet.push_str("</mark>");
+        position = token.end;
+    }
+    snippet.push_str(&escape_html(&text[position..end]));
+    if end < text.len() {
+        snippet.push('…');
+    }
+
+    snippet
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::TaskCreate;
+
+    fn task(title: &str, description: &str) -> Task {
+        Task::new(TaskCreate {
+            title: title.to_string(),
+            description: description.to_string(),
+            priority: Default::default(),
+            tags: Vec::new(),
+            due_at: None,
+        })
+    }
+
+    fn search(index: &SearchIndex, text: &str) -> SearchResults {
+        let params = SearchParams {
+            q: text.to_string(),
+            limit: None,
+        };
+        index.search(&SearchQuery::try_from(params).unwrap())
+    }
+
+    fn titles(results: &SearchResults) -> Vec<&str> {
+        results.results.iter().map(|hit| hit.task.title.as_str()).collect()
+    }
+
+    #[test]
+    fn ranks_title_and_exact_matches_first() {
+        let index = SearchIndex::from_tasks([
+            task("Write notes", "About the docker setup"),
+            task("Docker image", "Build it"),
+            task("Dockerfile lint", "Check the build"),
+            task("Unrelated", "Nothing to see"),
+        ]);
+
+        let results = search(&index, "docker");
+        assert_eq!(titles(&results), ["Docker image", "Dockerfile lint", "Write notes"]);
+        assert_eq!(results.total, 3);
+        let scores: Vec<f64> = results.results.iter().map(|hit| hit.score).collect();
+        assert!(scores.windows(2).all(|pair| pair[0] > pair[1]), "{:?}", scores);
+
+        // Every word has to match
+        assert_eq!(titles(&search(&index, "docker build")), ["Docker image", "Dockerfile lint"]);
+        assert_eq!(search(&index, "docker nothing").total, 0);
+    }
+
+    #[test]
+    fn weighs_rare_terms_above_common_ones() {
+        let index = SearchIndex::from_tasks([
+            task("Deploy api", ""),
+            task("Deploy web", ""),
+            task("Deploy worker", ""),
+            task("Rollback api", ""),
+        ]);
+        let deploy = search(&index, "deploy").results[0].score;
+        let rollback = search(&index, "rollback").results[0].score;
+        assert!(rollback > deploy);
+    }
+
+    #[test]
+    fn limits_results_but_counts_every_hit() {
+        let index = SearchIndex::from_tasks((0..5).map(|n| task(&format!("Task {}", n), "")));
+        let params = SearchParams {
+            q: "task".to_string(),
+            limit: Some(2),
+        };
+        let results = index.search(&SearchQuery::try_from(params).unwrap());
+        assert_eq!((results.results.len(), results.total), (2, 5));
+
+        let params = SearchParams {
+            q: " -- ".to_string(),
+            limit: None,
+        };
+        let err = SearchQuery::try_from(params).unwrap_err();
+        assert_eq!(err.message, "q must contain at least one word");
+    }
+
+    #[test]
+    fn highlights_matches_in_escaped_snippets() {
+        let index = SearchIndex::from_tasks([task(
+            "<Docker> & Dockerfiles",
+            "Mention \"docker\" once",
+        )]);
+        let highlights = &search(&index, "dock").results[0].highlights;
+        assert_eq!(
+            highlights.title,
+            "&lt;<mark>Docker</mark>&gt; &amp; <mark>Dockerfiles</mark>"
+        );
+        assert_eq!(highlights.description, "Mention &quot;<mark>docker</mark>&quot; once");
+    }
+
+    #[test]
+    fn cuts_long_descriptions_around_the_first_match() {
+        let description = format!("{} needle {}", "a ".repeat(200), "b ".repeat(200));
+        let index = SearchIndex::from_tasks([task("Haystack", &description)]);
+        let snippet = &search(&index, "needle").results[0].highlights.description;
+
+        assert!(snippet.starts_with('…') && snippet.ends_with('…'), "{}", snippet);
+        assert!(snippet.contains("<mark>needle</mark>"));
+        let text = snippet.replace("<mark>", "").replace("</mark>", "");
+        assert_eq!(text.chars().count(), SNIPPET_CHARS + 2);
+        // Some context is kept before the match
+        assert_eq!(text.find("needle").map(|at| text[..at].chars().count()), Some(41));
+    }
+
+    #[test]
+    fn forgets_purged_tasks_entirely() {
+        let mut index = SearchIndex::default();
+        let mut trashed = task("Rotate keys", "");
+        index.insert(trashed.clone());
+
+        trashed.set_deleted(true);
+        index.remove(&trashed);
+        assert!(index.deleted.contains_key(&trashed.id));
+
+        index.forget(&trashed.id);
+        assert!(index.deleted.is_empty());
+        assert!(index.documents.is_empty());
+        assert!(index.terms.is_empty());
+    }
+}
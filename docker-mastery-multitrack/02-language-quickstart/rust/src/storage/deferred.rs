@@ -0,0 +1,134 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+use super::{Conditional, StorageError, StorageResult, TaskRepository};
+use crate::batch::BatchOperation;
+use crate::history::Revision;
+use crate::models::{Task, TaskUpdate};
+use crate::query::{TaskPage, TaskQuery};
+use crate::search::{SearchQuery, SearchResults};
+use crate::tags::TagCount;
+
+// Stands in for the real backend while startup recovery and migrations run,
+// so the HTTP server (and its probes) can come up before storage is ready.
+// Task requests get a 503 until `set` is called.
+#[derive(Default)]
+pub struct DeferredRepository {
+    inner: OnceLock<Arc<dyn TaskRepository>>,
+}
+
+impl DeferredRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, repository: Arc<dyn TaskRepository>) {
+        if self.inner.set(repository).is_err() {
+            log::warn!("Task storage was already initialized");
+        }
+    }
+
+    fn inner(&self) -> StorageResult<&Arc<dyn TaskRepository>> {
+        self.inner
+            .get()
+            .ok_or_else(|| StorageError::Unavailable("storage is starting up".to_string()))
+    }
+}
+
+#[async_trait]
+impl TaskRepository for DeferredRepository {
+    async fn ping(&self) -> StorageResult<()> {
+        self.inner()?.ping().await
+    }
+
+    async fn liveness(&self, deadline: Duration) -> StorageResult<()> {
+        // Still recovering is not the same as being wedged
+        match self.inner.get() {
+            Some(inner) => inner.liveness(deadline).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn list(&self) -> StorageResult<Vec<Task>> {
+        self.inner()?.list().await
+    }
+
+    async fn list_page(&self, query: &TaskQuery) -> StorageResult<TaskPage> {
+        self.inner()?.list_page(query).await
+    }
+
+    async fn search(&self, query: &SearchQuery) -> StorageResult<SearchResults> {
+        self.inner()?.search(query).await
+    }
+
+    async fn tags(&self) -> StorageResult<Vec<TagCount>> {
+        self.inner()?.tags().await
+    }
+
+    async fn retag(
+        &self,
+        from: &[String],
+        to: &str,
+        merge: bool,
+    ) -> StorageResult<Option<Vec<Task>>> {
+        self.inner()?.retag(from, to, merge).await
+    }
+
+    async fn create(&self, task: Task) -> StorageResult<Task> {
+        self.inner()?.create(task).await
+    }
+
+    async fn get(&self, id: &str) -> StorageResult<Option<Task>> {
+        self.inner()?.get(id).await
+    }
+
+    async fn update(&self, id: &str, changes: TaskUpdate) -> StorageResult<Option<Task>> {
+        self.inner()?.update(id, changes).await
+    }
+
+    async fn update_if_version(
+        &self,
+        id: &str,
+        changes: TaskUpdate,
+        version: i64,
+    ) -> StorageResult<Conditional> {
+        self.inner()?.update_if_version(id, changes, version).await
+    }
+
+    async fn delete(&self, id: &str) -> StorageResult<Option<Task>> {
+        self.inner()?.delete(id).await
+    }
+
+    async fn delete_if_version(&self, id: &str, version: i64) -> StorageResult<Conditional> {
+        self.inner()?.delete_if_version(id, version).await
+    }
+
+    async fn restore(&self, id: &str) -> StorageResult<Option<Task>> {
+        self.inner()?.restore(id).await
+    }
+
+    async fn purge(&self, id: &str) -> StorageResult<Option<Task>> {
+        self.inner()?.purge(id).await
+    }
+
+    async fn purge_trashed_before(&self, cutoff: DateTime<Utc>) -> StorageResult<Vec<String>> {
+        self.inner()?.purge_trashed_before(cutoff).await
+    }
+
+    async fn history(&self, id: &str) -> StorageResult<Vec<Revision>> {
+        self.inner()?.history(id).await
+    }
+
+    async fn revision(&self, id: &str, revision: i64) -> StorageResult<Option<Revision>> {
+        self.inner()?.revision(id, revision).await
+    }
+
+    async fn apply_atomically(
+        &self,
+        operations: Vec<BatchOperation>,
+    ) -> StorageResult<Vec<Conditional>> {
+        self.inner()?.apply_atomically(operations).await
+    }
+}
@@ -0,0 +1,190 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::info;
+use std::sync::{Arc, PoisonError, RwLock, RwLockWriteGuard};
+use std::time::Duration;
+
+use super::{Conditional, StorageResult, TaskRepository};
+use crate::batch::BatchOperation;
+use crate::history::Revision;
+use crate::models::{Task, TaskUpdate};
+use crate::query::{TaskPage, TaskQuery};
+use crate::search::{SearchIndex, SearchQuery, SearchResults};
+use crate::tags::TagCount;
+
+// Keeps a search index in step with the wrapped backend. The index is built
+// from a full listing at startup and then follows every write made through
+// this process; with several replicas sharing one database, a replica only
+// sees other replicas' changes after it restarts.
+pub struct IndexedRepository {
+    inner: Arc<dyn TaskRepository>,
+    index: RwLock<SearchIndex>,
+}
+
+impl IndexedRepository {
+    pub async fn build(inner: Arc<dyn TaskRepository>) -> StorageResult<Self> {
+        let tasks = inner.list().await?;
+        info!("Indexed {} tasks for search", tasks.len());
+
+        Ok(Self {
+            index: RwLock::new(SearchIndex::from_tasks(tasks)),
+            inner,
+        })
+    }
+
+    // A panic mid-update can at worst leave one task mis-indexed, which is
+    // better than failing every later search on a poisoned lock
+    fn index(&self) -> RwLockWriteGuard<'_, SearchIndex> {
+        self.index.write().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+#[async_trait]
+impl TaskRepository for IndexedRepository {
+    async fn ping(&self) -> StorageResult<()> {
+        self.inner.ping().await
+    }
+
+    async fn liveness(&self, deadline: Duration) -> StorageResult<()> {
+        self.inner.liveness(deadline).await
+    }
+
+    async fn list(&self) -> StorageResult<Vec<Task>> {
+        self.inner.list().await
+    }
+
+    async fn list_page(&self, query: &TaskQuery) -> StorageResult<TaskPage> {
+        self.inner.list_page(query).await
+    }
+
+    async fn search(&self, query: &SearchQuery) -> StorageResult<SearchResults> {
+        let index = self.index.read().unwrap_or_else(PoisonError::into_inner);
+        Ok(index.search(query))
+    }
+
+    async fn tags(&self) -> StorageResult<Vec<TagCount>> {
+        self.inner.tags().await
+    }
+
+    async fn retag(
+        &self,
+        from: &[String],
+        to: &str,
+        merge: bool,
+    ) -> StorageResult<Option<Vec<Task>>> {
+        let retagged = self.inner.retag(from, to, merge).await?;
+        let mut index = self.index();
+        for task in retagged.iter().flatten() {
+            index.insert(task.clone());
+        }
+        Ok(retagged)
+    }
+
+    async fn create(&self, task: Task) -> StorageResult<Task> {
+        let task = self.inner.create(task).await?;
+        self.index().insert(task.clone());
+        Ok(task)
+    }
+
+    async fn get(&self, id: &str) -> StorageResult<Option<Task>> {
+        self.inner.get(id).await
+    }
+
+    async fn update(&self, id: &str, changes: TaskUpdate) -> StorageResult<Option<Task>> {
+        let updated = self.inner.update(id, changes).await?;
+        if let Some(task) = &updated {
+            self.index().insert(task.clone());
+        }
+        Ok(updated)
+    }
+
+    async fn update_if_version(
+        &self,
+        id: &str,
+        changes: TaskUpdate,
+        version: i64,
+    ) -> StorageResult<Conditional> {
+        let outcome = self.inner.update_if_version(id, changes, version).await?;
+        if let Conditional::Applied(task) = &outcome {
+            self.index().insert(task.clone());
+        }
+        Ok(outcome)
+    }
+
+    async fn delete(&self, id: &str) -> StorageResult<Option<Task>> {
+        let removed = self.inner.delete(id).await?;
+        if let Some(task) = &removed {
+            self.index().remove(task);
+        }
+        Ok(removed)
+    }
+
+    async fn delete_if_version(&self, id: &str, version: i64) -> StorageResult<Conditional> {
+        let outcome = self.inner.delete_if_version(id, version).await?;
+        if let Conditional::Applied(task) = &outcome {
+            self.index().remove(task);
+        }
+        Ok(outcome)
+    }
+
+    async fn restore(&self, id: &str) -> StorageResult<Option<Task>> {
+        let restored = self.inner.restore(id).await?;
+        if let Some(task) = &restored {
+            self.index().insert(task.clone());
+        }
+        Ok(restored)
+    }
+
+    async fn purge(&self, id: &str) -> StorageResult<Option<Task>> {
+        let purged = self.inner.purge(id).await?;
+        if purged.is_some() {
+            self.index().forget(id);
+        }
+        Ok(purged)
+    }
+
+    async fn purge_trashed_before(&self, cutoff: DateTime<Utc>) -> StorageResult<Vec<String>> {
+        let purged = self.inner.purge_trashed_before(cutoff).await?;
+        let mut index = self.index();
+        for id in &purged {
+            index.forget(id);
+        }
+        Ok(purged)
+    }
+
+    async fn history(&self, id: &str) -> StorageResult<Vec<Revision>> {
+        self.inner.history(id).await
+    }
+
+    async fn revision(&self, id: &str, revision: i64) -> StorageResult<Option<Revision>> {
+        self.inner.revision(id, revision).await
+    }
+
+    async fn apply_atomically(
+        &self,
+        operations: Vec<BatchOperation>,
+    ) -> StorageResult<Vec<Conditional>> {
+        let count = operations.len();
+        let deletes: Vec<bool> = operations
+            .iter()
+            .map(|operation| matches!(operation, BatchOperation::Delete { .. }))
+            .collect();
+
+        let outcomes = self.inner.apply_atomically(operations).await?;
+        let committed = outcomes.len() == count
+            && outcomes.iter().all(|outcome| matches!(outcome, Conditional::Applied(_)));
+        if committed {
+            let mut index = self.index();
+            for (outcome, delete) in outcomes.iter().zip(deletes) {
+                if let Conditional::Applied(task) = outcome {
+                    if delete {
+                        index.remove(task);
+                    } else {
+                        index.insert(task.clone());
+                    }
+                }
+            }
+        }
+        Ok(outcomes)
+    }
+}
@@ -0,0 +1,867 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::warn;
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::BuildHasher;
+use std::sync::{
+    Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError,
+};
+use std::time::{Duration, Instant};
+
+use super::wal::{self, PreparedSnapshot, Recovered, WalConfig, WalRecord, WriteAheadLog};
+use super::{Conditional, StorageError, StorageResult, TaskRepository};
+use crate::batch::BatchOperation;
+use crate::history::{Action, History, Revision};
+use crate::models::{Task, TaskUpdate};
+use crate::query::{TaskPage, TaskQuery};
+use crate::tags;
+
+// Tasks are spread over independently locked shards so readers never wait on
+// each other and a writer only blocks the shard holding its task
+const SHARD_COUNT: usize = 16;
+
+type Shard = HashMap<String, Task>;
+
+// A task unless it is in the trash
+fn live<'a>(shard: &'a Shard, id: &str) -> Option<&'a Task> {
+    shard.get(id).filter(|task| !task.is_deleted())
+}
+
+// A task only if it is in the trash
+fn trashed<'a>(shard: &'a Shard, id: &str) -> Option<&'a Task> {
+    shard.get(id).filter(|task| task.is_deleted())
+}
+
+fn poisoned<T>(_: T) -> StorageError {
+    StorageError::Internal("task storage lock poisoned".to_string())
+}
+
+// In-memory storage, optionally made durable with a write-ahead log
+pub struct InMemoryTaskRepository {
+    shards: Box<[RwLock<Shard>]>,
+    hasher: RandomState,
+    // Lock order is always shard(s) first, then the log, then the history
+    // Shared with the background flusher of an interval fsync policy
+    wal: Option<Arc<Mutex<WriteAheadLog>>>,
+    history: RwLock<History>,
+}
+
+impl Default for InMemoryTaskRepository {
+    fn default() -> Self {
+        Self::with_recovered(Recovered::default(), None)
+    }
+}
+
+impl InMemoryTaskRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Recover tasks from the snapshot and log in `config.dir`
+    pub fn open(config: WalConfig) -> StorageResult<Self> {
+        let (wal, recovered) = WriteAheadLog::open(config)?;
+        let interval = wal.sync_interval();
+        let repository = Self::with_recovered(recovered, Some(wal));
+        if let (Some(wal), Some(interval)) = (&repository.wal, interval) {
+            wal::spawn_flusher(Arc::downgrade(wal), interval);
+        }
+        Ok(repository)
+    }
+
+    fn with_recovered(recovered: Recovered, wal: Option<WriteAheadLog>) -> Self {
+        let mut repository = Self {
+            shards: (0..SHARD_COUNT).map(|_| RwLock::default()).collect(),
+            hasher: RandomState::new(),
+            wal: wal.map(|wal| Arc::new(Mutex::new(wal))),
+            history: RwLock::new(recovered.history),
+        };
+
+        for (id, task) in recovered.tasks {
+            let index = repository.shard_index(&id);
+            repository.shards[index]
+                .get_mut()
+                .unwrap_or_else(|err| err.into_inner())
+                .insert(id, task);
+        }
+        repository
+    }
+
+    fn shard_index(&self, id: &str) -> usize {
+        self.hasher.hash_one(id) as usize % SHARD_COUNT
+    }
+
+    fn read(&self, id: &str) -> StorageResult<RwLockReadGuard<'_, Shard>> {
+        self.read_shard(self.shard_index(id))
+    }
+
+    fn write(&self, id: &str) -> StorageResult<RwLockWriteGuard<'_, Shard>> {
+        self.write_shard(self.shard_index(id))
+    }
+
+    fn read_all(&self) -> StorageResult<Vec<RwLockReadGuard<'_, Shard>>> {
+        (0..SHARD_COUNT).map(|index| self.read_shard(index)).collect()
+    }
+
+    // Always locked in index order, so two callers can't deadlock
+    fn write_all(&self) -> StorageResult<Vec<RwLockWriteGuard<'_, Shard>>> {
+        (0..SHARD_COUNT).map(|index| self.write_shard(index)).collect()
+    }
+
+    fn read_shard(&self, index: usize) -> StorageResult<RwLockReadGuard<'_, Shard>> {
+        if let Ok(guard) = self.shards[index].read() {
+            return Ok(guard);
+        }
+
+        // Recovery needs the write lock, so the poisoned read guard is dropped first
+        drop(self.write_shard(index)?);
+        self.shards[index].read().map_err(poisoned)
+    }
+
+    // A panic while a shard was write-locked poisons it. Rather than failing
+    // every later request, rebuild the shard and clear the poison.
+    fn write_shard(&self, index: usize) -> StorageResult<RwLockWriteGuard<'_, Shard>> {
+        let mut guard = match self.shards[index].write() {
+            Ok(guard) => return Ok(guard),
+            Err(poison) => poison.into_inner(),
+        };
+
+        match self.wal()? {
+            // The log is the source of truth, so restore exactly what was committed
+            Some(wal) => {
+                let tasks = wal.replay().map_err(|err| {
+                    StorageError::Unavailable(format!(
+                        "task storage could not be rebuilt after a failed write: {}",
+                        err
+                    ))
+                })?;
+                *guard = tasks
+                    .tasks
+                    .into_iter()
+                    .filter(|(id, _)| self.shard_index(id) == index)
+                    .collect();
+                warn!(
+                    "Rebuilt poisoned task storage shard {} from the write-ahead log",
+                    index
+                );
+            }
+            // Entries are only ever replaced whole, so the map itself is intact
+            None => warn!("Recovered poisoned task storage shard {}", index),
+        }
+
+        self.shards[index].clear_poison();
+        Ok(guard)
+    }
+
+    fn wal(&self) -> StorageResult<Option<MutexGuard<'_, WriteAheadLog>>> {
+        let Some(wal) = &self.wal else {
+            return Ok(None);
+        };
+
+        match wal.lock() {
+            Ok(guard) => Ok(Some(guard)),
+            Err(poison) => {
+                // A panic mid-append may have left a partial record behind
+                let mut guard = poison.into_inner();
+                guard.repair().map_err(|err| {
+                    StorageError::Unavailable(format!(
+                        "write-ahead log could not be repaired: {}",
+                        err
+                    ))
+                })?;
+                wal.clear_poison();
+                warn!("Recovered poisoned write-ahead log");
+                Ok(Some(guard))
+            }
+        }
+    }
+
+    // Revisions are only ever appended whole, so a poisoned lock is harmless
+    fn history(&self) -> RwLockWriteGuard<'_, History> {
+        self.history.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    // Log the record and the revision it makes (when persistence is
+    // enabled), then apply both to the shard the caller holds for the
+    // record's task and to the history
+    fn commit(
+        &self,
+        shard: &mut Shard,
+        record: WalRecord,
+        revision: Revision,
+    ) -> StorageResult<()> {
+        self.commit_batch(shard, vec![record, WalRecord::Revise { revision }])
+    }
+
+    // Log the records in one frame, then apply them
+    fn commit_batch(&self, shard: &mut Shard, records: Vec<WalRecord>) -> StorageResult<()> {
+        let record = WalRecord::Batch { records };
+        if let Some(mut wal) = self.wal()? {
+            wal.append(&record)?;
+        }
+
+        record.apply(shard, &mut self.history());
+        Ok(())
+    }
+
+    // A live task as the batch in progress sees it, given its staged changes
+    fn staged_task(
+        &self,
+        shards: &[RwLockWriteGuard<'_, Shard>],
+        staged: &HashMap<String, Task>,
+        id: &str,
+    ) -> Option<Task> {
+        match staged.get(id) {
+            Some(task) => Some(task.clone()).filter(|task| !task.is_deleted()),
+            None => live(&shards[self.shard_index(id)], id).cloned(),
+        }
+    }
+
+    // Every task, trashed ones included. All shards are held at once, so an
+    // atomic batch is seen either whole or not at all.
+    fn all_tasks(&self) -> StorageResult<Vec<Task>> {
+        let shards = self.read_all()?;
+        Ok(shards.iter().flat_map(|shard| shard.values().cloned()).collect())
+    }
+
+    // Move a live task in or out of the trash
+    fn set_deleted(
+        &self,
+        id: &str,
+        deleted: bool,
+        version: Option<i64>,
+    ) -> StorageResult<Conditional> {
+        let task = {
+            let mut shard = self.write(id)?;
+            let found = if deleted { live(&shard, id) } else { trashed(&shard, id) };
+            let Some(mut task) = found.cloned() else {
+                return Ok(Conditional::NotFound);
+            };
+            if version.is_some_and(|version| version != task.version) {
+                return Ok(Conditional::Conflict);
+            }
+
+            task.set_deleted(deleted);
+            let action = if deleted { Action::Deleted } else { Action::Restored };
+            let revision = Revision::new(action, task.clone());
+            self.commit(&mut shard, WalRecord::Put { task: task.clone() }, revision)?;
+            task
+        };
+
+        self.compact_if_due();
+        Ok(Conditional::Applied(task))
+    }
+
+    // Snapshot once the log is long enough. Must be called without holding a
+    // shard lock, since a consistent snapshot needs all of them.
+    fn compact_if_due(&self) {
+        let due = matches!(self.wal(), Ok(Some(wal)) if wal.needs_compaction());
+        if !due {
+            return;
+        }
+
+        let result = self.read_all().and_then(|shards| {
+            let Some(mut wal) = self.wal()? else {
+                return Ok(());
+            };
+            // Another writer may have compacted while we waited for the locks
+            if !wal.needs_compaction() {
+                return Ok(());
+            }
+            let tasks = shards.iter().flat_map(|shard| shard.values()).collect();
+            let snapshot = PreparedSnapshot::new(tasks, &self.history())?;
+            // Writers still wait on the log until it is truncated, but readers
+            // need not wait for the disk
+            drop(shards);
+            wal.compact(snapshot)?;
+            Ok(())
+        });
+
+        // The log still holds every record, so a failed snapshot is not fatal
+        if let Err(err) = result {
+            warn!("Write-ahead log compaction failed: {}", err);
+        }
+    }
+}
+
+#[async_trait]
+impl TaskRepository for InMemoryTaskRepository {
+    async fn ping(&self) -> StorageResult<()> {
+        self.read_all().map(|_| ())
+    }
+
+    async fn liveness(&self, deadline: Duration) -> StorageResult<()> {
+        // Poll with try_read so a stuck writer can't block the probe itself
+        let started = Instant::now();
+        for shard in self.shards.iter() {
+            loop {
+                // A poisoned lock can still be taken, so it doesn't count as wedged
+                let acquired = !matches!(shard.try_read(), Err(TryLockError::WouldBlock));
+                if acquired {
+                    break;
+                }
+                if started.elapsed() >= deadline {
+                    return Err(StorageError::Unavailable(format!(
+                        "task storage lock not acquired within {}ms",
+                        deadline.as_millis()
+                    )));
+                }
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        }
+        Ok(())
+    }
+
+    async fn list(&self) -> StorageResult<Vec<Task>> {
+        let mut tasks = self.all_tasks()?;
+        tasks.retain(|task| !task.is_deleted());
+        Ok(tasks)
+    }
+
+    // The query's filter decides between live and trashed tasks
+    async fn list_page(&self, query: &TaskQuery) -> StorageResult<TaskPage> {
+        Ok(query.paginate(self.all_tasks()?))
+    }
+
+    async fn retag(
+        &self,
+        from: &[String],
+        to: &str,
+        merge: bool,
+    ) -> StorageResult<Option<Vec<Task>>> {
+        let retagged = {
+            let mut shards = self.write_all()?;
+            let taken = shards
+                .iter()
+                .flat_map(|shard| shard.values())
+                .any(|task| !task.is_deleted() && task.tags.iter().any(|tag| tag == to));
+            if taken && !merge && !from.iter().any(|tag| tag == to) {
+                return Ok(None);
+            }
+
+            let mut retagged = Vec::new();
+            for task in shards.iter().flat_map(|shard| shard.values()) {
+                if task.is_deleted() {
+                    continue;
+                }
+                if let Some(tags) = tags::retagged(&task.tags, from, to) {
+                    let mut task = task.clone();
+                    let changes = TaskUpdate {
+                        tags: Some(tags),
+                        ..TaskUpdate::default()
+                    };
+                    changes.apply(&mut task);
+                    retagged.push(task);
+                }
+            }
+            if retagged.is_empty() {
+                return Ok(Some(retagged));
+            }
+
+            let revisions: Vec<Revision> = retagged
+                .iter()
+                .map(|task| Revision::new(Action::Updated, task.clone()))
+                .collect();
+            if let Some(mut wal) = self.wal()? {
+                let records = retagged
+                    .iter()
+                    .map(|task| WalRecord::Put { task: task.clone() })
+                    .chain(revisions.iter().cloned().map(|revision| WalRecord::Revise { revision }))
+                    .collect();
+                wal.append(&WalRecord::Batch { records })?;
+            }
+            for task in &retagged {
+                shards[self.shard_index(&task.id)].insert(task.id.clone(), task.clone());
+            }
+            let mut history = self.history();
+            for revision in revisions {
+                history.record(revision);
+            }
+            retagged
+        };
+
+        self.compact_if_due();
+        Ok(Some(retagged))
+    }
+
+    async fn create(&self, task: Task) -> StorageResult<Task> {
+        {
+            let mut shard = self.write(&task.id)?;
+            let revision = Revision::new(Action::Created, task.clone());
+            self.commit(&mut shard, WalRecord::Put { task: task.clone() }, revision)?;
+        }
+
+        self.compact_if_due();
+        Ok(task)
+    }
+
+    async fn get(&self, id: &str) -> StorageResult<Option<Task>> {
+        Ok(live(&*self.read(id)?, id).cloned())
+    }
+
+    async fn update(&self, id: &str, changes: TaskUpdate) -> StorageResult<Option<Task>> {
+        let updated = {
+            let mut shard = self.write(id)?;
+            let Some(mut task) = live(&shard, id).cloned() else {
+                return Ok(None);
+            };
+
+            changes.apply(&mut task);
+            let revision = Revision::new(Action::Updated, task.clone());
+            self.commit(&mut shard, WalRecord::Put { task: task.clone() }, revision)?;
+            task
+        };
+
+        self.compact_if_due();
+        Ok(Some(updated))
+    }
+
+    async fn update_if_version(
+        &self,
+        id: &str,
+        changes: TaskUpdate,
+        version: i64,
+    ) -> StorageResult<Conditional> {
+        let updated = {
+            let mut shard = self.write(id)?;
+            let Some(mut task) = live(&shard, id).cloned() else {
+                return Ok(Conditional::NotFound);
+            };
+            if task.version != version {
+                return Ok(Conditional::Conflict);
+            }
+
+            changes.apply(&mut task);
+            let revision = Revision::new(Action::Updated, task.clone());
+            self.commit(&mut shard, WalRecord::Put { task: task.clone() }, revision)?;
+            task
+        };
+
+        self.compact_if_due();
+        Ok(Conditional::Applied(updated))
+    }
+
+    async fn delete(&self, id: &str) -> StorageResult<Option<Task>> {
+        match self.set_deleted(id, true, None)? {
+            Conditional::Applied(task) => Ok(Some(task)),
+            _ => Ok(None),
+        }
+    }
+
+    async fn delete_if_version(&self, id: &str, version: i64) -> StorageResult<Conditional> {
+        self.set_deleted(id, true, Some(version))
+    }
+
+    async fn restore(&self, id: &str) -> StorageResult<Option<Task>> {
+        match self.set_deleted(id, false, None)? {
+            Conditional::Applied(task) => Ok(Some(task)),
+            _ => Ok(None),
+        }
+    }
+
+    async fn purge(&self, id: &str) -> StorageResult<Option<Task>> {
+        let purged = {
+            let mut shard = self.write(id)?;
+            let Some(task) = trashed(&shard, id).cloned() else {
+                return Ok(None);
+            };
+
+            let records = vec![
+                WalRecord::Delete { id: id.to_string() },
+                WalRecord::Forget { id: id.to_string() },
+            ];
+            self.commit_batch(&mut shard, records)?;
+            task
+        };
+
+        self.compact_if_due();
+        Ok(Some(purged))
+    }
+
+    async fn purge_trashed_before(&self, cutoff: DateTime<Utc>) -> StorageResult<Vec<String>> {
+        let ids = {
+            let mut shards = self.write_all()?;
+            let ids: Vec<String> = shards
+                .iter()
+                .flat_map(|shard| shard.values())
+                .filter(|task| task.deleted_at.is_some_and(|deleted_at| deleted_at < cutoff))
+                .map(|task| task.id.clone())
+                .collect();
+            if ids.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            if let Some(mut wal) = self.wal()? {
+                let records = ids
+                    .iter()
+                    .flat_map(|id| {
+                        [WalRecord::Delete { id: id.clone() }, WalRecord::Forget { id: id.clone() }]
+                    })
+                    .collect();
+                wal.append(&WalRecord::Batch { records })?;
+            }
+            let mut history = self.history();
+            for id in &ids {
+                shards[self.shard_index(id)].remove(id);
+                history.forget(id);
+            }
+            ids
+        };
+
+        self.compact_if_due();
+        Ok(ids)
+    }
+
+    async fn history(&self, id: &str) -> StorageResult<Vec<Revision>> {
+        let history = self.history.read().unwrap_or_else(PoisonError::into_inner);
+        Ok(history.of(id))
+    }
+
+    async fn apply_atomically(
+        &self,
+        operations: Vec<BatchOperation>,
+    ) -> StorageResult<Vec<Conditional>> {
+        let count = operations.len();
+        let mut outcomes = Vec::with_capacity(count);
+        {
+            let mut shards = self.write_all()?;
+            // Changes made so far by this batch, and the revisions they make
+            let mut staged: HashMap<String, Task> = HashMap::new();
+            let mut revisions = Vec::with_capacity(count);
+            let mut records = Vec::with_capacity(count);
+
+            for operation in operations {
+                let outcome = match operation {
+                    BatchOperation::Create(task) => {
+                        records.push(WalRecord::Put { task: task.clone() });
+                        revisions.push(Revision::new(Action::Created, task.clone()));
+                        staged.insert(task.id.clone(), task.clone());
+                        Conditional::Applied(task)
+                    }
+                    BatchOperation::Update { id, changes, version } => {
+                        match self.staged_task(&shards, &staged, &id) {
+                            None => Conditional::NotFound,
+                            Some(task) if version.is_some_and(|v| v != task.version) => {
+                                Conditional::Conflict
+                            }
+                            Some(mut task) => {
+                                changes.apply(&mut task);
+                                records.push(WalRecord::Put { task: task.clone() });
+                                revisions.push(Revision::new(Action::Updated, task.clone()));
+                                staged.insert(id, task.clone());
+                                Conditional::Applied(task)
+                            }
+                        }
+                    }
+                    BatchOperation::Delete { id, version } => {
+                        match self.staged_task(&shards, &staged, &id) {
+                            None => Conditional::NotFound,
+                            Some(task) if version.is_some_and(|v| v != task.version) => {
+                                Conditional::Conflict
+                            }
+                            Some(mut task) => {
+                                task.set_deleted(true);
+                                records.push(WalRecord::Put { task: task.clone() });
+                                revisions.push(Revision::new(Action::Deleted, task.clone()));
+                                staged.insert(id, task.clone());
+                                Conditional::Applied(task)
+                            }
+                        }
+                    }
+                };
+
+                let applied = matches!(outcome, Conditional::Applied(_));
+                outcomes.push(outcome);
+                if !applied {
+                    return Ok(outcomes);
+                }
+            }
+
+            if let Some(mut wal) = self.wal()? {
+                records.extend(
+                    revisions.iter().cloned().map(|revision| WalRecord::Revise { revision }),
+                );
+                wal.append(&WalRecord::Batch { records })?;
+            }
+            for (id, task) in staged {
+                shards[self.shard_index(&id)].insert(id, task);
+            }
+            let mut history = self.history();
+            for revision in revisions {
+                history.record(revision);
+            }
+        }
+
+        self.compact_if_due();
+        Ok(outcomes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::{create_task, get_task, TaskStorage};
+    use crate::idempotency::IdempotencyStore;
+    use crate::middleware::CatchPanic;
+    use crate::models::TaskCreate;
+    use actix_web::body::to_bytes;
+    use actix_web::test::{
+        call_service, init_service, read_body_json, try_call_service, TestRequest,
+    };
+    use actix_web::{web, App};
+    use futures_util::FutureExt;
+    use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    const PRELOADED_TASKS: usize = 1_000;
+    const READERS: usize = 4;
+    const WRITERS: usize = 4;
+    const RUN_FOR: Duration = Duration::from_secs(2);
+
+    fn sample_tasks() -> Vec<Task> {
+        (0..PRELOADED_TASKS)
+            .map(|n| {
+                Task::new(TaskCreate {
+                    title: format!("Task {}", n),
+                    description: "Benchmark task".to_string(),
+                    priority: Default::default(),
+                    tags: Vec::new(),
+                    due_at: None,
+                })
+            })
+            .collect()
+    }
+
+    // Runs `read` on READERS threads while WRITERS threads call `write` in a
+    // loop, returning completed reads per second
+    fn reads_per_second(
+        read: impl Fn() + Send + Sync + 'static,
+        write: impl Fn(usize) + Send + Sync + 'static,
+    ) -> f64 {
+        let read = Arc::new(read);
+        let write = Arc::new(write);
+        let stop = Arc::new(AtomicBool::new(false));
+        let reads = Arc::new(AtomicU64::new(0));
+
+        let mut handles = Vec::new();
+        for _ in 0..READERS {
+            let (read, stop, reads) = (read.clone(), stop.clone(), reads.clone());
+            handles.push(thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    read();
+                    reads.fetch_add(1, Ordering::Relaxed);
+                }
+            }));
+        }
+        for writer in 0..WRITERS {
+            let (write, stop) = (write.clone(), stop.clone());
+            handles.push(thread::spawn(move || {
+                let mut n = writer;
+                while !stop.load(Ordering::Relaxed) {
+                    write(n % PRELOADED_TASKS);
+                    n += WRITERS;
+                }
+            }));
+        }
+
+        thread::sleep(RUN_FOR);
+        stop.store(true, Ordering::Relaxed);
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        reads.load(Ordering::Relaxed) as f64 / RUN_FOR.as_secs_f64()
+    }
+
+    // A handler that panics while holding a shard's write lock must not take
+    // the server down or leave the shard unusable
+    #[actix_web::test]
+    async fn keeps_serving_after_a_handler_panics_holding_the_lock() {
+        let repository = Arc::new(InMemoryTaskRepository::new());
+        let task = repository.create(sample_tasks().remove(0)).await.unwrap();
+
+        let panicking = repository.clone();
+        let panicking_id = task.id.clone();
+        let storage: TaskStorage = web::Data::from(repository as Arc<dyn TaskRepository>);
+        let app = init_service(
+            App::new()
+                .app_data(storage)
+                .app_data(web::Data::new(IdempotencyStore::from_env()))
+                .wrap(CatchPanic)
+                .route("/api/tasks", web::post().to(create_task))
+                .route("/api/tasks/{id}", web::get().to(get_task))
+                .route(
+                    "/boom",
+                    web::get().to(move || {
+                        let repository = panicking.clone();
+                        let id = panicking_id.clone();
+                        async move {
+                            let _shard = repository.write(&id).unwrap();
+                            panic!("simulated bug while holding the storage lock");
+                            #[allow(unreachable_code)]
+                            actix_web::HttpResponse::Ok().finish()
+                        }
+                    }),
+                ),
+        )
+        .await;
+
+        // The panic surfaces as an error that actix renders as the response
+        let err = try_call_service(&app, TestRequest::get().uri("/boom").to_request())
+            .await
+            .expect_err("panicking handler should produce an error");
+        let res = err.error_response();
+        assert_eq!(res.status(), 500);
+        let body = to_bytes(res.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"], "internal server error");
+
+        let res = call_service(
+            &app,
+            TestRequest::get()
+                .uri(&format!("/api/tasks/{}", task.id))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), 200);
+        let fetched: Task = read_body_json(res).await;
+        assert_eq!(fetched.title, task.title);
+
+        let res = call_service(
+            &app,
+            TestRequest::post()
+                .uri("/api/tasks")
+                .set_json(serde_json::json!({"title": "After panic", "description": ""}))
+                .to_request(),
+        )
+        .await;
+        assert_eq!(res.status(), 201);
+    }
+
+    #[actix_web::test]
+    async fn rolls_back_an_atomic_batch_that_fails() {
+        let repository = InMemoryTaskRepository::new();
+        let existing = repository.create(sample_tasks().remove(0)).await.unwrap();
+
+        let outcomes = repository
+            .apply_atomically(vec![
+                BatchOperation::Create(sample_tasks().remove(1)),
+                BatchOperation::Update {
+                    id: existing.id.clone(),
+                    changes: TaskUpdate {
+                        completed: Some(true),
+                        ..Default::default()
+                    },
+                    version: None,
+                },
+                BatchOperation::Delete {
+                    id: existing.id.clone(),
+                    version: Some(existing.version),
+                },
+            ])
+            .await
+            .unwrap();
+
+        // The update moved the task past the version the delete expects
+        assert!(matches!(outcomes.last(), Some(Conditional::Conflict)));
+        let tasks = repository.list().await.unwrap();
+        assert_eq!(tasks.len(), 1);
+        assert!(!tasks[0].completed);
+        assert_eq!(tasks[0].version, existing.version);
+        assert_eq!(TaskRepository::history(&repository, &existing.id).await.unwrap().len(), 1);
+    }
+
+    // Tasks created in pairs by atomic batches are always listed in pairs
+    #[test]
+    fn lists_never_show_half_an_atomic_batch() {
+        let repository = Arc::new(InMemoryTaskRepository::new());
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let (writer, writer_stop) = (repository.clone(), stop.clone());
+        let handle = thread::spawn(move || {
+            while !writer_stop.load(Ordering::Relaxed) {
+                let pair = sample_tasks().into_iter().take(2).map(BatchOperation::Create);
+                writer.apply_atomically(pair.collect()).now_or_never().unwrap().unwrap();
+            }
+        });
+
+        for _ in 0..2_000 {
+            let listed = repository.list().now_or_never().unwrap().unwrap().len();
+            assert_eq!(listed % 2, 0, "listed {} tasks", listed);
+        }
+        stop.store(true, Ordering::Relaxed);
+        handle.join().unwrap();
+    }
+
+    // List throughput under concurrent writers, sharded store vs the previous
+    // single Mutex<HashMap>. Run with:
+    //   cargo test --release list_throughput -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn list_throughput_under_concurrent_writers() {
+        let tasks = sample_tasks();
+        let ids: Arc<Vec<String>> = Arc::new(tasks.iter().map(|task| task.id.clone()).collect());
+
+        // Baseline: the original global mutex
+        let mutex_store: Arc<Mutex<HashMap<String, Task>>> = Arc::new(Mutex::new(
+            tasks.iter().map(|task| (task.id.clone(), task.clone())).collect(),
+        ));
+        let (reader, writer, writer_ids) = (mutex_store.clone(), mutex_store, ids.clone());
+        let mutex_rate = reads_per_second(
+            move || {
+                let tasks = reader.lock().unwrap();
+                let _list: Vec<Task> = tasks.values().cloned().collect();
+            },
+            move |n| {
+                let mut tasks = writer.lock().unwrap();
+                if let Some(task) = tasks.get_mut(&writer_ids[n]) {
+                    TaskUpdate {
+                        title: None,
+                        description: None,
+                        completed: Some(!task.completed),
+                        priority: None,
+                        tags: None,
+                        due_at: None,
+                    }
+                    .apply(task);
+                }
+            },
+        );
+
+        // Sharded store; its futures never await, so poll them inline
+        let repository = Arc::new(InMemoryTaskRepository::new());
+        for task in tasks {
+            repository.create(task).now_or_never().unwrap().unwrap();
+        }
+        let (reader, writer, writer_ids) = (repository.clone(), repository, ids);
+        let sharded_rate = reads_per_second(
+            move || {
+                reader.list().now_or_never().unwrap().unwrap();
+            },
+            move |n| {
+                let changes = TaskUpdate {
+                    title: None,
+                    description: None,
+                    completed: Some(n % 2 == 0),
+                    priority: None,
+                    tags: None,
+                    due_at: None,
+                };
+                writer.update(&writer_ids[n], changes).now_or_never().unwrap().unwrap();
+            },
+        );
+
+        println!(
+            "list() over {} tasks with {} readers and {} writers:\n  \
+             Mutex<HashMap>  {:>10.0} lists/s\n  \
+             sharded RwLock  {:>10.0} lists/s ({:.1}x)",
+            PRELOADED_TASKS,
+            READERS,
+            WRITERS,
+            mutex_rate,
+            sharded_rate,
+            sharded_rate / mutex_rate
+        );
+    }
+}
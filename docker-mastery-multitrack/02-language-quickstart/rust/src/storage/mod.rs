@@ -0,0 +1,261 @@
+/*!
+ * Task storage backends
+ *
+ * Handlers only talk to the `TaskRepository` trait, so the backing store can be
+ * swapped at startup without touching the HTTP layer.
+ */
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::info;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::batch::BatchOperation;
+use crate::history::Revision;
+use crate::models::{Task, TaskUpdate};
+use crate::query::{TaskPage, TaskQuery};
+use crate::search::{SearchIndex, SearchQuery, SearchResults};
+use crate::tags::{self, TagCount};
+
+mod deferred;
+mod indexed;
+mod memory;
+mod postgres;
+mod sql;
+mod sqlite;
+mod wal;
+
+pub use deferred::DeferredRepository;
+pub use indexed::IndexedRepository;
+pub use memory::InMemoryTaskRepository;
+pub use postgres::PostgresTaskRepository;
+pub use sqlite::SqliteTaskRepository;
+pub use wal::WalConfig;
+
+pub type StorageResult<T> = Result<T, StorageError>;
+
+#[derive(Debug)]
+pub enum StorageError {
+    // The backend could not be reached
+    Unavailable(String),
+    // Any other backend failure
+    Internal(String),
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StorageError::Unavailable(msg) => write!(f, "storage unavailable: {}", msg),
+            StorageError::Internal(msg) => write!(f, "storage error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<sqlx::Error> for StorageError {
+    fn from(err: sqlx::Error) -> Self {
+        match err {
+            sqlx::Error::Io(_) | sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed => {
+                StorageError::Unavailable(err.to_string())
+            }
+            _ => StorageError::Internal(err.to_string()),
+        }
+    }
+}
+
+impl From<std::io::Error> for StorageError {
+    fn from(err: std::io::Error) -> Self {
+        StorageError::Internal(err.to_string())
+    }
+}
+
+impl From<serde_json::Error> for StorageError {
+    fn from(err: serde_json::Error) -> Self {
+        StorageError::Internal(err.to_string())
+    }
+}
+
+impl From<sqlx::migrate::MigrateError> for StorageError {
+    fn from(err: sqlx::migrate::MigrateError) -> Self {
+        StorageError::Internal(format!("migration failed: {}", err))
+    }
+}
+
+impl ResponseError for StorageError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            StorageError::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            StorageError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status_code()).json(serde_json::json!({
+            "error": self.to_string()
+        }))
+    }
+}
+
+// Outcome of a write that only goes through while the task is still at the
+// version the caller read
+#[derive(Debug)]
+pub enum Conditional {
+    // The updated or removed task
+    Applied(Task),
+    NotFound,
+    // The task was modified in the meantime
+    Conflict,
+}
+
+// Deleting a task only moves it to the trash (see `Task::deleted_at`). Every
+// method but the trash ones sees live tasks only: a trashed task cannot be
+// fetched, listed, searched, updated or deleted again until it is restored.
+// Each write also records a revision of the task (see `history`).
+#[async_trait]
+pub trait TaskRepository: Send + Sync {
+    // Cheap round trip used by the health check
+    async fn ping(&self) -> StorageResult<()>;
+
+    // Fails only when the store is wedged, e.g. its lock cannot be taken
+    // within `deadline`. An unreachable database is a readiness problem.
+    async fn liveness(&self, _deadline: Duration) -> StorageResult<()> {
+        Ok(())
+    }
+
+    async fn list(&self) -> StorageResult<Vec<Task>>;
+
+    // One page of the tasks matching `query.filter`, in `query.sort` order
+    async fn list_page(&self, query: &TaskQuery) -> StorageResult<TaskPage>;
+
+    // Full-text search over titles and descriptions. Backends are wrapped in
+    // an `IndexedRepository`; without one, every task is indexed per search.
+    async fn search(&self, query: &SearchQuery) -> StorageResult<SearchResults> {
+        Ok(SearchIndex::from_tasks(self.list().await?).search(query))
+    }
+
+    // Tags of live tasks and how many have each, by tag
+    async fn tags(&self) -> StorageResult<Vec<TagCount>> {
+        Ok(tags::count(&self.list().await?))
+    }
+
+    // Replace every tag in `from` with `to` on each live task that has one of
+    // them, all or nothing. Returns the tasks that changed, or `None` without
+    // changing anything when `merge` is false and a live task already has
+    // `to`; the check and the rewrite are one atomic step.
+    async fn retag(
+        &self,
+        from: &[String],
+        to: &str,
+        merge: bool,
+    ) -> StorageResult<Option<Vec<Task>>>;
+
+    async fn create(&self, task: Task) -> StorageResult<Task>;
+
+    async fn get(&self, id: &str) -> StorageResult<Option<Task>>;
+
+    // Returns `None` when no task with the given id exists
+    async fn update(&self, id: &str, changes: TaskUpdate) -> StorageResult<Option<Task>>;
+
+    // Like `update`, but only while the task is still at `version`
+    async fn update_if_version(
+        &self,
+        id: &str,
+        changes: TaskUpdate,
+        version: i64,
+    ) -> StorageResult<Conditional>;
+
+    // Move a task to the trash. Returns the trashed task, or `None` when there
+    // is no such live task.
+    async fn delete(&self, id: &str) -> StorageResult<Option<Task>>;
+
+    // Like `delete`, but only while the task is still at `version`
+    async fn delete_if_version(&self, id: &str, version: i64) -> StorageResult<Conditional>;
+
+    // Take a task back out of the trash. Returns `None` when it is not there.
+    async fn restore(&self, id: &str) -> StorageResult<Option<Task>>;
+
+    // Permanently remove a task from the trash, and its history with it.
+    // Returns `None` when it is not there.
+    async fn purge(&self, id: &str) -> StorageResult<Option<Task>>;
+
+    // Permanently remove every task trashed before `cutoff`, returning their ids
+    async fn purge_trashed_before(&self, cutoff: DateTime<Utc>) -> StorageResult<Vec<String>>;
+
+    // Every recorded revision of a task, oldest first. Purging a task drops
+    // its history.
+    async fn history(&self, id: &str) -> StorageResult<Vec<Revision>>;
+
+    async fn revision(&self, id: &str, revision: i64) -> StorageResult<Option<Revision>> {
+        let history = self.history(id).await?;
+        Ok(history.into_iter().find(|recorded| recorded.revision == revision))
+    }
+
+    // Apply `operations` in order, all or nothing. Returns the outcome of each
+    // operation up to the first one that was not applied; unless all of them
+    // were, nothing was committed.
+    async fn apply_atomically(
+        &self,
+        operations: Vec<BatchOperation>,
+    ) -> StorageResult<Vec<Conditional>>;
+}
+
+// Storage settings read from the environment
+pub struct StorageConfig {
+    pub database_url: Option<String>,
+    pub max_connections: u32,
+    // Write-ahead log settings for the in-memory store
+    pub wal: Option<WalConfig>,
+}
+
+impl StorageConfig {
+    pub fn from_env() -> Self {
+        StorageConfig {
+            database_url: std::env::var("DATABASE_URL").ok().filter(|url| !url.is_empty()),
+            max_connections: std::env::var("DATABASE_MAX_CONNECTIONS")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(5),
+            wal: WalConfig::from_env(),
+        }
+    }
+}
+
+// Open the configured backend and build its search index
+pub async fn connect(config: &StorageConfig) -> StorageResult<Arc<dyn TaskRepository>> {
+    let backend = connect_backend(config).await?;
+    Ok(Arc::new(IndexedRepository::build(backend).await?))
+}
+
+// Pick the backend from DATABASE_URL, falling back to in-memory storage
+async fn connect_backend(config: &StorageConfig) -> StorageResult<Arc<dyn TaskRepository>> {
+    match config.database_url.as_deref() {
+        Some(url) if url.starts_with("postgres://") || url.starts_with("postgresql://") => {
+            info!("Using PostgreSQL task storage");
+            let repository = PostgresTaskRepository::connect(url, config.max_connections).await?;
+            Ok(Arc::new(repository))
+        }
+        Some(url) if url.starts_with("sqlite:") => {
+            info!("Using SQLite task storage");
+            let repository = SqliteTaskRepository::connect(url, config.max_connections).await?;
+            Ok(Arc::new(repository))
+        }
+        Some(url) => Err(StorageError::Internal(format!(
+            "unsupported DATABASE_URL scheme: {}",
+            url.split("://").next().unwrap_or(url)
+        ))),
+        None => match &config.wal {
+            Some(wal) => {
+                info!("Using in-memory task storage with write-ahead log in {}", wal.dir.display());
+                Ok(Arc::new(InMemoryTaskRepository::open(wal.clone())?))
+            }
+            None => {
+                info!("DATABASE_URL not set, using in-memory task storage");
+                Ok(Arc::new(InMemoryTaskRepository::new()))
+            }
+        },
+    }
+}
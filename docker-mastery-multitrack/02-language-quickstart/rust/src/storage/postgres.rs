@@ -0,0 +1,976 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use sqlx::postgres::{
+    PgArguments, PgConnection, PgExecutor, PgPool, PgPoolOptions, PgRow, Postgres,
+};
+use sqlx::query::Query;
+use sqlx::Row;
+use std::time::Duration;
+use uuid::Uuid;
+
+use super::sql::{self, priority_from_rank, Dialect, SqlQuery, SqlValue};
+use super::{Conditional, StorageError, StorageResult, TaskRepository};
+use crate::batch::BatchOperation;
+use crate::history::{Action, Revision};
+use crate::models::{Priority, Task, TaskUpdate};
+use crate::query::{TaskFilter, TaskPage, TaskQuery};
+use crate::tags::{self, TagCount};
+
+// A task's tags live in task_tags and are read back with each row
+const TASK_COLUMNS: &str =
+    "id, title, description, completed, priority, due_at, created_at, updated_at, version, \
+     deleted_at, \
+     ARRAY(SELECT tag FROM task_tags WHERE task_id = tasks.id ORDER BY tag COLLATE \"C\") AS tags";
+
+// Compose may start the API before postgres accepts connections
+const CONNECT_ATTEMPTS: u32 = 10;
+const CONNECT_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+// PostgreSQL storage using the schema from TASK_API_SPECIFICATION.md
+pub struct PostgresTaskRepository {
+    pool: PgPool,
+}
+
+impl PostgresTaskRepository {
+    // Connect to the database and apply any pending migrations
+    pub async fn connect(database_url: &str, max_connections: u32) -> StorageResult<Self> {
+        let options = PgPoolOptions::new()
+            .max_connections(max_connections)
+            .acquire_timeout(Duration::from_secs(5));
+
+        let mut attempt = 1;
+        let pool = loop {
+            match options.clone().connect(database_url).await {
+                Ok(pool) => break pool,
+                Err(err) if attempt < CONNECT_ATTEMPTS => {
+                    warn!(
+                        "PostgreSQL not reachable (attempt {}/{}): {}",
+                        attempt, CONNECT_ATTEMPTS, err
+                    );
+                    attempt += 1;
+                    tokio::time::sleep(CONNECT_RETRY_DELAY).await;
+                }
+                Err(err) => return Err(err.into()),
+            }
+        };
+
+        sqlx::migrate!("./migrations/postgres").run(&pool).await?;
+        info!("PostgreSQL migrations applied");
+
+        Ok(Self { pool })
+    }
+}
+
+fn task_from_row(row: PgRow) -> Result<Task, sqlx::Error> {
+    Ok(Task {
+        id: row.try_get::<Uuid, _>("id")?.to_string(),
+        title: row.try_get("title")?,
+        description: row.try_get("description")?,
+        completed: row.try_get("completed")?,
+        priority: priority_from_rank(row.try_get("priority")?)?,
+        tags: row.try_get("tags")?,
+        due_at: row.try_get("due_at")?,
+        created_at: row.try_get("created_at")?,
+        updated_at: row.try_get("updated_at")?,
+        version: row.try_get("version")?,
+        deleted_at: row.try_get("deleted_at")?,
+    })
+}
+
+fn parse_id(id: &str) -> StorageResult<Uuid> {
+    Uuid::parse_str(id).map_err(|err| StorageError::Internal(err.to_string()))
+}
+
+async fn insert_row(conn: &mut PgConnection, task: &Task) -> StorageResult<Task> {
+    let id = parse_id(&task.id)?;
+    let row = sqlx::query(&format!(
+        "INSERT INTO tasks (id, title, description, completed, priority, due_at, created_at, \
+            updated_at, version, deleted_at) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10) RETURNING {}",
+        TASK_COLUMNS
+    ))
+    .bind(id)
+    .bind(&task.title)
+    .bind(&task.description)
+    .bind(task.completed)
+    .bind(task.priority.rank())
+    .bind(task.due_at)
+    .bind(task.created_at)
+    .bind(task.updated_at)
+    .bind(task.version)
+    .bind(task.deleted_at)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let mut created = task_from_row(row)?;
+    set_tags(conn, id, &task.tags).await?;
+    created.tags = task.tags.clone();
+    record(conn, Action::Created, &created).await?;
+    Ok(created)
+}
+
+// Replace a task's tags
+async fn set_tags(conn: &mut PgConnection, id: Uuid, tags: &[String]) -> StorageResult<()> {
+    sqlx::query("DELETE FROM task_tags WHERE task_id = $1")
+        .bind(id)
+        .execute(&mut *conn)
+        .await?;
+    for tag in tags {
+        sqlx::query("INSERT INTO task_tags (task_id, tag) VALUES ($1, $2)")
+            .bind(id)
+            .bind(tag)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+// With `version`, only updates a task that is still at that version
+async fn update_row(
+    conn: &mut PgConnection,
+    id: Uuid,
+    changes: TaskUpdate,
+    version: Option<i64>,
+) -> StorageResult<Option<Task>> {
+    // updated_at is maintained by the update_tasks_updated_at trigger
+    let row = sqlx::query(&format!(
+        "UPDATE tasks SET \
+            title = COALESCE($2, title), \
+            description = COALESCE($3, description), \
+            completed = COALESCE($4, completed), \
+            priority = COALESCE($8, priority), \
+            due_at = CASE WHEN $6::boolean THEN $7::timestamptz ELSE due_at END, \
+            version = version + 1 \
+         WHERE id = $1 AND deleted_at IS NULL AND ($5::bigint IS NULL OR version = $5) \
+         RETURNING {}",
+        TASK_COLUMNS
+    ))
+    .bind(id)
+    .bind(changes.title)
+    .bind(changes.description)
+    .bind(changes.completed)
+    .bind(version)
+    .bind(changes.due_at.is_some())
+    .bind(changes.due_at.flatten())
+    .bind(changes.priority.map(Priority::rank))
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let mut task = task_from_row(row)?;
+    if let Some(tags) = changes.tags {
+        set_tags(conn, id, &tags).await?;
+        task.tags = tags;
+    }
+    record(conn, Action::Updated, &task).await?;
+    Ok(Some(task))
+}
+
+// Move a live task to the trash (`deleted`) or a trashed one back out. With
+// `version`, only while the task is still at that version.
+async fn trash_row(
+    conn: &mut PgConnection,
+    id: Uuid,
+    deleted: bool,
+    version: Option<i64>,
+) -> StorageResult<Option<Task>> {
+    let (deleted_at, in_trash) = if deleted {
+        ("CURRENT_TIMESTAMP", "deleted_at IS NULL")
+    } else {
+        ("NULL", "deleted_at IS NOT NULL")
+    };
+    let row = sqlx::query(&format!(
+        "UPDATE tasks SET deleted_at = {}, version = version + 1 \
+         WHERE id = $1 AND {} AND ($2::bigint IS NULL OR version = $2) RETURNING {}",
+        deleted_at, in_trash, TASK_COLUMNS
+    ))
+    .bind(id)
+    .bind(version)
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let task = task_from_row(row)?;
+    let action = if deleted { Action::Deleted } else { Action::Restored };
+    record(conn, action, &task).await?;
+    Ok(Some(task))
+}
+
+// Add a revision of `task` to its history, in the caller's transaction
+async fn record(conn: &mut PgConnection, action: Action, task: &Task) -> StorageResult<()> {
+    let revision = Revision::new(action, task.clone());
+    sqlx::query(
+        "INSERT INTO task_revisions (task_id, revision, action, actor, changed_at, task) \
+         VALUES ($1, $2, $3, $4, $5, $6::jsonb)",
+    )
+    .bind(parse_id(&task.id)?)
+    .bind(revision.revision)
+    .bind(revision.action.name())
+    .bind(&revision.actor)
+    .bind(revision.changed_at)
+    .bind(serde_json::to_string(&revision.task)?)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+fn revision_from_row(row: PgRow) -> StorageResult<Revision> {
+    let action: String = row.try_get("action")?;
+    let task: String = row.try_get("task")?;
+    Ok(Revision {
+        revision: row.try_get("revision")?,
+        action: action.parse().map_err(StorageError::Internal)?,
+        actor: row.try_get("actor")?,
+        changed_at: row.try_get("changed_at")?,
+        task: serde_json::from_str(&task)?,
+    })
+}
+
+// Why a conditional write matched no row
+async fn missed(executor: impl PgExecutor<'_>, id: Uuid) -> StorageResult<Conditional> {
+    let exists: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM tasks WHERE id = $1 AND deleted_at IS NULL)")
+        .bind(id)
+        .fetch_one(executor)
+        .await?;
+    Ok(if exists { Conditional::Conflict } else { Conditional::NotFound })
+}
+
+// Run one batch operation on a connection, usually inside a transaction
+async fn apply_operation(
+    conn: &mut PgConnection,
+    operation: BatchOperation,
+) -> StorageResult<Conditional> {
+    match operation {
+        BatchOperation::Create(task) => Ok(Conditional::Applied(insert_row(conn, &task).await?)),
+        BatchOperation::Update { id, changes, version } => {
+            let Ok(id) = Uuid::parse_str(&id) else {
+                return Ok(Conditional::NotFound);
+            };
+            match update_row(&mut *conn, id, changes, version).await? {
+                Some(task) => Ok(Conditional::Applied(task)),
+                None => missed(conn, id).await,
+            }
+        }
+        BatchOperation::Delete { id, version } => {
+            let Ok(id) = Uuid::parse_str(&id) else {
+                return Ok(Conditional::NotFound);
+            };
+            match trash_row(&mut *conn, id, true, version).await? {
+                Some(task) => Ok(Conditional::Applied(task)),
+                None => missed(conn, id).await,
+            }
+        }
+    }
+}
+
+// Bind the rendered parameters in order
+fn bind_params(sql: &SqlQuery) -> StorageResult<Query<'_, Postgres, PgArguments>> {
+    let mut query = sqlx::query(&sql.sql);
+    for param in &sql.params {
+        query = match param {
+            SqlValue::Bool(value) => query.bind(*value),
+            SqlValue::Int(value) => query.bind(*value),
+            SqlValue::Time(value) => query.bind(*value),
+            SqlValue::Text(value) => query.bind(value.as_str()),
+            SqlValue::Id(value) => query.bind(
+                Uuid::parse_str(value).map_err(|err| StorageError::Internal(err.to_string()))?,
+            ),
+        };
+    }
+    Ok(query)
+}
+
+#[async_trait]
+impl TaskRepository for PostgresTaskRepository {
+    async fn ping(&self) -> StorageResult<()> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn list(&self) -> StorageResult<Vec<Task>> {
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM tasks WHERE deleted_at IS NULL ORDER BY created_at, id",
+            TASK_COLUMNS
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(task_from_row).collect::<Result<_, _>>()?)
+    }
+
+    async fn list_page(&self, query: &TaskQuery) -> StorageResult<TaskPage> {
+        let select = sql::select_page(Dialect::Postgres, TASK_COLUMNS, query);
+        let rows = bind_params(&select)?.fetch_all(&self.pool).await?;
+
+        let count = sql::count(Dialect::Postgres, &query.filter);
+        let total: i64 = bind_params(&count)?.fetch_one(&self.pool).await?.try_get(0)?;
+
+        let tasks = rows.into_iter().map(task_from_row).collect::<Result<_, _>>()?;
+        Ok(TaskPage::new(tasks, query, total as usize))
+    }
+
+    async fn tags(&self) -> StorageResult<Vec<TagCount>> {
+        let rows = sqlx::query(
+            "SELECT tag, COUNT(*) AS count FROM task_tags \
+             JOIN tasks ON tasks.id = task_tags.task_id \
+             WHERE tasks.deleted_at IS NULL GROUP BY tag ORDER BY tag COLLATE \"C\"",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                let count: i64 = row.try_get("count")?;
+                Ok(TagCount {
+                    tag: row.try_get("tag")?,
+                    count: count as usize,
+                })
+            })
+            .collect()
+    }
+
+    async fn retag(
+        &self,
+        from: &[String],
+        to: &str,
+        merge: bool,
+    ) -> StorageResult<Option<Vec<Task>>> {
+        let filter = TaskFilter {
+            tags_any: Some(from.to_vec()),
+            ..TaskFilter::default()
+        };
+        let mut select = sql::select(Dialect::Postgres, TASK_COLUMNS, &filter);
+        select.push(" FOR UPDATE");
+
+        let mut tx = self.pool.begin().await?;
+        if !merge && !from.iter().any(|tag| tag == to) {
+            // Keeps any other write from giving a task `to` until this commits
+            sqlx::query("LOCK TABLE task_tags IN SHARE ROW EXCLUSIVE MODE")
+                .execute(&mut *tx)
+                .await?;
+            let taken = TaskFilter {
+                tags_any: Some(vec![to.to_string()]),
+                ..TaskFilter::default()
+            };
+            let count = sql::count(Dialect::Postgres, &taken);
+            let count: i64 = bind_params(&count)?.fetch_one(&mut *tx).await?.try_get(0)?;
+            if count > 0 {
+                return Ok(None);
+            }
+        }
+        let rows = bind_params(&select)?.fetch_all(&mut *tx).await?;
+        let mut retagged = Vec::with_capacity(rows.len());
+        for row in rows {
+            let task = task_from_row(row)?;
+            let Some(tags) = tags::retagged(&task.tags, from, to) else {
+                continue;
+            };
+            let changes = TaskUpdate {
+                tags: Some(tags),
+                ..TaskUpdate::default()
+            };
+            retagged.extend(update_row(&mut tx, parse_id(&task.id)?, changes, None).await?);
+        }
+        tx.commit().await?;
+        Ok(Some(retagged))
+    }
+
+    async fn create(&self, task: Task) -> StorageResult<Task> {
+        let mut tx = self.pool.begin().await?;
+        let task = insert_row(&mut tx, &task).await?;
+        tx.commit().await?;
+        Ok(task)
+    }
+
+    async fn get(&self, id: &str) -> StorageResult<Option<Task>> {
+        let Ok(id) = Uuid::parse_str(id) else {
+            return Ok(None);
+        };
+
+        let row = sqlx::query(&format!(
+            "SELECT {} FROM tasks WHERE id = $1 AND deleted_at IS NULL",
+            TASK_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(task_from_row).transpose()?)
+    }
+
+    async fn update(&self, id: &str, changes: TaskUpdate) -> StorageResult<Option<Task>> {
+        let Ok(id) = Uuid::parse_str(id) else {
+            return Ok(None);
+        };
+
+        let mut tx = self.pool.begin().await?;
+        let written = update_row(&mut tx, id, changes, None).await?;
+        tx.commit().await?;
+        Ok(written)
+    }
+
+    async fn update_if_version(
+        &self,
+        id: &str,
+        changes: TaskUpdate,
+        version: i64,
+    ) -> StorageResult<Conditional> {
+        let Ok(id) = Uuid::parse_str(id) else {
+            return Ok(Conditional::NotFound);
+        };
+
+        let mut tx = self.pool.begin().await?;
+        let outcome = match update_row(&mut tx, id, changes, Some(version)).await? {
+            Some(task) => Conditional::Applied(task),
+            None => missed(&mut *tx, id).await?,
+        };
+        tx.commit().await?;
+        Ok(outcome)
+    }
+
+    async fn delete(&self, id: &str) -> StorageResult<Option<Task>> {
+        let Ok(id) = Uuid::parse_str(id) else {
+            return Ok(None);
+        };
+
+        let mut tx = self.pool.begin().await?;
+        let written = trash_row(&mut tx, id, true, None).await?;
+        tx.commit().await?;
+        Ok(written)
+    }
+
+    async fn delete_if_version(&self, id: &str, version: i64) -> StorageResult<Conditional> {
+        let Ok(id) = Uuid::parse_str(id) else {
+            return Ok(Conditional::NotFound);
+        };
+
+        let mut tx = self.pool.begin().await?;
+        let outcome = match trash_row(&mut tx, id, true, Some(version)).await? {
+            Some(task) => Conditional::Applied(task),
+            None => missed(&mut *tx, id).await?,
+        };
+        tx.commit().await?;
+        Ok(outcome)
+    }
+
+    async fn restore(&self, id: &str) -> StorageResult<Option<Task>> {
+        let Ok(id) = Uuid::parse_str(id) else {
+            return Ok(None);
+        };
+
+        let mut tx = self.pool.begin().await?;
+        let written = trash_row(&mut tx, id, false, None).await?;
+        tx.commit().await?;
+        Ok(written)
+    }
+
+    async fn purge(&self, id: &str) -> StorageResult<Option<Task>> {
+        let Ok(id) = Uuid::parse_str(id) else {
+            return Ok(None);
+        };
+
+        let mut tx = self.pool.begin().await?;
+        let row = sqlx::query(&format!(
+            "DELETE FROM tasks WHERE id = $1 AND deleted_at IS NOT NULL RETURNING {}",
+            TASK_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let task = task_from_row(row)?;
+        sqlx::query("DELETE FROM task_tags WHERE task_id = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM task_revisions WHERE task_id = $1")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(Some(task))
+    }
+
+    async fn purge_trashed_before(&self, cutoff: DateTime<Utc>) -> StorageResult<Vec<String>> {
+        let mut tx = self.pool.begin().await?;
+        let rows = sqlx::query(&format!(
+            "DELETE FROM tasks WHERE deleted_at < $1 RETURNING {}",
+            TASK_COLUMNS
+        ))
+        .bind(cutoff)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let ids = rows
+            .into_iter()
+            .map(|row| task_from_row(row).map(|task| task.id))
+            .collect::<Result<Vec<_>, _>>()?;
+        for table in ["task_tags", "task_revisions"] {
+            sqlx::query(&format!(
+                "DELETE FROM {} WHERE task_id NOT IN (SELECT id FROM tasks)",
+                table
+            ))
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(ids)
+    }
+
+    async fn history(&self, id: &str) -> StorageResult<Vec<Revision>> {
+        let Ok(id) = Uuid::parse_str(id) else {
+            return Ok(Vec::new());
+        };
+
+        let rows = sqlx::query(
+            "SELECT revision, action, actor, changed_at, task::text AS task FROM task_revisions \
+             WHERE task_id = $1 ORDER BY revision",
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(revision_from_row).collect()
+    }
+
+    async fn apply_atomically(
+        &self,
+        operations: Vec<BatchOperation>,
+    ) -> StorageResult<Vec<Conditional>> {
+        let mut tx = self.pool.begin().await?;
+        let mut outcomes = Vec::with_capacity(operations.len());
+
+        for operation in operations {
+            let outcome = apply_operation(&mut tx, operation).await?;
+            let applied = matches!(outcome, Conditional::Applied(_));
+            outcomes.push(outcome);
+            if !applied {
+                tx.rollback().await?;
+                return Ok(outcomes);
+            }
+        }
+
+        tx.commit().await?;
+        Ok(outcomes)
+    }
+}
+
+// These need a PostgreSQL database: run them with
+// `DATABASE_URL=postgres://... cargo test -- --ignored`
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterExpr;
+    use crate::models::TaskCreate;
+    use crate::query::{SortField, SortKey};
+    use crate::storage::InMemoryTaskRepository;
+
+    // A schema of its own for one test, so tests can share the database
+    struct Schema {
+        name: String,
+        admin: PgPool,
+    }
+
+    impl Schema {
+        async fn drop(self) {
+            let sql = format!("DROP SCHEMA {} CASCADE", self.name);
+            sqlx::query(&sql).execute(&self.admin).await.unwrap();
+        }
+    }
+
+    // A migrated repository in a fresh schema of DATABASE_URL
+    async fn repository() -> (PostgresTaskRepository, Schema) {
+        let url = std::env::var("DATABASE_URL").expect("DATABASE_URL must name a database");
+        let admin = PgPoolOptions::new().max_connections(1).connect(&url).await.unwrap();
+        let name = format!("task_api_test_{}", Uuid::new_v4().simple());
+        sqlx::query(&format!("CREATE SCHEMA {}", name)).execute(&admin).await.unwrap();
+
+        let separator = if url.contains('?') { '&' } else { '?' };
+        let url = format!("{}{}options=-c%20search_path%3D{}", url, separator, name);
+        let repository = PostgresTaskRepository::connect(&url, 2).await.unwrap();
+        (repository, Schema { name, admin })
+    }
+
+    fn task(title: &str, priority: Priority, tags: &[&str]) -> Task {
+        Task::new(TaskCreate {
+            title: title.to_string(),
+            description: format!("About {}", title),
+            priority,
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            due_at: None,
+        })
+    }
+
+    fn query(sort: Vec<SortKey>, limit: usize) -> TaskQuery {
+        TaskQuery {
+            filter: TaskFilter {
+                now: Utc::now(),
+                ..Default::default()
+            },
+            sort,
+            limit,
+            after: None,
+        }
+    }
+
+    fn by_title() -> Vec<SortKey> {
+        vec![SortKey {
+            field: SortField::Title,
+            descending: false,
+        }]
+    }
+
+    // Every page of `query`, following cursors
+    async fn all_pages(repository: &dyn TaskRepository, mut query: TaskQuery) -> Vec<String> {
+        let mut ids = Vec::new();
+        loop {
+            let page = repository.list_page(&query).await.unwrap();
+            ids.extend(page.tasks.iter().map(|task| task.id.clone()));
+            let Some(last) = page.tasks.last() else {
+                break;
+            };
+            if page.next_cursor.is_none() {
+                break;
+            }
+            query.after = Some(crate::query::Cursor::after(last, &query.sort));
+        }
+        ids
+    }
+
+    // Create each task in both `repository` and a memory store to compare with
+    async fn seed(
+        repository: &PostgresTaskRepository,
+        tasks: Vec<Task>,
+    ) -> InMemoryTaskRepository {
+        let memory = InMemoryTaskRepository::new();
+        for task in tasks {
+            repository.create(task.clone()).await.unwrap();
+            memory.create(task).await.unwrap();
+        }
+        memory
+    }
+
+    #[actix_web::test]
+    #[ignore]
+    async fn stores_and_reads_back_every_field() {
+        let (repository, schema) = repository().await;
+        // The longest title VARCHAR(255) takes, in multi-byte characters
+        let mut task = task(&"é".repeat(255), Priority::Urgent, &["docker", "rust"]);
+        task.due_at = Some("2030-01-02T03:04:05.123456Z".parse().unwrap());
+
+        let created = repository.create(task.clone()).await.unwrap();
+        let fetched = repository.get(&task.id).await.unwrap().unwrap();
+        for stored in [&created, &fetched] {
+            assert_eq!(stored.title, task.title);
+            assert_eq!(stored.description, task.description);
+            assert_eq!(stored.priority, Priority::Urgent);
+            assert_eq!(stored.tags, ["docker", "rust"]);
+            assert_eq!(stored.due_at, task.due_at);
+            assert_eq!(stored.created_at, task.created_at);
+            assert_eq!(stored.version, 1);
+        }
+        assert!(repository.get(&Uuid::new_v4().to_string()).await.unwrap().is_none());
+        // Ids that are not UUIDs name no task rather than failing the query
+        assert!(repository.get("not-a-uuid").await.unwrap().is_none());
+        schema.drop().await;
+    }
+
+    #[actix_web::test]
+    #[ignore]
+    async fn updates_only_at_the_expected_version() {
+        let (repository, schema) = repository().await;
+        let task = repository.create(task("Write", Priority::Normal, &[])).await.unwrap();
+        let changes = || TaskUpdate {
+            completed: Some(true),
+            ..Default::default()
+        };
+
+        let updated = repository.update_if_version(&task.id, changes(), 1).await.unwrap();
+        let Conditional::Applied(updated) = updated else {
+            panic!("expected the update to apply, got {:?}", updated);
+        };
+        assert!(updated.completed);
+        assert_eq!(updated.version, 2);
+        // Set by the update_tasks_updated_at trigger, and read back as stored
+        assert!(updated.updated_at > task.updated_at);
+        let fetched = repository.get(&task.id).await.unwrap().unwrap();
+        assert_eq!(fetched.updated_at, updated.updated_at);
+
+        let stale = repository.update_if_version(&task.id, changes(), 1).await.unwrap();
+        assert!(matches!(stale, Conditional::Conflict));
+        let missing = repository.update(&Uuid::new_v4().to_string(), changes()).await;
+        assert!(missing.unwrap().is_none());
+        schema.drop().await;
+    }
+
+    #[actix_web::test]
+    #[ignore]
+    async fn trashes_restores_and_purges() {
+        let (repository, schema) = repository().await;
+        let task = repository.create(task("Tidy", Priority::Low, &["home"])).await.unwrap();
+
+        let trashed = repository.delete(&task.id).await.unwrap().unwrap();
+        assert!(trashed.is_deleted());
+        assert!(repository.get(&task.id).await.unwrap().is_none());
+        assert!(repository.delete(&task.id).await.unwrap().is_none());
+        assert!(repository.tags().await.unwrap().is_empty());
+
+        let restored = repository.restore(&task.id).await.unwrap().unwrap();
+        assert!(!restored.is_deleted());
+        assert_eq!(restored.tags, ["home"]);
+
+        repository.delete(&task.id).await.unwrap();
+        let purged = repository.purge_trashed_before(Utc::now()).await.unwrap();
+        assert_eq!(purged, vec![task.id.clone()]);
+        assert!(repository.restore(&task.id).await.unwrap().is_none());
+        assert!(repository.purge(&task.id).await.unwrap().is_none());
+        assert!(repository.history(&task.id).await.unwrap().is_empty());
+        schema.drop().await;
+    }
+
+    #[actix_web::test]
+    #[ignore]
+    async fn pages_in_the_same_order_as_the_memory_store() {
+        let (repository, schema) = repository().await;
+        // Case and punctuation order differently under a locale collation
+        // than under the byte order the memory store uses
+        let titles = ["task", "Task", "_task", "Zebra", "apple", "Äpfel"];
+        let priorities = [Priority::High, Priority::Low, Priority::Normal];
+        let tasks = (0..12)
+            .map(|n| task(titles[n % titles.len()], priorities[n % 3], &[]))
+            .collect();
+        let memory = seed(&repository, tasks).await;
+
+        let sorts = [
+            vec![SortKey {
+                field: SortField::CreatedAt,
+                descending: false,
+            }],
+            by_title(),
+            vec![
+                SortKey {
+                    field: SortField::Priority,
+                    descending: true,
+                },
+                SortKey {
+                    field: SortField::Title,
+                    descending: true,
+                },
+            ],
+        ];
+        for sort in sorts {
+            let expected = all_pages(&memory, query(sort.clone(), 5)).await;
+            assert_eq!(expected.len(), 12);
+            assert_eq!(all_pages(&repository, query(sort, 5)).await, expected);
+        }
+        schema.drop().await;
+    }
+
+    #[actix_web::test]
+    #[ignore]
+    async fn matches_text_like_the_memory_store() {
+        let (repository, schema) = repository().await;
+        let titles = ["Build the Docker image", "docker compose", "Write Rust", "50% done"];
+        let tasks = titles.iter().map(|title| task(title, Priority::Normal, &[])).collect();
+        let memory = seed(&repository, tasks).await;
+
+        let filters = [
+            ("title~DOCKER", 2),
+            ("title~\"the d\"", 1),
+            ("description~about", 4),
+            ("title~%", 1),
+            ("NOT title~rust", 3),
+            ("title:\"Write Rust\"", 1),
+        ];
+        for (filter, count) in filters {
+            let mut query = query(by_title(), 50);
+            query.filter.expression = Some(FilterExpr::parse(filter).unwrap());
+            let expected = all_pages(&memory, query.clone()).await;
+            assert_eq!(expected.len(), count, "{}", filter);
+            assert_eq!(all_pages(&repository, query).await, expected, "{}", filter);
+        }
+        schema.drop().await;
+    }
+
+    #[actix_web::test]
+    #[ignore]
+    async fn filters_on_due_dates_like_the_memory_store() {
+        let (repository, schema) = repository().await;
+        let dues = [None, Some("2020-05-01T12:00:00Z"), Some("2040-05-01T12:00:00Z")];
+        let mut tasks = Vec::new();
+        for (n, due_at) in dues.into_iter().enumerate() {
+            for completed in [false, true] {
+                let mut task = task(&format!("Task {}", n), Priority::Normal, &[]);
+                task.due_at = due_at.map(|due_at| due_at.parse().unwrap());
+                task.completed = completed;
+                tasks.push(task);
+            }
+        }
+        let memory = seed(&repository, tasks).await;
+
+        let filters = [
+            ("due:none", 2),
+            ("due!=none", 4),
+            ("NOT due:none", 4),
+            ("due<2030-01-01", 2),
+            ("NOT due<2030-01-01", 4),
+            ("due:2020-05-01", 2),
+            ("due!=2020-05-01", 4),
+            ("due>=2040-05-01T12:00:00Z OR due:none", 4),
+        ];
+        for (filter, count) in filters {
+            let mut query = query(by_title(), 50);
+            query.filter.expression = Some(FilterExpr::parse(filter).unwrap());
+            let expected = all_pages(&memory, query.clone()).await;
+            assert_eq!(expected.len(), count, "{}", filter);
+            assert_eq!(all_pages(&repository, query).await, expected, "{}", filter);
+        }
+
+        // Undated tasks sort after every due date in both directions' pages
+        for descending in [false, true] {
+            let sort = vec![SortKey {
+                field: SortField::DueAt,
+                descending,
+            }];
+            let expected = all_pages(&memory, query(sort.clone(), 4)).await;
+            assert_eq!(all_pages(&repository, query(sort, 4)).await, expected);
+        }
+
+        // Only the open task due in the past is overdue
+        for (overdue, count) in [(true, 1), (false, 5)] {
+            let mut query = query(by_title(), 50);
+            query.filter.overdue = Some(overdue);
+            let expected = all_pages(&memory, query.clone()).await;
+            assert_eq!(expected.len(), count);
+            assert_eq!(all_pages(&repository, query).await, expected);
+        }
+        schema.drop().await;
+    }
+
+    #[actix_web::test]
+    #[ignore]
+    async fn picks_the_same_next_task_as_the_memory_store() {
+        let (repository, schema) = repository().await;
+        let dues = [None, Some("2030-01-01T00:00:00Z"), Some("2030-01-01T00:00:00.5Z")];
+        let priorities = [Priority::High, Priority::Low, Priority::Urgent, Priority::Normal];
+        let tasks = (0..12)
+            .map(|n| {
+                let mut task = task(&format!("Task {}", n), priorities[n % 4], &[]);
+                task.due_at = dues[n % 3].map(|due_at| due_at.parse().unwrap());
+                task.completed = n == 2;
+                task
+            })
+            .collect();
+        let memory = seed(&repository, tasks).await;
+
+        let mut query = crate::query::next_task();
+        let next = repository.list_page(&query).await.unwrap().tasks;
+        assert_eq!(next[0].title, "Task 10", "the open urgent task with a due date");
+
+        query.limit = 5;
+        let expected = all_pages(&memory, query.clone()).await;
+        assert_eq!(expected.len(), 11);
+        assert_eq!(all_pages(&repository, query.clone()).await, expected);
+
+        for filter in ["priority>=high", "priority:low OR priority!=urgent", "priority<normal"] {
+            query.filter.expression = Some(FilterExpr::parse(filter).unwrap());
+            let expected = all_pages(&memory, query.clone()).await;
+            assert!(!expected.is_empty(), "{}", filter);
+            assert_eq!(all_pages(&repository, query.clone()).await, expected, "{}", filter);
+        }
+        schema.drop().await;
+    }
+
+    #[actix_web::test]
+    #[ignore]
+    async fn filters_on_tags_like_the_memory_store() {
+        let (repository, schema) = repository().await;
+        let tag_sets: [&[&str]; 4] = [&[], &["docker"], &["docker", "rust"], &["rust"]];
+        let tasks = tag_sets
+            .into_iter()
+            .enumerate()
+            .map(|(n, tags)| task(&format!("Task {}", n), Priority::Normal, tags))
+            .collect();
+        let memory = seed(&repository, tasks).await;
+
+        let tags = |tags: &[&str]| Some(tags.iter().map(|tag| tag.to_string()).collect());
+        let filters = [
+            (tags(&["docker", "rust"]), None, None, 3),
+            (None, tags(&["docker", "rust"]), None, 1),
+            (None, tags(&["docker"]), None, 2),
+            (tags(&["docker"]), tags(&["rust"]), None, 1),
+            (None, None, Some("tags:rust"), 2),
+            (None, None, Some("tags!=rust"), 2),
+            (None, None, Some("NOT tags:docker AND tags!=rust"), 1),
+            (None, None, Some("tags:Docker OR tags:go"), 2),
+        ];
+        for (tags_any, tags_all, expression, count) in filters {
+            let mut query = query(by_title(), 50);
+            query.filter.tags_any = tags_any;
+            query.filter.tags_all = tags_all;
+            query.filter.expression = expression.map(|filter| FilterExpr::parse(filter).unwrap());
+            let expected = all_pages(&memory, query.clone()).await;
+            assert_eq!(expected.len(), count, "{:?}", query.filter);
+            assert_eq!(all_pages(&repository, query).await, expected);
+        }
+        schema.drop().await;
+    }
+
+    #[actix_web::test]
+    #[ignore]
+    async fn renames_a_tag_only_to_an_unused_name() {
+        let (repository, schema) = repository().await;
+        let docker = repository.create(task("Build", Priority::Normal, &["docker"])).await.unwrap();
+        repository.create(task("Write", Priority::Normal, &["rust"])).await.unwrap();
+        let from = ["docker".to_string()];
+
+        assert!(repository.retag(&from, "rust", false).await.unwrap().is_none());
+        assert_eq!(repository.get(&docker.id).await.unwrap().unwrap().tags, ["docker"]);
+
+        let merged = repository.retag(&from, "rust", true).await.unwrap().unwrap();
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].tags, ["rust"]);
+        let tags = repository.tags().await.unwrap();
+        assert_eq!((tags[0].tag.as_str(), tags[0].count), ("rust", 2));
+        schema.drop().await;
+    }
+
+    #[actix_web::test]
+    #[ignore]
+    async fn rolls_back_a_batch_that_fails_part_way() {
+        let (repository, schema) = repository().await;
+        let existing = repository.create(task("Existing", Priority::Normal, &[])).await.unwrap();
+
+        let outcomes = repository
+            .apply_atomically(vec![
+                BatchOperation::Create(task("New", Priority::Normal, &[])),
+                BatchOperation::Delete {
+                    id: existing.id.clone(),
+                    version: None,
+                },
+                BatchOperation::Update {
+                    id: existing.id.clone(),
+                    changes: TaskUpdate::default(),
+                    version: None,
+                },
+            ])
+            .await
+            .unwrap();
+
+        assert_eq!(outcomes.len(), 3);
+        assert!(matches!(outcomes[2], Conditional::NotFound));
+        let tasks = repository.list().await.unwrap();
+        let titles: Vec<_> = tasks.iter().map(|task| task.title.as_str()).collect();
+        assert_eq!(titles, ["Existing"]);
+        assert_eq!(repository.history(&existing.id).await.unwrap().len(), 1);
+        schema.drop().await;
+    }
+}
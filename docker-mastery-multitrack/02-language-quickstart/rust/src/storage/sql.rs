@@ -0,0 +1,357 @@
+/*!
+ * SQL rendering of list queries, shared by the PostgreSQL and SQLite backends
+ *
+ * Queries are rendered to a string plus positional parameters so both
+ * backends filter, sort and paginate exactly like `TaskQuery::paginate`.
+ */
+
+use chrono::{DateTime, SecondsFormat, Utc};
+
+use crate::filter::{Field, FilterExpr, Operator, Value};
+use crate::models::Priority;
+use crate::query::{no_due_date, SortField, SortKey, SortValue, TaskFilter, TaskQuery};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    Postgres,
+    Sqlite,
+}
+
+#[derive(Debug, Clone)]
+pub enum SqlValue {
+    Bool(bool),
+    Int(i64),
+    Time(DateTime<Utc>),
+    Text(String),
+    // A task id; a UUID column in PostgreSQL, text in SQLite
+    Id(String),
+}
+
+impl From<Value> for SqlValue {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Text(value) => SqlValue::Text(value),
+            Value::Bool(value) => SqlValue::Bool(value),
+            Value::Time(value) => SqlValue::Time(value),
+            Value::Priority(value) => SqlValue::Int(value.rank().into()),
+            Value::None => unreachable!("`none` is compared with IS NULL, not as a parameter"),
+        }
+    }
+}
+
+impl From<SortValue> for SqlValue {
+    fn from(value: SortValue) -> Self {
+        match value {
+            SortValue::Bool(value) => SqlValue::Bool(value),
+            SortValue::Time(value) => SqlValue::Time(value),
+            SortValue::Text(value) => SqlValue::Text(value),
+            SortValue::Priority(value) => SqlValue::Int(value.rank().into()),
+        }
+    }
+}
+
+// Read back a priority stored as its rank
+pub fn priority_from_rank(rank: i16) -> Result<Priority, sqlx::Error> {
+    Priority::from_rank(rank).ok_or_else(|| sqlx::Error::ColumnDecode {
+        index: "priority".to_string(),
+        source: format!("unknown priority rank {}", rank).into(),
+    })
+}
+
+pub struct SqlQuery {
+    dialect: Dialect,
+    pub sql: String,
+    pub params: Vec<SqlValue>,
+    has_where: bool,
+}
+
+impl SqlQuery {
+    pub fn new(dialect: Dialect, sql: impl Into<String>) -> Self {
+        SqlQuery {
+            dialect,
+            sql: sql.into(),
+            params: Vec::new(),
+            has_where: false,
+        }
+    }
+
+    pub fn push(&mut self, sql: &str) -> &mut Self {
+        self.sql.push_str(sql);
+        self
+    }
+
+    pub fn push_param(&mut self, value: impl Into<SqlValue>) -> &mut Self {
+        self.params.push(value.into());
+        match self.dialect {
+            Dialect::Postgres => self.sql.push_str(&format!("${}", self.params.len())),
+            Dialect::Sqlite => self.sql.push('?'),
+        }
+        self
+    }
+
+    // Start the next condition of the WHERE clause
+    fn and(&mut self) -> &mut Self {
+        let keyword = if self.has_where { " AND " } else { " WHERE " };
+        self.has_where = true;
+        self.push(keyword)
+    }
+
+    fn push_filter(&mut self, filter: &TaskFilter) {
+        self.and().push(if filter.trashed {
+            "deleted_at IS NOT NULL"
+        } else {
+            "deleted_at IS NULL"
+        });
+        if let Some(completed) = filter.completed {
+            self.and().push("completed = ").push_param(SqlValue::Bool(completed));
+        }
+        let bounds = [
+            ("created_at > ", filter.created_after),
+            ("created_at < ", filter.created_before),
+            ("updated_at > ", filter.updated_after),
+            ("updated_at < ", filter.updated_before),
+            ("due_at < ", filter.due_before),
+        ];
+        for (condition, bound) in bounds {
+            if let Some(bound) = bound {
+                self.and().push(condition).push_param(SqlValue::Time(bound));
+            }
+        }
+        // Must agree with `Task::is_overdue`; a NULL due date is never overdue
+        match filter.overdue {
+            Some(true) => {
+                self.and()
+                    .push("(completed = ")
+                    .push_param(SqlValue::Bool(false))
+                    .push(" AND due_at < ")
+                    .push_param(SqlValue::Time(filter.now))
+                    .push(")");
+            }
+            Some(false) => {
+                self.and()
+                    .push("(completed = ")
+                    .push_param(SqlValue::Bool(true))
+                    .push(" OR due_at IS NULL OR due_at >= ")
+                    .push_param(SqlValue::Time(filter.now))
+                    .push(")");
+            }
+            None => {}
+        }
+        if let Some(priorities) = &filter.priority {
+            self.and().push("priority IN (");
+            for (n, priority) in priorities.iter().enumerate() {
+                if n > 0 {
+                    self.push(", ");
+                }
+                self.push_param(SqlValue::Int(priority.rank().into()));
+            }
+            self.push(")");
+        }
+        if let Some(tags) = &filter.tags_any {
+            self.and().push("EXISTS (SELECT 1 FROM task_tags WHERE task_id = tasks.id AND ");
+            self.push_tags(tags).push(")");
+        }
+        // The tags are distinct, so a task has all of them when it has that many
+        if let Some(tags) = &filter.tags_all {
+            self.and().push("(SELECT COUNT(*) FROM task_tags WHERE task_id = tasks.id AND ");
+            self.push_tags(tags)
+                .push(") = ")
+                .push_param(SqlValue::Int(tags.len() as i64));
+        }
+        if let Some(expression) = &filter.expression {
+            self.and();
+            self.push_expression(expression);
+        }
+    }
+
+    // `tag IN (...)`, or a condition no row meets when `tags` is empty
+    fn push_tags(&mut self, tags: &[String]) -> &mut Self {
+        if tags.is_empty() {
+            return self.push("1 = 0");
+        }
+        self.push("tag IN (");
+        for (n, tag) in tags.iter().enumerate() {
+            if n > 0 {
+                self.push(", ");
+            }
+            self.push_param(SqlValue::Text(tag.clone()));
+        }
+        self.push(")")
+    }
+
+    // Translate a filter expression; must select exactly what
+    // `FilterExpr::matches` accepts
+    fn push_expression(&mut self, expression: &FilterExpr) {
+        match expression {
+            FilterExpr::And(left, right) => self.push_binary(left, " AND ", right),
+            FilterExpr::Or(left, right) => self.push_binary(left, " OR ", right),
+            FilterExpr::Not(inner) => {
+                self.push("(NOT ");
+                self.push_expression(inner);
+                self.push(")");
+            }
+            FilterExpr::Compare { field, op, value } => {
+                let column = field.column();
+                if *field == Field::Tags {
+                    self.push(match op {
+                        Operator::Ne => "NOT EXISTS",
+                        _ => "EXISTS",
+                    });
+                    self.push(" (SELECT 1 FROM task_tags WHERE task_id = tasks.id AND tag = ")
+                        .push_param(value.clone())
+                        .push(")");
+                    return;
+                }
+                if field.nullable() {
+                    self.push_nullable_comparison(column, *op, value);
+                    return;
+                }
+                let value = match (field, value) {
+                    (Field::Id, Value::Text(id)) => SqlValue::Id(id.clone()),
+                    _ => value.clone().into(),
+                };
+                let op = match op {
+                    Operator::Contains => {
+                        let function = match self.dialect {
+                            Dialect::Postgres => "strpos",
+                            Dialect::Sqlite => "instr",
+                        };
+                        self.push(&format!("{}(lower({}), lower(", function, column))
+                            .push_param(value)
+                            .push(")) > 0");
+                        return;
+                    }
+                    Operator::Eq => " = ",
+                    Operator::Ne => " <> ",
+                    Operator::Gt => " > ",
+                    Operator::Ge => " >= ",
+                    Operator::Lt => " < ",
+                    Operator::Le => " <= ",
+                };
+                self.push(column).push(op).push_param(value);
+            }
+        }
+    }
+
+    // A comparison on a column that may be NULL, made true or false (never
+    // NULL) so that NOT negates it like `FilterExpr::matches` does
+    fn push_nullable_comparison(&mut self, column: &str, op: Operator, value: &Value) {
+        let op = match (op, value) {
+            (Operator::Eq, Value::None) => {
+                self.push(column).push(" IS NULL");
+                return;
+            }
+            (Operator::Ne, Value::None) => {
+                self.push(column).push(" IS NOT NULL");
+                return;
+            }
+            (Operator::Ne, _) => {
+                self.push(&format!("({} IS NULL OR {} <> ", column, column))
+                    .push_param(value.clone())
+                    .push(")");
+                return;
+            }
+            (Operator::Eq, _) => " = ",
+            (Operator::Gt, _) => " > ",
+            (Operator::Ge, _) => " >= ",
+            (Operator::Lt, _) => " < ",
+            (Operator::Le, _) => " <= ",
+            (Operator::Contains, _) => unreachable!("nullable fields are not text"),
+        };
+        self.push(&format!("({} IS NOT NULL AND {}{}", column, column, op))
+            .push_param(value.clone())
+            .push(")");
+    }
+
+    fn push_binary(&mut self, left: &FilterExpr, keyword: &str, right: &FilterExpr) {
+        self.push("(");
+        self.push_expression(left);
+        self.push(keyword);
+        self.push_expression(right);
+        self.push(")");
+    }
+
+    // Sort expression for a field; text sorts bytewise in every backend, and
+    // a missing due date as `no_due_date()`, like `SortField::value`
+    fn column(&self, field: SortField) -> String {
+        match (self.dialect, field) {
+            (Dialect::Postgres, SortField::Title) => "title COLLATE \"C\"".to_string(),
+            (Dialect::Postgres, SortField::DueAt) => format!(
+                "COALESCE(due_at, '{}'::timestamptz)",
+                no_due_date().to_rfc3339_opts(SecondsFormat::AutoSi, true)
+            ),
+            // The text SQLite stores timestamps as
+            (Dialect::Sqlite, SortField::DueAt) => format!(
+                "COALESCE(due_at, '{}')",
+                no_due_date().to_rfc3339_opts(SecondsFormat::AutoSi, false)
+            ),
+            _ => field.name().to_string(),
+        }
+    }
+
+    // Keyset condition selecting rows strictly after the cursor:
+    // (k1 > v1) OR (k1 = v1 AND k2 > v2) OR ... OR (k1 = v1 AND ... AND id > id0)
+    fn push_after(&mut self, sort: &[SortKey], values: &[SortValue], id: &str) {
+        self.and().push("(");
+        for depth in 0..=sort.len() {
+            if depth > 0 {
+                self.push(" OR ");
+            }
+            self.push("(");
+            for (key, value) in sort.iter().zip(values).take(depth) {
+                let column = self.column(key.field);
+                self.push(&column).push(" = ").push_param(value.clone()).push(" AND ");
+            }
+            match sort.get(depth) {
+                Some(key) => {
+                    let column = self.column(key.field);
+                    let op = if key.descending { " < " } else { " > " };
+                    self.push(&column).push(op).push_param(values[depth].clone());
+                }
+                None => {
+                    self.push("id > ").push_param(SqlValue::Id(id.to_string()));
+                }
+            }
+            self.push(")");
+        }
+        self.push(")");
+    }
+}
+
+// One page of tasks, fetching a row beyond `limit` to detect a next page
+pub fn select_page(dialect: Dialect, columns: &str, query: &TaskQuery) -> SqlQuery {
+    let mut sql = SqlQuery::new(dialect, format!("SELECT {} FROM tasks", columns));
+    sql.push_filter(&query.filter);
+    if let Some(after) = &query.after {
+        sql.push_after(&query.sort, &after.values, &after.id);
+    }
+
+    let order: Vec<String> = query
+        .sort
+        .iter()
+        .map(|key| {
+            let direction = if key.descending { "DESC" } else { "ASC" };
+            format!("{} {}", sql.column(key.field), direction)
+        })
+        .chain(std::iter::once("id ASC".to_string()))
+        .collect();
+    sql.push(" ORDER BY ")
+        .push(&order.join(", "))
+        .push(" LIMIT ")
+        .push_param(SqlValue::Int(query.limit as i64 + 1));
+    sql
+}
+
+// Every task matching the filter, in no particular order
+pub fn select(dialect: Dialect, columns: &str, filter: &TaskFilter) -> SqlQuery {
+    let mut sql = SqlQuery::new(dialect, format!("SELECT {} FROM tasks", columns));
+    sql.push_filter(filter);
+    sql
+}
+
+// Number of tasks matching the filter, ignoring pagination
+pub fn count(dialect: Dialect, filter: &TaskFilter) -> SqlQuery {
+    let mut sql = SqlQuery::new(dialect, "SELECT COUNT(*) FROM tasks");
+    sql.push_filter(filter);
+    sql
+}
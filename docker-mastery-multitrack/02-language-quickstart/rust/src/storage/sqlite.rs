@@ -0,0 +1,843 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use log::info;
+use sqlx::query::Query;
+use sqlx::sqlite::{
+    Sqlite, SqliteArguments, SqliteConnectOptions, SqliteConnection, SqliteExecutor,
+    SqliteJournalMode, SqlitePool, SqlitePoolOptions, SqliteRow,
+};
+use sqlx::Row;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use super::sql::{self, priority_from_rank, Dialect, SqlQuery, SqlValue};
+use super::{Conditional, StorageError, StorageResult, TaskRepository};
+use crate::batch::BatchOperation;
+use crate::history::{Action, Revision};
+use crate::models::{timestamp, Priority, Task, TaskUpdate};
+use crate::query::{TaskFilter, TaskPage, TaskQuery};
+use crate::tags::{self, TagCount};
+
+// A task's tags live in task_tags and are read back with each row, as a
+// JSON array
+const TASK_COLUMNS: &str =
+    "id, title, description, completed, priority, due_at, created_at, updated_at, version, \
+     deleted_at, \
+     (SELECT json_group_array(tag) FROM task_tags WHERE task_id = tasks.id) AS tags";
+
+// Embedded SQLite storage for single-container deployments
+pub struct SqliteTaskRepository {
+    pool: SqlitePool,
+}
+
+impl SqliteTaskRepository {
+    // Open (or create) the database file and apply any pending migrations
+    pub async fn connect(database_url: &str, max_connections: u32) -> StorageResult<Self> {
+        let options = SqliteConnectOptions::from_str(database_url)?
+            .create_if_missing(true)
+            .journal_mode(SqliteJournalMode::Wal)
+            .busy_timeout(Duration::from_secs(5));
+
+        // The data directory may be a freshly mounted, empty volume
+        if let Some(parent) = options.get_filename().parent() {
+            if parent != Path::new("") {
+                std::fs::create_dir_all(parent)?;
+            }
+        }
+
+        let pool = SqlitePoolOptions::new()
+            .max_connections(max_connections)
+            .acquire_timeout(Duration::from_secs(5))
+            .connect_with(options)
+            .await?;
+
+        sqlx::migrate!("./migrations/sqlite").run(&pool).await?;
+        info!("SQLite migrations applied");
+
+        Ok(Self { pool })
+    }
+}
+
+fn task_from_row(row: SqliteRow) -> Result<Task, sqlx::Error> {
+    Ok(Task {
+        id: row.try_get("id")?,
+        title: row.try_get("title")?,
+        description: row.try_get("description")?,
+        completed: row.try_get("completed")?,
+        priority: priority_from_rank(row.try_get("priority")?)?,
+        tags: tags_from_json(row.try_get("tags")?)?,
+        due_at: row.try_get("due_at")?,
+        created_at: row.try_get("created_at")?,
+        updated_at: row.try_get("updated_at")?,
+        version: row.try_get("version")?,
+        deleted_at: row.try_get("deleted_at")?,
+    })
+}
+
+// Tags in the order the API shows them
+fn tags_from_json(json: String) -> Result<Vec<String>, sqlx::Error> {
+    let mut tags: Vec<String> =
+        serde_json::from_str(&json).map_err(|err| sqlx::Error::ColumnDecode {
+            index: "tags".to_string(),
+            source: Box::new(err),
+        })?;
+    tags.sort();
+    Ok(tags)
+}
+
+async fn insert_row(conn: &mut SqliteConnection, task: &Task) -> StorageResult<Task> {
+    let row = sqlx::query(&format!(
+        "INSERT INTO tasks (id, title, description, completed, priority, due_at, created_at, \
+            updated_at, version, deleted_at) \
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?) RETURNING {}",
+        TASK_COLUMNS
+    ))
+    .bind(&task.id)
+    .bind(&task.title)
+    .bind(&task.description)
+    .bind(task.completed)
+    .bind(task.priority.rank())
+    .bind(task.due_at)
+    .bind(task.created_at)
+    .bind(task.updated_at)
+    .bind(task.version)
+    .bind(task.deleted_at)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let mut created = task_from_row(row)?;
+    set_tags(conn, &task.id, &task.tags).await?;
+    created.tags = task.tags.clone();
+    record(conn, Action::Created, &created).await?;
+    Ok(created)
+}
+
+// Replace a task's tags
+async fn set_tags(conn: &mut SqliteConnection, id: &str, tags: &[String]) -> StorageResult<()> {
+    sqlx::query("DELETE FROM task_tags WHERE task_id = ?")
+        .bind(id)
+        .execute(&mut *conn)
+        .await?;
+    for tag in tags {
+        sqlx::query("INSERT INTO task_tags (task_id, tag) VALUES (?, ?)")
+            .bind(id)
+            .bind(tag)
+            .execute(&mut *conn)
+            .await?;
+    }
+    Ok(())
+}
+
+// With `version`, only updates a task that is still at that version
+async fn update_row(
+    conn: &mut SqliteConnection,
+    id: &str,
+    changes: TaskUpdate,
+    version: Option<i64>,
+) -> StorageResult<Option<Task>> {
+    let row = sqlx::query(&format!(
+        "UPDATE tasks SET \
+            title = COALESCE(?1, title), \
+            description = COALESCE(?2, description), \
+            completed = COALESCE(?3, completed), \
+            priority = COALESCE(?9, priority), \
+            due_at = CASE WHEN ?7 THEN ?8 ELSE due_at END, \
+            updated_at = ?4, \
+            version = version + 1 \
+         WHERE id = ?5 AND deleted_at IS NULL AND (?6 IS NULL OR version = ?6) \
+         RETURNING {}",
+        TASK_COLUMNS
+    ))
+    .bind(changes.title)
+    .bind(changes.description)
+    .bind(changes.completed)
+    .bind(timestamp())
+    .bind(id)
+    .bind(version)
+    .bind(changes.due_at.is_some())
+    .bind(changes.due_at.flatten())
+    .bind(changes.priority.map(Priority::rank))
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let mut task = task_from_row(row)?;
+    if let Some(tags) = changes.tags {
+        set_tags(conn, id, &tags).await?;
+        task.tags = tags;
+    }
+    record(conn, Action::Updated, &task).await?;
+    Ok(Some(task))
+}
+
+// Move a live task to the trash (`deleted`) or a trashed one back out. With
+// `version`, only while the task is still at that version.
+async fn trash_row(
+    conn: &mut SqliteConnection,
+    id: &str,
+    deleted: bool,
+    version: Option<i64>,
+) -> StorageResult<Option<Task>> {
+    let now = timestamp();
+    let in_trash = if deleted {
+        "deleted_at IS NULL"
+    } else {
+        "deleted_at IS NOT NULL"
+    };
+    let row = sqlx::query(&format!(
+        "UPDATE tasks SET deleted_at = ?1, updated_at = ?2, version = version + 1 \
+         WHERE id = ?3 AND {} AND (?4 IS NULL OR version = ?4) RETURNING {}",
+        in_trash, TASK_COLUMNS
+    ))
+    .bind(deleted.then_some(now))
+    .bind(now)
+    .bind(id)
+    .bind(version)
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let task = task_from_row(row)?;
+    let action = if deleted { Action::Deleted } else { Action::Restored };
+    record(conn, action, &task).await?;
+    Ok(Some(task))
+}
+
+// Add a revision of `task` to its history, in the caller's transaction
+async fn record(conn: &mut SqliteConnection, action: Action, task: &Task) -> StorageResult<()> {
+    let revision = Revision::new(action, task.clone());
+    sqlx::query(
+        "INSERT INTO task_revisions (task_id, revision, action, actor, changed_at, task) \
+         VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&task.id)
+    .bind(revision.revision)
+    .bind(revision.action.name())
+    .bind(&revision.actor)
+    .bind(revision.changed_at)
+    .bind(serde_json::to_string(&revision.task)?)
+    .execute(conn)
+    .await?;
+    Ok(())
+}
+
+fn revision_from_row(row: SqliteRow) -> StorageResult<Revision> {
+    let action: String = row.try_get("action")?;
+    let task: String = row.try_get("task")?;
+    Ok(Revision {
+        revision: row.try_get("revision")?,
+        action: action.parse().map_err(StorageError::Internal)?,
+        actor: row.try_get("actor")?,
+        changed_at: row.try_get("changed_at")?,
+        task: serde_json::from_str(&task)?,
+    })
+}
+
+// Why a conditional write matched no row
+async fn missed(executor: impl SqliteExecutor<'_>, id: &str) -> StorageResult<Conditional> {
+    let exists: bool = sqlx::query_scalar("SELECT EXISTS (SELECT 1 FROM tasks WHERE id = ? AND deleted_at IS NULL)")
+        .bind(id)
+        .fetch_one(executor)
+        .await?;
+    Ok(if exists { Conditional::Conflict } else { Conditional::NotFound })
+}
+
+// Run one batch operation on a connection, usually inside a transaction
+async fn apply_operation(
+    conn: &mut SqliteConnection,
+    operation: BatchOperation,
+) -> StorageResult<Conditional> {
+    match operation {
+        BatchOperation::Create(task) => Ok(Conditional::Applied(insert_row(conn, &task).await?)),
+        BatchOperation::Update { id, changes, version } => {
+            match update_row(&mut *conn, &id, changes, version).await? {
+                Some(task) => Ok(Conditional::Applied(task)),
+                None => missed(conn, &id).await,
+            }
+        }
+        BatchOperation::Delete { id, version } => {
+            match trash_row(&mut *conn, &id, true, version).await? {
+                Some(task) => Ok(Conditional::Applied(task)),
+                None => missed(conn, &id).await,
+            }
+        }
+    }
+}
+
+// Bind the rendered parameters in order
+fn bind_params(sql: &SqlQuery) -> Query<'_, Sqlite, SqliteArguments<'_>> {
+    let mut query = sqlx::query(&sql.sql);
+    for param in &sql.params {
+        query = match param {
+            SqlValue::Bool(value) => query.bind(*value),
+            SqlValue::Int(value) => query.bind(*value),
+            SqlValue::Time(value) => query.bind(*value),
+            SqlValue::Text(value) | SqlValue::Id(value) => query.bind(value.as_str()),
+        };
+    }
+    query
+}
+
+#[async_trait]
+impl TaskRepository for SqliteTaskRepository {
+    async fn ping(&self) -> StorageResult<()> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn list(&self) -> StorageResult<Vec<Task>> {
+        let rows = sqlx::query(&format!(
+            "SELECT {} FROM tasks WHERE deleted_at IS NULL ORDER BY created_at, id",
+            TASK_COLUMNS
+        ))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows.into_iter().map(task_from_row).collect::<Result<_, _>>()?)
+    }
+
+    async fn list_page(&self, query: &TaskQuery) -> StorageResult<TaskPage> {
+        // RFC 3339 text compares in chronological order
+        let select = sql::select_page(Dialect::Sqlite, TASK_COLUMNS, query);
+        let rows = bind_params(&select).fetch_all(&self.pool).await?;
+
+        let count = sql::count(Dialect::Sqlite, &query.filter);
+        let total: i64 = bind_params(&count).fetch_one(&self.pool).await?.try_get(0)?;
+
+        let tasks = rows.into_iter().map(task_from_row).collect::<Result<_, _>>()?;
+        Ok(TaskPage::new(tasks, query, total as usize))
+    }
+
+    async fn tags(&self) -> StorageResult<Vec<TagCount>> {
+        let rows = sqlx::query(
+            "SELECT tag, COUNT(*) AS count FROM task_tags \
+             JOIN tasks ON tasks.id = task_tags.task_id \
+             WHERE tasks.deleted_at IS NULL GROUP BY tag ORDER BY tag",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter()
+            .map(|row| {
+                let count: i64 = row.try_get("count")?;
+                Ok(TagCount {
+                    tag: row.try_get("tag")?,
+                    count: count as usize,
+                })
+            })
+            .collect()
+    }
+
+    async fn retag(
+        &self,
+        from: &[String],
+        to: &str,
+        merge: bool,
+    ) -> StorageResult<Option<Vec<Task>>> {
+        let filter = TaskFilter {
+            tags_any: Some(from.to_vec()),
+            ..TaskFilter::default()
+        };
+        let select = sql::select(Dialect::Sqlite, TASK_COLUMNS, &filter);
+
+        // Takes the write lock up front, so no task gets `to` after the check
+        let mut tx = self.pool.begin_with("BEGIN IMMEDIATE").await?;
+        if !merge && !from.iter().any(|tag| tag == to) {
+            let taken = TaskFilter {
+                tags_any: Some(vec![to.to_string()]),
+                ..TaskFilter::default()
+            };
+            let count = sql::count(Dialect::Sqlite, &taken);
+            let count: i64 = bind_params(&count).fetch_one(&mut *tx).await?.try_get(0)?;
+            if count > 0 {
+                return Ok(None);
+            }
+        }
+        let rows = bind_params(&select).fetch_all(&mut *tx).await?;
+        let mut retagged = Vec::with_capacity(rows.len());
+        for row in rows {
+            let task = task_from_row(row)?;
+            let Some(tags) = tags::retagged(&task.tags, from, to) else {
+                continue;
+            };
+            let changes = TaskUpdate {
+                tags: Some(tags),
+                ..TaskUpdate::default()
+            };
+            retagged.extend(update_row(&mut tx, &task.id, changes, None).await?);
+        }
+        tx.commit().await?;
+        Ok(Some(retagged))
+    }
+
+    async fn create(&self, task: Task) -> StorageResult<Task> {
+        let mut tx = self.pool.begin().await?;
+        let task = insert_row(&mut tx, &task).await?;
+        tx.commit().await?;
+        Ok(task)
+    }
+
+    async fn get(&self, id: &str) -> StorageResult<Option<Task>> {
+        let row = sqlx::query(&format!(
+            "SELECT {} FROM tasks WHERE id = ? AND deleted_at IS NULL",
+            TASK_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(row.map(task_from_row).transpose()?)
+    }
+
+    async fn update(&self, id: &str, changes: TaskUpdate) -> StorageResult<Option<Task>> {
+        let mut tx = self.pool.begin().await?;
+        let written = update_row(&mut tx, id, changes, None).await?;
+        tx.commit().await?;
+        Ok(written)
+    }
+
+    async fn update_if_version(
+        &self,
+        id: &str,
+        changes: TaskUpdate,
+        version: i64,
+    ) -> StorageResult<Conditional> {
+        let mut tx = self.pool.begin().await?;
+        let outcome = match update_row(&mut tx, id, changes, Some(version)).await? {
+            Some(task) => Conditional::Applied(task),
+            None => missed(&mut *tx, id).await?,
+        };
+        tx.commit().await?;
+        Ok(outcome)
+    }
+
+    async fn delete(&self, id: &str) -> StorageResult<Option<Task>> {
+        let mut tx = self.pool.begin().await?;
+        let written = trash_row(&mut tx, id, true, None).await?;
+        tx.commit().await?;
+        Ok(written)
+    }
+
+    async fn delete_if_version(&self, id: &str, version: i64) -> StorageResult<Conditional> {
+        let mut tx = self.pool.begin().await?;
+        let outcome = match trash_row(&mut tx, id, true, Some(version)).await? {
+            Some(task) => Conditional::Applied(task),
+            None => missed(&mut *tx, id).await?,
+        };
+        tx.commit().await?;
+        Ok(outcome)
+    }
+
+    async fn restore(&self, id: &str) -> StorageResult<Option<Task>> {
+        let mut tx = self.pool.begin().await?;
+        let written = trash_row(&mut tx, id, false, None).await?;
+        tx.commit().await?;
+        Ok(written)
+    }
+
+    async fn purge(&self, id: &str) -> StorageResult<Option<Task>> {
+        let mut tx = self.pool.begin().await?;
+        let row = sqlx::query(&format!(
+            "DELETE FROM tasks WHERE id = ? AND deleted_at IS NOT NULL RETURNING {}",
+            TASK_COLUMNS
+        ))
+        .bind(id)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(row) = row else {
+            return Ok(None);
+        };
+        let task = task_from_row(row)?;
+        sqlx::query("DELETE FROM task_tags WHERE task_id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM task_revisions WHERE task_id = ?")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(Some(task))
+    }
+
+    async fn purge_trashed_before(&self, cutoff: DateTime<Utc>) -> StorageResult<Vec<String>> {
+        let mut tx = self.pool.begin().await?;
+        let rows = sqlx::query(&format!(
+            "DELETE FROM tasks WHERE deleted_at < ? RETURNING {}",
+            TASK_COLUMNS
+        ))
+        .bind(cutoff)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let ids = rows
+            .into_iter()
+            .map(|row| task_from_row(row).map(|task| task.id))
+            .collect::<Result<Vec<_>, _>>()?;
+        for table in ["task_tags", "task_revisions"] {
+            sqlx::query(&format!(
+                "DELETE FROM {} WHERE task_id NOT IN (SELECT id FROM tasks)",
+                table
+            ))
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(ids)
+    }
+
+    async fn history(&self, id: &str) -> StorageResult<Vec<Revision>> {
+        let rows = sqlx::query(
+            "SELECT revision, action, actor, changed_at, task FROM task_revisions \
+             WHERE task_id = ? ORDER BY revision",
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        rows.into_iter().map(revision_from_row).collect()
+    }
+
+    async fn apply_atomically(
+        &self,
+        operations: Vec<BatchOperation>,
+    ) -> StorageResult<Vec<Conditional>> {
+        // Take the write lock up front rather than failing to upgrade a read
+        // lock halfway through the batch
+        let mut tx = self.pool.begin_with("BEGIN IMMEDIATE").await?;
+        let mut outcomes = Vec::with_capacity(operations.len());
+
+        for operation in operations {
+            let outcome = apply_operation(&mut tx, operation).await?;
+            let applied = matches!(outcome, Conditional::Applied(_));
+            outcomes.push(outcome);
+            if !applied {
+                tx.rollback().await?;
+                return Ok(outcomes);
+            }
+        }
+
+        tx.commit().await?;
+        Ok(outcomes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FilterExpr;
+    use crate::models::TaskCreate;
+    use crate::query::{SortField, SortKey};
+    use crate::storage::InMemoryTaskRepository;
+
+    // A private in-memory database; one connection, since each connection to
+    // `:memory:` opens a database of its own
+    async fn repository() -> SqliteTaskRepository {
+        SqliteTaskRepository::connect("sqlite::memory:", 1).await.unwrap()
+    }
+
+    fn task(title: &str, priority: Priority, tags: &[&str]) -> Task {
+        Task::new(TaskCreate {
+            title: title.to_string(),
+            description: format!("About {}", title),
+            priority,
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            due_at: None,
+        })
+    }
+
+    fn query(sort: Vec<SortKey>, limit: usize) -> TaskQuery {
+        TaskQuery {
+            filter: TaskFilter {
+                now: Utc::now(),
+                ..Default::default()
+            },
+            sort,
+            limit,
+            after: None,
+        }
+    }
+
+    // Every page of `query`, following cursors
+    async fn all_pages(repository: &dyn TaskRepository, mut query: TaskQuery) -> Vec<String> {
+        let mut ids = Vec::new();
+        loop {
+            let page = repository.list_page(&query).await.unwrap();
+            ids.extend(page.tasks.iter().map(|task| task.id.clone()));
+            let Some(last) = page.tasks.last() else {
+                break;
+            };
+            if page.next_cursor.is_none() {
+                break;
+            }
+            query.after = Some(crate::query::Cursor::after(last, &query.sort));
+        }
+        ids
+    }
+
+    #[actix_web::test]
+    async fn stores_and_reads_back_every_field() {
+        let repository = repository().await;
+        // The longest title a task may have, in multi-byte characters
+        let mut task = task(&"é".repeat(255), Priority::Urgent, &["docker", "rust"]);
+        task.due_at = Some("2030-01-02T03:04:05.123456Z".parse().unwrap());
+
+        let created = repository.create(task.clone()).await.unwrap();
+        let fetched = repository.get(&task.id).await.unwrap().unwrap();
+        for stored in [&created, &fetched] {
+            assert_eq!(stored.title, task.title);
+            assert_eq!(stored.priority, Priority::Urgent);
+            assert_eq!(stored.tags, ["docker", "rust"]);
+            assert_eq!(stored.due_at, task.due_at);
+            assert_eq!(stored.created_at, task.created_at);
+            assert_eq!(stored.version, 1);
+        }
+    }
+
+    #[actix_web::test]
+    async fn updates_only_at_the_expected_version() {
+        let repository = repository().await;
+        let task = repository.create(task("Write", Priority::Normal, &[])).await.unwrap();
+        let changes = || TaskUpdate {
+            completed: Some(true),
+            ..Default::default()
+        };
+
+        let updated = repository.update_if_version(&task.id, changes(), 1).await.unwrap();
+        let Conditional::Applied(updated) = updated else {
+            panic!("expected the update to apply, got {:?}", updated);
+        };
+        assert!(updated.completed);
+        assert_eq!(updated.version, 2);
+
+        let stale = repository.update_if_version(&task.id, changes(), 1).await.unwrap();
+        assert!(matches!(stale, Conditional::Conflict));
+        let missing = repository.update(&uuid::Uuid::new_v4().to_string(), changes()).await;
+        assert!(missing.unwrap().is_none());
+    }
+
+    #[actix_web::test]
+    async fn trashes_restores_and_purges() {
+        let repository = repository().await;
+        let task = repository.create(task("Tidy", Priority::Low, &["home"])).await.unwrap();
+
+        let trashed = repository.delete(&task.id).await.unwrap().unwrap();
+        assert!(trashed.is_deleted());
+        assert!(repository.get(&task.id).await.unwrap().is_none());
+        assert!(repository.delete(&task.id).await.unwrap().is_none());
+        assert!(repository.tags().await.unwrap().is_empty());
+
+        let restored = repository.restore(&task.id).await.unwrap().unwrap();
+        assert!(!restored.is_deleted());
+        assert_eq!(restored.tags, ["home"]);
+
+        repository.delete(&task.id).await.unwrap();
+        let purged = repository.purge_trashed_before(Utc::now()).await.unwrap();
+        assert_eq!(purged, vec![task.id.clone()]);
+        assert!(repository.restore(&task.id).await.unwrap().is_none());
+        assert!(repository.purge(&task.id).await.unwrap().is_none());
+    }
+
+    #[actix_web::test]
+    async fn pages_in_the_same_order_as_the_memory_store() {
+        let repository = repository().await;
+        let memory = InMemoryTaskRepository::new();
+        let priorities = [Priority::High, Priority::Low, Priority::Normal];
+        for n in 0..12 {
+            // Repeated titles and priorities leave ties for the id to break
+            let task = task(&format!("Task {}", n % 4), priorities[n % 3], &[]);
+            repository.create(task.clone()).await.unwrap();
+            memory.create(task).await.unwrap();
+        }
+
+        let sorts = [
+            vec![SortKey {
+                field: SortField::CreatedAt,
+                descending: false,
+            }],
+            vec![
+                SortKey {
+                    field: SortField::Priority,
+                    descending: true,
+                },
+                SortKey {
+                    field: SortField::Title,
+                    descending: false,
+                },
+            ],
+        ];
+        for sort in sorts {
+            let expected = all_pages(&memory, query(sort.clone(), 5)).await;
+            assert_eq!(expected.len(), 12);
+            assert_eq!(all_pages(&repository, query(sort, 5)).await, expected);
+        }
+    }
+
+    #[actix_web::test]
+    async fn filters_on_due_dates_like_the_memory_store() {
+        let repository = repository().await;
+        let memory = InMemoryTaskRepository::new();
+        let dues = [None, Some("2020-05-01T12:00:00Z"), Some("2040-05-01T12:00:00Z")];
+        for (n, due_at) in dues.into_iter().enumerate() {
+            for completed in [false, true] {
+                let mut task = task(&format!("Task {}", n), Priority::Normal, &[]);
+                task.due_at = due_at.map(|due_at| due_at.parse().unwrap());
+                task.completed = completed;
+                repository.create(task.clone()).await.unwrap();
+                memory.create(task).await.unwrap();
+            }
+        }
+
+        let sort = vec![SortKey {
+            field: SortField::Title,
+            descending: false,
+        }];
+        let filters = [
+            ("due:none", 2),
+            ("due!=none", 4),
+            ("NOT due:none", 4),
+            ("due<2030-01-01", 2),
+            ("NOT due<2030-01-01", 4),
+            ("due:2020-05-01", 2),
+            ("due!=2020-05-01", 4),
+            ("due>=2040-05-01T12:00:00Z OR due:none", 4),
+        ];
+        for (filter, count) in filters {
+            let mut query = query(sort.clone(), 50);
+            query.filter.expression = Some(FilterExpr::parse(filter).unwrap());
+            let expected = all_pages(&memory, query.clone()).await;
+            assert_eq!(expected.len(), count, "{}", filter);
+            assert_eq!(all_pages(&repository, query).await, expected, "{}", filter);
+        }
+
+        // Only the open task due in the past is overdue
+        for (overdue, count) in [(true, 1), (false, 5)] {
+            let mut query = query(sort.clone(), 50);
+            query.filter.overdue = Some(overdue);
+            let expected = all_pages(&memory, query.clone()).await;
+            assert_eq!(expected.len(), count);
+            assert_eq!(all_pages(&repository, query).await, expected);
+        }
+    }
+
+    #[actix_web::test]
+    async fn picks_the_same_next_task_as_the_memory_store() {
+        let repository = repository().await;
+        let memory = InMemoryTaskRepository::new();
+        let dues = [None, Some("2030-01-01T00:00:00Z"), Some("2030-01-01T00:00:00.5Z")];
+        let priorities = [Priority::High, Priority::Low, Priority::Urgent, Priority::Normal];
+        for n in 0..12 {
+            let mut task = task(&format!("Task {}", n), priorities[n % 4], &[]);
+            task.due_at = dues[n % 3].map(|due_at| due_at.parse().unwrap());
+            task.completed = n == 2;
+            repository.create(task.clone()).await.unwrap();
+            memory.create(task).await.unwrap();
+        }
+
+        let mut query = crate::query::next_task();
+        let next = repository.list_page(&query).await.unwrap().tasks;
+        assert_eq!(next[0].title, "Task 10", "the open urgent task with a due date");
+
+        // Paging through the whole order agrees with the memory store
+        query.limit = 5;
+        let expected = all_pages(&memory, query.clone()).await;
+        assert_eq!(expected.len(), 11);
+        assert_eq!(all_pages(&repository, query.clone()).await, expected);
+
+        for filter in ["priority>=high", "priority:low OR priority!=urgent", "priority<normal"] {
+            query.filter.expression = Some(FilterExpr::parse(filter).unwrap());
+            let expected = all_pages(&memory, query.clone()).await;
+            assert!(!expected.is_empty(), "{}", filter);
+            assert_eq!(all_pages(&repository, query.clone()).await, expected, "{}", filter);
+        }
+    }
+
+    #[actix_web::test]
+    async fn filters_on_tags_like_the_memory_store() {
+        let repository = repository().await;
+        let memory = InMemoryTaskRepository::new();
+        let tag_sets: [&[&str]; 4] = [&[], &["docker"], &["docker", "rust"], &["rust"]];
+        for (n, tags) in tag_sets.into_iter().enumerate() {
+            let task = task(&format!("Task {}", n), Priority::Normal, tags);
+            repository.create(task.clone()).await.unwrap();
+            memory.create(task).await.unwrap();
+        }
+
+        let sort = vec![SortKey {
+            field: SortField::Title,
+            descending: false,
+        }];
+        let tags = |tags: &[&str]| Some(tags.iter().map(|tag| tag.to_string()).collect());
+        let filters = [
+            (tags(&["docker", "rust"]), None, None, 3),
+            (None, tags(&["docker", "rust"]), None, 1),
+            (None, tags(&["docker"]), None, 2),
+            (tags(&["docker"]), tags(&["rust"]), None, 1),
+            (None, None, Some("tags:rust"), 2),
+            (None, None, Some("tags!=rust"), 2),
+            (None, None, Some("NOT tags:docker AND tag!=rust"), 1),
+            (None, None, Some("tags:Docker OR tags:go"), 2),
+        ];
+        for (tags_any, tags_all, expression, count) in filters {
+            let mut query = query(sort.clone(), 50);
+            query.filter.tags_any = tags_any;
+            query.filter.tags_all = tags_all;
+            query.filter.expression = expression.map(|filter| FilterExpr::parse(filter).unwrap());
+            let expected = all_pages(&memory, query.clone()).await;
+            assert_eq!(expected.len(), count, "{:?}", query.filter);
+            assert_eq!(all_pages(&repository, query).await, expected);
+        }
+    }
+
+    #[actix_web::test]
+    async fn renames_a_tag_only_to_an_unused_name() {
+        let repository = repository().await;
+        let docker = repository.create(task("Build", Priority::Normal, &["docker"])).await.unwrap();
+        repository.create(task("Write", Priority::Normal, &["rust"])).await.unwrap();
+        let from = ["docker".to_string()];
+
+        assert!(repository.retag(&from, "rust", false).await.unwrap().is_none());
+        assert_eq!(repository.get(&docker.id).await.unwrap().unwrap().tags, ["docker"]);
+
+        let merged = repository.retag(&from, "rust", true).await.unwrap().unwrap();
+        assert_eq!(merged.len(), 1);
+        assert_eq!(merged[0].tags, ["rust"]);
+        let tags = repository.tags().await.unwrap();
+        assert_eq!((tags[0].tag.as_str(), tags[0].count), ("rust", 2));
+    }
+
+    #[actix_web::test]
+    async fn rolls_back_a_batch_that_fails_part_way() {
+        let repository = repository().await;
+        let existing = repository.create(task("Existing", Priority::Normal, &[])).await.unwrap();
+
+        let outcomes = repository
+            .apply_atomically(vec![
+                BatchOperation::Create(task("New", Priority::Normal, &[])),
+                BatchOperation::Delete {
+                    id: existing.id.clone(),
+                    version: None,
+                },
+                BatchOperation::Update {
+                    id: existing.id.clone(),
+                    changes: TaskUpdate::default(),
+                    version: None,
+                },
+            ])
+            .await
+            .unwrap();
+
+        assert_eq!(outcomes.len(), 3);
+        assert!(matches!(outcomes[2], Conditional::NotFound));
+        let tasks = repository.list().await.unwrap();
+        let titles: Vec<_> = tasks.iter().map(|task| task.title.as_str()).collect();
+        assert_eq!(titles, ["Existing"]);
+        assert_eq!(repository.history(&existing.id).await.unwrap().len(), 1);
+    }
+}
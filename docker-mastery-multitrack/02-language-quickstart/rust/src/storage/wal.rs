@@ -0,0 +1,581 @@
+/*!
+ * Write-ahead log and snapshots for the in-memory store
+ *
+ * Every mutation is appended to `tasks.wal` before it is applied to the map,
+ * in the same record as the revision it adds to the task's history. Once
+ * enough records accumulate, the full map and history are written to
+ * `snapshot.json` and the log is truncated. Startup loads the snapshot and
+ * replays the log.
+ *
+ * Log records are framed as
+ * `[len: u32 LE][crc32 of len: u32 LE][crc32 of payload: u32 LE][JSON payload]`
+ * so a record torn by a crash mid-write can be detected and dropped on replay.
+ * The length has its own checksum, so a frame is only taken to be torn when
+ * the end of the log cuts it short; a record that fails either checksum
+ * anywhere is corruption, and startup fails rather than discarding the
+ * committed records behind it.
+ */
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::history::{History, Revision};
+use crate::models::Task;
+
+const LOG_FILE: &str = "tasks.wal";
+const SNAPSHOT_FILE: &str = "snapshot.json";
+const HEADER_LEN: usize = 12;
+
+// When appended records are flushed to disk
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FsyncPolicy {
+    // fsync after every record; no acknowledged write is ever lost
+    Always,
+    // fsync at most once per interval; a crash may lose the last interval
+    Interval(Duration),
+    // Leave flushing to the OS
+    Never,
+}
+
+impl FsyncPolicy {
+    // Parse WAL_FSYNC ("always", "never" or "interval") with WAL_FSYNC_INTERVAL_MS
+    pub fn from_env() -> Self {
+        let interval_ms = std::env::var("WAL_FSYNC_INTERVAL_MS")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(1000);
+
+        match std::env::var("WAL_FSYNC").as_deref() {
+            Ok("never") => FsyncPolicy::Never,
+            Ok("interval") => FsyncPolicy::Interval(Duration::from_millis(interval_ms)),
+            Ok("always") | Err(_) => FsyncPolicy::Always,
+            Ok(other) => {
+                warn!("Unknown WAL_FSYNC value '{}', using 'always'", other);
+                FsyncPolicy::Always
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WalConfig {
+    pub dir: PathBuf,
+    pub fsync: FsyncPolicy,
+    // Number of log records after which a snapshot is taken
+    pub compact_after: usize,
+}
+
+impl WalConfig {
+    // WAL persistence is enabled by setting WAL_DIR
+    pub fn from_env() -> Option<Self> {
+        let dir = std::env::var("WAL_DIR").ok().filter(|dir| !dir.is_empty())?;
+
+        Some(WalConfig {
+            dir: PathBuf::from(dir),
+            fsync: FsyncPolicy::from_env(),
+            compact_after: std::env::var("WAL_COMPACT_EVERY")
+                .ok()
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(1000),
+        })
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum WalRecord {
+    // Insert or fully replace a task
+    Put { task: Task },
+    Delete { id: String },
+    // Add a revision to a task's history
+    Revise { revision: Revision },
+    // Drop a purged task's history
+    Forget { id: String },
+    // Records that are logged in one frame, so a crash keeps all or none
+    Batch { records: Vec<WalRecord> },
+}
+
+impl WalRecord {
+    pub fn apply(self, tasks: &mut HashMap<String, Task>, history: &mut History) {
+        match self {
+            WalRecord::Put { task } => {
+                tasks.insert(task.id.clone(), task);
+            }
+            WalRecord::Delete { id } => {
+                tasks.remove(&id);
+            }
+            WalRecord::Revise { revision } => history.record(revision),
+            WalRecord::Forget { id } => history.forget(&id),
+            WalRecord::Batch { records } => {
+                for record in records {
+                    record.apply(tasks, history);
+                }
+            }
+        }
+    }
+}
+
+// Everything the snapshot and log hold
+#[derive(Debug, Default)]
+pub struct Recovered {
+    pub tasks: HashMap<String, Task>,
+    pub history: History,
+}
+
+impl Recovered {
+    fn apply(&mut self, record: WalRecord) {
+        record.apply(&mut self.tasks, &mut self.history);
+    }
+}
+
+// Written with borrowed tasks and history, read back as owned ones
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Snapshot<T, H> {
+    Full { tasks: Vec<T>, history: H },
+    // Written before task history was kept
+    Tasks(Vec<T>),
+}
+
+// A snapshot serialized while the store is locked, to be written out once
+// the locks are released
+pub struct PreparedSnapshot {
+    json: Vec<u8>,
+    tasks: usize,
+}
+
+impl PreparedSnapshot {
+    pub fn new(mut tasks: Vec<&Task>, history: &History) -> io::Result<Self> {
+        tasks.sort_by(|a, b| a.created_at.cmp(&b.created_at).then_with(|| a.id.cmp(&b.id)));
+        let count = tasks.len();
+        Ok(PreparedSnapshot {
+            json: serde_json::to_vec(&Snapshot::Full { tasks, history })?,
+            tasks: count,
+        })
+    }
+}
+
+pub struct WriteAheadLog {
+    config: WalConfig,
+    log: File,
+    records_since_snapshot: usize,
+    last_sync: Instant,
+    // Records appended since the last fsync
+    unsynced: bool,
+}
+
+impl WriteAheadLog {
+    // Open the log directory, returning the log and the recovered tasks
+    pub fn open(config: WalConfig) -> io::Result<(Self, Recovered)> {
+        fs::create_dir_all(&config.dir)?;
+
+        let mut recovered = load_snapshot(&config.dir.join(SNAPSHOT_FILE))?;
+        let snapshot_len = recovered.tasks.len();
+
+        let log_path = config.dir.join(LOG_FILE);
+        let log = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log_path)?;
+
+        let records = read_log(&log_path, &log)?;
+        let replayed = records.len();
+        for record in records {
+            recovered.apply(record);
+        }
+
+        info!(
+            "Recovered {} tasks from {} ({} from snapshot, {} log records replayed)",
+            recovered.tasks.len(),
+            config.dir.display(),
+            snapshot_len,
+            replayed
+        );
+
+        let wal = WriteAheadLog {
+            config,
+            log,
+            records_since_snapshot: replayed,
+            last_sync: Instant::now(),
+            unsynced: false,
+        };
+
+        Ok((wal, recovered))
+    }
+
+    // Rebuild the committed state from disk without touching the log
+    pub fn replay(&self) -> io::Result<Recovered> {
+        let mut recovered = load_snapshot(&self.config.dir.join(SNAPSHOT_FILE))?;
+        for record in decode_records(&fs::read(self.config.dir.join(LOG_FILE))?)?.0 {
+            recovered.apply(record);
+        }
+        Ok(recovered)
+    }
+
+    // Drop any partial record left at the end of the log
+    pub fn repair(&mut self) -> io::Result<()> {
+        read_log(&self.config.dir.join(LOG_FILE), &self.log).map(|_| ())
+    }
+
+    pub fn append(&mut self, record: &WalRecord) -> io::Result<()> {
+        let payload = serde_json::to_vec(record)?;
+
+        let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+        let len = (payload.len() as u32).to_le_bytes();
+        frame.extend_from_slice(&len);
+        frame.extend_from_slice(&crc32fast::hash(&len).to_le_bytes());
+        frame.extend_from_slice(&crc32fast::hash(&payload).to_le_bytes());
+        frame.extend_from_slice(&payload);
+
+        let len_before = self.log.metadata()?.len();
+        let written = self.log.write_all(&frame).and_then(|()| {
+            self.unsynced = true;
+            match self.config.fsync {
+                FsyncPolicy::Always => self.sync(),
+                FsyncPolicy::Interval(interval) if self.last_sync.elapsed() >= interval => {
+                    self.sync()
+                }
+                FsyncPolicy::Interval(_) | FsyncPolicy::Never => Ok(()),
+            }
+        });
+        if let Err(err) = written {
+            // The write is reported as failed, so it must not be replayed
+            // later, and later records must not follow a partial frame
+            let _ = self.log.set_len(len_before);
+            return Err(err);
+        }
+        self.records_since_snapshot += 1;
+
+        Ok(())
+    }
+
+    pub fn sync(&mut self) -> io::Result<()> {
+        self.log.sync_data()?;
+        self.last_sync = Instant::now();
+        self.unsynced = false;
+        Ok(())
+    }
+
+    // The interval of an interval fsync policy
+    pub fn sync_interval(&self) -> Option<Duration> {
+        match self.config.fsync {
+            FsyncPolicy::Interval(interval) => Some(interval),
+            FsyncPolicy::Always | FsyncPolicy::Never => None,
+        }
+    }
+
+    pub fn needs_compaction(&self) -> bool {
+        self.records_since_snapshot >= self.config.compact_after
+    }
+
+    // Write the full task set and history as a new snapshot and start an
+    // empty log. Nothing may be appended between preparing the snapshot and
+    // this call.
+    pub fn compact(&mut self, snapshot: PreparedSnapshot) -> io::Result<()> {
+        let snapshot_path = self.config.dir.join(SNAPSHOT_FILE);
+        let tmp_path = snapshot_path.with_extension("json.tmp");
+
+        let mut tmp = File::create(&tmp_path)?;
+        tmp.write_all(&snapshot.json)?;
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &snapshot_path)?;
+        sync_dir(&self.config.dir)?;
+
+        // Records already in the snapshot are idempotent puts, deletes and
+        // revisions, so a crash before this truncate only means replaying
+        // them again
+        self.log.set_len(0)?;
+        self.sync()?;
+        self.records_since_snapshot = 0;
+
+        info!("Compacted write-ahead log into snapshot of {} tasks", snapshot.tasks);
+        Ok(())
+    }
+}
+
+fn load_snapshot(path: &Path) -> io::Result<Recovered> {
+    let snapshot: Snapshot<Task, History> = match File::open(path) {
+        Ok(file) => serde_json::from_reader(io::BufReader::new(file))?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Snapshot::Tasks(Vec::new()),
+        Err(err) => return Err(err),
+    };
+
+    let (tasks, history) = match snapshot {
+        Snapshot::Full { tasks, history } => (tasks, history),
+        Snapshot::Tasks(tasks) => (tasks, History::default()),
+    };
+    Ok(Recovered {
+        tasks: tasks.into_iter().map(|task| (task.id.clone(), task)).collect(),
+        history,
+    })
+}
+
+// Decode the log, truncating a torn tail so new records are not appended
+// after it
+fn read_log(path: &Path, log: &File) -> io::Result<Vec<WalRecord>> {
+    let contents = fs::read(path)?;
+
+    let (records, valid_len) = decode_records(&contents).map_err(|err| {
+        io::Error::new(err.kind(), format!("{}: {}", path.display(), err))
+    })?;
+    if valid_len < contents.len() {
+        warn!(
+            "Discarding {} bytes of a torn record at the end of {}",
+            contents.len() - valid_len,
+            path.display()
+        );
+        log.set_len(valid_len as u64)?;
+        log.sync_all()?;
+    }
+
+    Ok(records)
+}
+
+// Decode consecutive records, stopping at a frame cut short by the end of the
+// log. Returns the records and the length of the complete prefix; any frame
+// whose length or payload fails its checksum, or that does not parse, is an
+// error.
+fn decode_records(contents: &[u8]) -> io::Result<(Vec<WalRecord>, usize)> {
+    let mut records = Vec::new();
+    let mut offset = 0;
+
+    while contents.len() - offset >= HEADER_LEN {
+        let header = &contents[offset..offset + HEADER_LEN];
+        let word = |at: usize| {
+            u32::from_le_bytes([header[at], header[at + 1], header[at + 2], header[at + 3]])
+        };
+        let corrupt = |reason: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("corrupt log record at byte {}: {}", offset, reason),
+            )
+        };
+        if crc32fast::hash(&header[..4]) != word(4) {
+            return Err(corrupt("length checksum mismatch"));
+        }
+
+        let start = offset + HEADER_LEN;
+        let Some(payload) = contents.get(start..start + word(0) as usize) else {
+            break;
+        };
+        if crc32fast::hash(payload) != word(8) {
+            return Err(corrupt("checksum mismatch"));
+        }
+        let record =
+            serde_json::from_slice(payload).map_err(|err| corrupt(&err.to_string()))?;
+        records.push(record);
+
+        offset = start + payload.len();
+    }
+
+    Ok((records, offset))
+}
+
+// Fsync the log every `interval` while it has unsynced records, so an interval
+// policy bounds the loss even when no further writes arrive. Stops once the
+// log is dropped.
+pub fn spawn_flusher(wal: Weak<Mutex<WriteAheadLog>>, interval: Duration) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        let Some(wal) = wal.upgrade() else {
+            return;
+        };
+        // A poisoned log is repaired by the next writer
+        let Ok(mut wal) = wal.lock() else {
+            continue;
+        };
+        if wal.unsynced {
+            if let Err(err) = wal.sync() {
+                warn!("Periodic write-ahead log fsync failed: {}", err);
+            }
+        }
+    });
+}
+
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::Action;
+    use crate::models::TaskCreate;
+
+    // A fresh log directory under the system temp dir
+    fn config() -> WalConfig {
+        WalConfig {
+            dir: std::env::temp_dir().join(format!("task-api-wal-{}", uuid::Uuid::new_v4())),
+            fsync: FsyncPolicy::Never,
+            compact_after: 1000,
+        }
+    }
+
+    fn task(title: &str) -> Task {
+        Task::new(TaskCreate {
+            title: title.to_string(),
+            description: String::new(),
+            priority: Default::default(),
+            tags: Vec::new(),
+            due_at: None,
+        })
+    }
+
+    fn created(task: &Task) -> WalRecord {
+        WalRecord::Batch {
+            records: vec![
+                WalRecord::Put { task: task.clone() },
+                WalRecord::Revise {
+                    revision: Revision::new(Action::Created, task.clone()),
+                },
+            ],
+        }
+    }
+
+    // Appends a record for each task and returns the log's length after each
+    fn write_log(config: &WalConfig, tasks: &[Task]) -> Vec<u64> {
+        let (mut wal, _) = WriteAheadLog::open(config.clone()).unwrap();
+        tasks
+            .iter()
+            .map(|task| {
+                wal.append(&created(task)).unwrap();
+                wal.log.metadata().unwrap().len()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn drops_a_record_torn_at_the_end_of_the_log() {
+        let config = config();
+        let tasks = [task("First"), task("Second")];
+        let lengths = write_log(&config, &tasks);
+
+        // A crash midway through a third frame leaves its header and part of
+        // the payload behind
+        let log_path = config.dir.join(LOG_FILE);
+        let mut log = OpenOptions::new().append(true).open(&log_path).unwrap();
+        let len = 64u32.to_le_bytes();
+        log.write_all(&len).unwrap();
+        log.write_all(&crc32fast::hash(&len).to_le_bytes()).unwrap();
+        log.write_all(&[1, 2, 3, 4, b'{', b'"']).unwrap();
+
+        let (_, recovered) = WriteAheadLog::open(config.clone()).unwrap();
+        assert_eq!(recovered.tasks.len(), 2);
+        assert_eq!(recovered.history.of(&tasks[1].id).len(), 1);
+        assert_eq!(fs::metadata(&log_path).unwrap().len(), lengths[1]);
+
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_to_open_a_log_corrupt_before_its_end() {
+        let config = config();
+        let lengths = write_log(&config, &[task("First"), task("Second"), task("Third")]);
+
+        // Flip a payload byte of the second record
+        let log_path = config.dir.join(LOG_FILE);
+        let mut contents = fs::read(&log_path).unwrap();
+        contents[lengths[0] as usize + HEADER_LEN + 1] ^= 0xff;
+        fs::write(&log_path, &contents).unwrap();
+
+        let err = WriteAheadLog::open(config.clone()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains(&format!("at byte {}", lengths[0])));
+        // The records after the corrupt one are still there to be salvaged
+        assert_eq!(fs::read(&log_path).unwrap(), contents);
+
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+
+    #[test]
+    fn refuses_to_open_a_log_with_a_corrupt_length_before_its_end() {
+        let config = config();
+        let lengths = write_log(&config, &[task("First"), task("Second"), task("Third")]);
+
+        // A length so large the record would run past the end of the log
+        let log_path = config.dir.join(LOG_FILE);
+        let mut contents = fs::read(&log_path).unwrap();
+        contents[lengths[0] as usize + 3] ^= 0x80;
+        fs::write(&log_path, &contents).unwrap();
+
+        let err = WriteAheadLog::open(config.clone()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(err.to_string().contains(&format!("at byte {}", lengths[0])));
+        assert!(err.to_string().contains("length checksum mismatch"));
+        assert_eq!(fs::read(&log_path).unwrap(), contents);
+
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+
+    #[test]
+    fn recovers_from_a_snapshot_and_the_log_written_after_it() {
+        let config = config();
+        let (first, second, third) = (task("First"), task("Second"), task("Third"));
+        let (mut wal, _) = WriteAheadLog::open(config.clone()).unwrap();
+        wal.append(&created(&first)).unwrap();
+        wal.append(&created(&second)).unwrap();
+
+        let mut history = History::default();
+        history.record(Revision::new(Action::Created, first.clone()));
+        history.record(Revision::new(Action::Created, second.clone()));
+        let snapshot = PreparedSnapshot::new(vec![&first, &second], &history).unwrap();
+        wal.compact(snapshot).unwrap();
+        assert_eq!(wal.log.metadata().unwrap().len(), 0);
+
+        wal.append(&created(&third)).unwrap();
+        wal.append(&WalRecord::Delete {
+            id: first.id.clone(),
+        })
+        .unwrap();
+        drop(wal);
+
+        let (wal, recovered) = WriteAheadLog::open(config.clone()).unwrap();
+        let mut titles: Vec<_> = recovered.tasks.values().map(|task| task.title.as_str()).collect();
+        titles.sort();
+        assert_eq!(titles, ["Second", "Third"]);
+        assert_eq!(recovered.history.of(&second.id).len(), 1);
+        assert_eq!(recovered.history.of(&third.id).len(), 1);
+        assert_eq!(wal.records_since_snapshot, 2);
+
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+
+    #[test]
+    fn replays_a_purge_without_the_task_or_its_history() {
+        let config = config();
+        let purged = task("Purged");
+        let (mut wal, _) = WriteAheadLog::open(config.clone()).unwrap();
+        wal.append(&created(&purged)).unwrap();
+        wal.append(&WalRecord::Batch {
+            records: vec![
+                WalRecord::Delete {
+                    id: purged.id.clone(),
+                },
+                WalRecord::Forget {
+                    id: purged.id.clone(),
+                },
+            ],
+        })
+        .unwrap();
+        drop(wal);
+
+        let (_, recovered) = WriteAheadLog::open(config.clone()).unwrap();
+        assert!(recovered.tasks.is_empty());
+        assert!(recovered.history.of(&purged.id).is_empty());
+
+        fs::remove_dir_all(&config.dir).unwrap();
+    }
+}
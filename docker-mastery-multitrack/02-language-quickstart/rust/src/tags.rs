@@ -0,0 +1,150 @@
+/*!
+ * Tags for grouping tasks
+ *
+ * A task has any number of tags (up to MAX_TAGS) and a tag any number of
+ * tasks. Tags are case-insensitive: they are trimmed, lowercased and kept
+ * sorted, so `Docker` and ` docker` are the same tag. A tag exists as long as
+ * a task has it; renaming or merging tags rewrites every live task that has
+ * one of them. Trashed tasks keep their tags as they were, like any other
+ * field, and are not counted.
+ */
+
+use serde::{Deserialize, Deserializer, Serialize};
+
+use crate::models::Task;
+use crate::query::QueryError;
+
+const MAX_TAGS: usize = 20;
+const MAX_TAG_LENGTH: usize = 50;
+// Tags one merge may fold into another
+const MAX_MERGE_TAGS: usize = 100;
+
+// The canonical form of one tag
+pub fn normalize(tag: &str) -> Result<String, String> {
+    let tag = tag.trim().to_lowercase();
+    if tag.is_empty() {
+        return Err("tags must not be empty".to_string());
+    }
+    if tag.chars().count() > MAX_TAG_LENGTH {
+        return Err(format!("tag '{}' is longer than {} characters", tag, MAX_TAG_LENGTH));
+    }
+    // Commas separate tags in query strings
+    if tag.contains(|c: char| c == ',' || c.is_whitespace()) {
+        return Err(format!("tag '{}' must not contain commas or whitespace", tag));
+    }
+    Ok(tag)
+}
+
+// Tags normalized, sorted and without duplicates
+fn normalize_list(tags: Vec<String>) -> Result<Vec<String>, String> {
+    let mut tags = tags
+        .iter()
+        .map(|tag| normalize(tag))
+        .collect::<Result<Vec<_>, _>>()?;
+    tags.sort();
+    tags.dedup();
+    Ok(tags)
+}
+
+// The canonical form of a task's tags
+pub fn normalize_all(tags: Vec<String>) -> Result<Vec<String>, String> {
+    let tags = normalize_list(tags)?;
+    if tags.len() > MAX_TAGS {
+        return Err(format!("a task can have at most {} tags", MAX_TAGS));
+    }
+    Ok(tags)
+}
+
+// Parse a query string parameter such as `docker,security`
+pub fn parse_list(value: &str) -> Result<Vec<String>, QueryError> {
+    let mut tags = value
+        .split(',')
+        .map(|tag| normalize(tag).map_err(QueryError::new))
+        .collect::<Result<Vec<_>, _>>()?;
+    tags.sort();
+    tags.dedup();
+    Ok(tags)
+}
+
+// Serde `deserialize_with` for a task's tags
+pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    normalize_all(Vec::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+// Serde `deserialize_with` for tags that may be left unchanged
+pub fn deserialize_some<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<String>>, D::Error> {
+    deserialize(deserializer).map(Some)
+}
+
+// `tags` with every tag in `from` replaced by `to`, or `None` when that
+// changes nothing
+pub fn retagged(tags: &[String], from: &[String], to: &str) -> Option<Vec<String>> {
+    if !tags.iter().any(|tag| from.contains(tag)) {
+        return None;
+    }
+    let mut renamed: Vec<String> = tags.iter().filter(|tag| !from.contains(tag)).cloned().collect();
+    renamed.push(to.to_string());
+    renamed.sort();
+    renamed.dedup();
+    (renamed != tags).then_some(renamed)
+}
+
+// A tag and how many live tasks have it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TagCount {
+    pub tag: String,
+    pub count: usize,
+}
+
+// Tags of the given tasks with their counts, by tag
+pub fn count(tasks: &[Task]) -> Vec<TagCount> {
+    let mut counts = std::collections::BTreeMap::<&str, usize>::new();
+    for tag in tasks.iter().flat_map(|task| &task.tags) {
+        *counts.entry(tag).or_default() += 1;
+    }
+    counts
+        .into_iter()
+        .map(|(tag, count)| TagCount {
+            tag: tag.to_string(),
+            count,
+        })
+        .collect()
+}
+
+// Body of POST /api/tags/{tag}/rename
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RenameTag {
+    #[serde(deserialize_with = "deserialize_one")]
+    pub name: String,
+}
+
+// Body of POST /api/tags/merge: every tag in `tags` becomes `into`
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MergeTags {
+    #[serde(deserialize_with = "deserialize_merged")]
+    pub tags: Vec<String>,
+    #[serde(deserialize_with = "deserialize_one")]
+    pub into: String,
+}
+
+fn deserialize_merged<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<String>, D::Error> {
+    let tags = normalize_list(Vec::deserialize(deserializer)?);
+    let tags = tags.map_err(serde::de::Error::custom)?;
+    if tags.len() > MAX_MERGE_TAGS {
+        return Err(serde::de::Error::custom(format!(
+            "at most {} tags can be merged at once",
+            MAX_MERGE_TAGS
+        )));
+    }
+    Ok(tags)
+}
+
+fn deserialize_one<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    normalize(&String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
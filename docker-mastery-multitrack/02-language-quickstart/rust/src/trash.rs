@@ -0,0 +1,213 @@
+/*!
+ * Trash for deleted tasks
+ *
+ * Deleting a task only moves it to the trash, from where it can be restored
+ * or purged for good. Tasks left in the trash longer than
+ * TRASH_RETENTION_DAYS (30 by default, 0 keeps them forever, at most 36500)
+ * are purged automatically. A task's attachments stay until the task is purged.
+ */
+
+use chrono::{DateTime, TimeDelta, Utc};
+use log::{info, warn};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::attachments::AttachmentStore;
+use crate::models::Task;
+use crate::storage::{StorageResult, TaskRepository};
+
+const DEFAULT_RETENTION_DAYS: i64 = 30;
+// A century; anything longer can't be subtracted from the current time
+const MAX_RETENTION_DAYS: i64 = 36_500;
+// How often the trash is checked for tasks past their retention
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// Trash settings read from the environment
+#[derive(Debug, Clone, Copy)]
+pub struct TrashConfig {
+    // `None` keeps trashed tasks until they are purged by hand
+    pub retention: Option<TimeDelta>,
+}
+
+impl TrashConfig {
+    pub fn from_env() -> Self {
+        let value = std::env::var("TRASH_RETENTION_DAYS").ok();
+        Self::from_days(value.as_deref())
+    }
+
+    fn from_days(value: Option<&str>) -> Self {
+        let days = match value.map(|value| (value, value.parse::<i64>())) {
+            None => DEFAULT_RETENTION_DAYS,
+            Some((_, Ok(days))) if days <= MAX_RETENTION_DAYS => days,
+            Some((value, _)) => {
+                warn!(
+                    "Invalid TRASH_RETENTION_DAYS value '{}' (expected 0 to {}), using {}",
+                    value, MAX_RETENTION_DAYS, DEFAULT_RETENTION_DAYS
+                );
+                DEFAULT_RETENTION_DAYS
+            }
+        };
+
+        TrashConfig {
+            retention: if days > 0 { TimeDelta::try_days(days) } else { None },
+        }
+    }
+}
+
+// The task's files go with it; it is gone either way, so a failure to remove
+// them is only logged
+async fn remove_attachments(attachments: &AttachmentStore, task_id: &str) {
+    if let Err(err) = attachments.remove_all(task_id).await {
+        warn!("Could not remove attachments of purged task {}: {}", task_id, err);
+    }
+}
+
+// Permanently remove one task from the trash
+pub async fn purge(
+    storage: &dyn TaskRepository,
+    attachments: &AttachmentStore,
+    id: &str,
+) -> StorageResult<Option<Task>> {
+    let purged = storage.purge(id).await?;
+    if let Some(task) = &purged {
+        remove_attachments(attachments, &task.id).await;
+    }
+    Ok(purged)
+}
+
+// Permanently remove every task trashed before `cutoff`, returning how many
+pub async fn purge_before(
+    storage: &dyn TaskRepository,
+    attachments: &AttachmentStore,
+    cutoff: DateTime<Utc>,
+) -> StorageResult<usize> {
+    let ids = storage.purge_trashed_before(cutoff).await?;
+    for id in &ids {
+        remove_attachments(attachments, id).await;
+    }
+    Ok(ids.len())
+}
+
+// Purge tasks past their retention now and then every PURGE_INTERVAL
+pub async fn purge_expired(
+    storage: Arc<dyn TaskRepository>,
+    attachments: Arc<AttachmentStore>,
+    retention: TimeDelta,
+) {
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let Some(cutoff) = Utc::now().checked_sub_signed(retention) else {
+            warn!(
+                "Trash retention of {} days reaches before the earliest date",
+                retention.num_days()
+            );
+            continue;
+        };
+        match purge_before(&*storage, &attachments, cutoff).await {
+            Ok(0) => {}
+            Ok(purged) => info!("Purged {} tasks from the trash", purged),
+            Err(err) => warn!("Could not purge expired tasks from the trash: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::attachments::UploadConfig;
+    use crate::models::TaskCreate;
+    use crate::search::{SearchParams, SearchQuery};
+    use crate::storage::{IndexedRepository, InMemoryTaskRepository};
+
+    fn upload_config() -> UploadConfig {
+        UploadConfig {
+            enabled: true,
+            dir: std::env::temp_dir().join(format!("task-api-trash-{}", uuid::Uuid::new_v4())),
+            max_bytes: 1024,
+            allowed_types: vec!["text/plain".to_string()],
+        }
+    }
+
+    async fn storage() -> IndexedRepository {
+        IndexedRepository::build(Arc::new(InMemoryTaskRepository::new())).await.unwrap()
+    }
+
+    async fn create(storage: &dyn TaskRepository, title: &str) -> Task {
+        let task = Task::new(TaskCreate {
+            title: title.to_string(),
+            description: String::new(),
+            priority: Default::default(),
+            tags: Vec::new(),
+            due_at: None,
+        });
+        storage.create(task).await.unwrap()
+    }
+
+    async fn search(storage: &dyn TaskRepository, text: &str) -> usize {
+        let params = SearchParams {
+            q: text.to_string(),
+            limit: None,
+        };
+        let query = SearchQuery::try_from(params).unwrap();
+        storage.search(&query).await.unwrap().total
+    }
+
+    #[test]
+    fn reads_the_retention_in_days() {
+        let days = |value| TrashConfig::from_days(value).retention.map(|r| r.num_days());
+        assert_eq!(days(None), Some(DEFAULT_RETENTION_DAYS));
+        assert_eq!(days(Some("7")), Some(7));
+        assert_eq!(days(Some("0")), None);
+        assert_eq!(days(Some("36500")), Some(36_500));
+        // Out of range or unreadable values fall back to the default
+        assert_eq!(days(Some("36501")), Some(DEFAULT_RETENTION_DAYS));
+        assert_eq!(days(Some("9223372036854775807")), Some(DEFAULT_RETENTION_DAYS));
+        assert_eq!(days(Some("a month")), Some(DEFAULT_RETENTION_DAYS));
+    }
+
+    #[actix_web::test]
+    async fn restores_a_trashed_task_into_search() {
+        let storage = storage().await;
+        let task = create(&storage, "Rotate keys").await;
+
+        storage.delete(&task.id).await.unwrap().unwrap();
+        assert!(storage.get(&task.id).await.unwrap().is_none());
+        assert_eq!(search(&storage, "rotate").await, 0);
+
+        let restored = storage.restore(&task.id).await.unwrap().unwrap();
+        assert_eq!(restored.version, task.version + 2);
+        assert_eq!(search(&storage, "rotate").await, 1);
+        // Only trashed tasks can be restored or purged
+        assert!(storage.restore(&task.id).await.unwrap().is_none());
+        assert!(storage.purge(&task.id).await.unwrap().is_none());
+    }
+
+    #[actix_web::test]
+    async fn purges_tasks_trashed_before_the_cutoff_with_their_files() {
+        let storage = storage().await;
+        let config = upload_config();
+        let attachments = AttachmentStore::new(config.clone());
+        let old = create(&storage, "Old").await;
+        let recent = create(&storage, "Recent").await;
+        let live = create(&storage, "Live").await;
+
+        let files = config.dir.join(&old.id);
+        std::fs::create_dir_all(&files).unwrap();
+        std::fs::write(files.join("notes.txt"), "notes").unwrap();
+
+        storage.delete(&old.id).await.unwrap();
+        let cutoff = Utc::now();
+        storage.delete(&recent.id).await.unwrap();
+
+        assert_eq!(purge_before(&storage, &attachments, cutoff).await.unwrap(), 1);
+        assert!(!files.exists());
+        assert!(storage.restore(&old.id).await.unwrap().is_none());
+        assert!(storage.restore(&recent.id).await.unwrap().is_some());
+        assert!(storage.get(&live.id).await.unwrap().is_some());
+
+        let purged = purge(&storage, &attachments, &live.id).await.unwrap();
+        assert!(purged.is_none(), "live tasks are not in the trash");
+        std::fs::remove_dir_all(&config.dir).unwrap();
+    }
+}